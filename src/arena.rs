@@ -0,0 +1,283 @@
+//! A tag map storing all tag lists in one allocation.
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+use std::mem::size_of;
+use std::ops::Range;
+use {Matcher, MemoryReport, TagMap};
+
+/// A tag map storing the tags of all entries contiguously, in a single `Vec`.
+///
+/// Each entry only records where its tags are in the arena, so loading a map
+/// doesn't allocate a list per entry, and full scans read the tags sequentially.
+/// Replacing or growing the tags of an entry leaves its old tags in the arena as
+/// garbage, which is reclaimed once it outweighs the live tags, or by `compact`.
+#[derive(Debug, Clone)]
+pub struct ArenaTagMap<T: Ord, TAG> {
+    entries: BTreeMap<T, Range<usize>>,
+    tags: Vec<TAG>,
+    /// Number of tags in the arena that don't belong to any entry.
+    garbage: usize,
+}
+
+impl<T: Ord, TAG> ArenaTagMap<T, TAG> {
+    /// Creates a new empty map.
+    pub fn new() -> Self {
+        ArenaTagMap {
+            entries: BTreeMap::new(),
+            tags: Vec::new(),
+            garbage: 0,
+        }
+    }
+    /// Creates a new empty map, with room for `tags` tags in the arena.
+    pub fn with_tag_capacity(tags: usize) -> Self {
+        ArenaTagMap { tags: Vec::with_capacity(tags), ..Self::new() }
+    }
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    /// Returns whether the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Removes all entries from the map.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.tags.clear();
+        self.garbage = 0;
+    }
+    /// Returns an iterator over the entries, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&T, &[TAG])> {
+        self.entries.iter().map(move |(key, range)| (key, &self.tags[range.clone()]))
+    }
+    /// Returns an iterator over the keys, in order.
+    pub fn keys(&self) -> impl Iterator<Item = &T> {
+        self.entries.keys()
+    }
+    /// Returns the tags of an entry, if it is present.
+    pub fn get_tags<Q: ?Sized + Ord>(&self, key: &Q) -> Option<&[TAG]>
+        where T: Borrow<Q>
+    {
+        self.entries.get(key).map(|range| &self.tags[range.clone()])
+    }
+    /// Returns whether the map contains an entry for the given key.
+    pub fn contains_key<Q: ?Sized + Ord>(&self, key: &Q) -> bool
+        where T: Borrow<Q>
+    {
+        self.entries.contains_key(key)
+    }
+    /// Inserts an entry with the given tags, at the end of the arena.
+    ///
+    /// Returns whether the key was already present, in which case its old tags
+    /// become garbage.
+    pub fn insert<I: IntoIterator<Item = TAG>>(&mut self, key: T, tags: I) -> bool {
+        let start = self.tags.len();
+        self.tags.extend(tags);
+        let old = self.entries.insert(key, start..self.tags.len());
+        if let Some(ref old) = old {
+            self.discard(old.len());
+        }
+        old.is_some()
+    }
+    /// Removes an entry, returning whether it was present.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> bool
+        where T: Borrow<Q>
+    {
+        match self.entries.remove(key) {
+            Some(range) => {
+                self.discard(range.len());
+                true
+            }
+            None => false,
+        }
+    }
+    /// Returns the keys of the entries matching the given rule, in order.
+    pub fn matching<'a, R>(&'a self, rule: &'a R) -> impl Iterator<Item = &'a T> + 'a
+        where R: ?Sized + Matcher<TAG>
+    {
+        self.iter().filter(move |&(_, tags)| rule.matches(tags)).map(|(key, _)| key)
+    }
+    /// Returns the number of entries matching the given rule.
+    pub fn count_matching<R: ?Sized + Matcher<TAG>>(&self, rule: &R) -> usize {
+        self.iter().filter(|&(_, tags)| rule.matches(tags)).count()
+    }
+    /// Estimates the memory used by the map, including garbage in the arena.
+    pub fn memory_usage(&self) -> MemoryReport {
+        MemoryReport {
+            keys: self.len() * size_of::<T>(),
+            tags: self.len() * size_of::<Range<usize>>() + self.tags.capacity() * size_of::<TAG>(),
+            ..MemoryReport::default()
+        }
+    }
+    /// Records that `count` tags no longer belong to any entry, reclaiming them if
+    /// they outweigh the live tags.
+    fn discard(&mut self, count: usize) {
+        self.garbage += count;
+        if self.garbage > self.tags.len() / 2 {
+            self.collect_garbage();
+        }
+    }
+    /// Moves the live tags to the front of the arena, keeping their order.
+    fn collect_garbage(&mut self) {
+        let mut live = vec![false; self.tags.len()];
+        let mut ranges: Vec<_> = self.entries.values_mut().collect();
+        for range in &ranges {
+            for flag in &mut live[range.start..range.end] {
+                *flag = true;
+            }
+        }
+        let mut flags = live.iter();
+        self.tags.retain(|_| *flags.next().unwrap());
+        // Entries don't overlap, so each one moves back by the garbage before it
+        ranges.sort_by_key(|range| range.start);
+        let mut start = 0;
+        for range in ranges {
+            *range = start..start + range.len();
+            start = range.end;
+        }
+        self.garbage = 0;
+    }
+}
+
+impl<T: Ord, TAG: Eq> ArenaTagMap<T, TAG> {
+    /// Returns whether the entry for the given key has the given tag.
+    pub fn has_tag<Q: ?Sized + Ord>(&self, key: &Q, tag: &TAG) -> bool
+        where T: Borrow<Q>
+    {
+        self.get_tags(key).is_some_and(|tags| tags.contains(tag))
+    }
+    /// Removes a tag from an entry, in place.
+    ///
+    /// Returns false if the entry is not present or didn't have the tag.
+    pub fn remove_tag<Q: ?Sized + Ord>(&mut self, key: &Q, tag: &TAG) -> bool
+        where T: Borrow<Q>
+    {
+        let range = match self.entries.get_mut(key) {
+            Some(range) => range,
+            None => return false,
+        };
+        let tags = &mut self.tags[range.clone()];
+        let mut kept = 0;
+        for i in 0..tags.len() {
+            if tags[i] != *tag {
+                tags.swap(kept, i);
+                kept += 1;
+            }
+        }
+        let removed = tags.len() - kept;
+        range.end -= removed;
+        if removed > 0 {
+            self.discard(removed);
+        }
+        removed > 0
+    }
+}
+
+impl<T: Ord, TAG: Eq + Clone> ArenaTagMap<T, TAG> {
+    /// Adds a tag to an entry, inserting the entry if it's not present.
+    ///
+    /// The entry's tags are moved to the end of the arena, unless they already are.
+    /// Returns false if the entry already had the tag.
+    pub fn add_tag(&mut self, key: T, tag: TAG) -> bool {
+        let range = match self.entries.get(&key) {
+            Some(range) if self.tags[range.clone()].contains(&tag) => return false,
+            Some(range) => range.clone(),
+            None => {
+                self.insert(key, Some(tag));
+                return true;
+            }
+        };
+        if range.end == self.tags.len() {
+            self.tags.push(tag);
+            self.entries.insert(key, range.start..range.end + 1);
+        } else {
+            let tags: Vec<_> = self.tags[range].iter().cloned().chain(Some(tag)).collect();
+            self.insert(key, tags);
+        }
+        true
+    }
+    /// Removes duplicate tags within entries, and reclaims the garbage and unused
+    /// capacity of the arena.
+    pub fn compact(&mut self) {
+        let mut tags = Vec::with_capacity(self.tags.len() - self.garbage);
+        for range in self.entries.values_mut() {
+            let start = tags.len();
+            for tag in &self.tags[range.clone()] {
+                if !tags[start..].contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+            *range = start..tags.len();
+        }
+        self.tags = tags;
+        self.garbage = 0;
+    }
+}
+
+impl<T: Ord, TAG> Default for ArenaTagMap<T, TAG> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord, TAG> FromIterator<(T, Vec<TAG>)> for ArenaTagMap<T, TAG> {
+    fn from_iter<I: IntoIterator<Item = (T, Vec<TAG>)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        for (key, tags) in iter {
+            map.insert(key, tags);
+        }
+        map
+    }
+}
+
+impl<T: Ord, TAG: Eq> From<TagMap<T, TAG>> for ArenaTagMap<T, TAG> {
+    fn from(map: TagMap<T, TAG>) -> Self {
+        let mut arena = Self::with_tag_capacity(map.values().map(|tags| tags.len()).sum());
+        for (key, tags) in map {
+            arena.insert(key, tags);
+        }
+        arena
+    }
+}
+
+impl<T: Ord, TAG: Eq> From<ArenaTagMap<T, TAG>> for TagMap<T, TAG> {
+    fn from(arena: ArenaTagMap<T, TAG>) -> Self {
+        let mut tags = arena.tags.into_iter().map(Some).collect::<Vec<_>>();
+        arena.entries
+            .into_iter()
+            .map(|(key, range)| (key, tags[range].iter_mut().filter_map(Option::take).collect()))
+            .collect()
+    }
+}
+
+#[test]
+fn test_arena() {
+    use MatchRule::*;
+    let mut map = ArenaTagMap::new();
+    map.insert(1, vec!["red", "round"]);
+    map.insert(2, vec!["green"]);
+    map.insert(3, vec!["red", "square", "red"]);
+    assert!(map.add_tag(1, "small"));
+    assert!(!map.add_tag(2, "green"));
+    assert!(map.add_tag(2, "round"));
+    assert!(map.remove_tag(&3, &"red"));
+    assert_eq!(map.get_tags(&1), Some(&["red", "round", "small"][..]));
+    assert_eq!(map.get_tags(&2), Some(&["green", "round"][..]));
+    assert_eq!(map.get_tags(&3), Some(&["square"][..]));
+    assert_eq!(map.matching(&Tags(vec!["round"])).collect::<Vec<_>>(), [&1, &2]);
+    for i in 0..10 {
+        map.insert(4, vec![if i % 2 == 0 { "even" } else { "odd" }]);
+    }
+    assert!(map.remove(&1));
+    assert!(map.tags.len() <= 2 * (map.tags.len() - map.garbage));
+    assert_eq!(map.iter().collect::<Vec<_>>(),
+               [(&2, &["green", "round"][..]), (&3, &["square"][..]), (&4, &["odd"][..])]);
+    map.insert(5, vec!["a", "a"]);
+    map.compact();
+    assert_eq!(map.tags, ["green", "round", "square", "odd", "a"]);
+    let map = TagMap::from(map);
+    assert_eq!(map.get_tags(&5), Some(&["a"][..]));
+    assert_eq!(ArenaTagMap::from(map).count_matching(&Anything), 4);
+}
@@ -0,0 +1,361 @@
+//! A compact binary snapshot format for maps.
+//!
+//! A snapshot starts with the magic bytes `TAGMAP`, followed by the format version as
+//! a little-endian `u32`. Then come the number of distinct tags and the tags, and the
+//! number of entries and the entries. Each entry is its key, the number of its tags,
+//! and their positions among the distinct tags. Counts and positions are written as
+//! LEB128 variable-length integers.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::marker::PhantomData;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use TagMap;
+
+const MAGIC: &[u8; 6] = b"TAGMAP";
+const VERSION: u32 = 1;
+
+/// A value that can be written to and read from a binary snapshot.
+///
+/// Implemented for strings, byte vectors, `bool`, and integer types. Integers are
+/// written as fixed-size little-endian values, strings and byte vectors are
+/// length-prefixed.
+pub trait BinaryValue: Sized {
+    /// Writes the value.
+    fn write_binary<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+    /// Reads a value written by `write_binary`.
+    fn read_binary<R: Read>(reader: &mut R) -> io::Result<Self>;
+}
+
+macro_rules! impl_binary_value_int {
+    ($($ty:ty),*) => {
+        $(
+            impl BinaryValue for $ty {
+                fn write_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                    writer.write_all(&self.to_le_bytes())
+                }
+                fn read_binary<R: Read>(reader: &mut R) -> io::Result<Self> {
+                    let mut bytes = [0; ::std::mem::size_of::<$ty>()];
+                    reader.read_exact(&mut bytes)?;
+                    Ok(<$ty>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    }
+}
+
+impl_binary_value_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl BinaryValue for bool {
+    fn write_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u8).write_binary(writer)
+    }
+    fn read_binary<R: Read>(reader: &mut R) -> io::Result<Self> {
+        match u8::read_binary(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid bool")),
+        }
+    }
+}
+
+impl BinaryValue for Vec<u8> {
+    fn write_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_len(writer, self.len())?;
+        writer.write_all(self)
+    }
+    fn read_binary<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let len = read_len(reader)?;
+        let mut bytes = Vec::new();
+        reader.take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(bytes)
+    }
+}
+
+impl BinaryValue for String {
+    fn write_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_len(writer, self.len())?;
+        writer.write_all(self.as_bytes())
+    }
+    fn read_binary<R: Read>(reader: &mut R) -> io::Result<Self> {
+        String::from_utf8(Vec::read_binary(reader)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+/// Writes a length as a LEB128 variable-length integer.
+pub(crate) fn write_len<W: Write>(writer: &mut W, mut len: usize) -> io::Result<()> {
+    while len >= 0x80 {
+        writer.write_all(&[len as u8 | 0x80])?;
+        len >>= 7;
+    }
+    writer.write_all(&[len as u8])
+}
+
+/// Reads a length written by `write_len`.
+pub(crate) fn read_len<R: Read>(reader: &mut R) -> io::Result<usize> {
+    let mut len = 0usize;
+    for shift in (0..usize::BITS).step_by(7) {
+        let byte = u8::read_binary(reader)?;
+        len |= ((byte & 0x7f) as usize).checked_shl(shift).unwrap_or(0);
+        if byte & 0x80 == 0 {
+            return Ok(len);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "length too large"))
+}
+
+/// Syncs the directory containing `path`, so a file renamed into it survives a crash.
+///
+/// Directories can't be opened as files on Windows, where this does nothing.
+fn sync_parent(path: &Path) -> io::Result<()> {
+    if cfg!(windows) {
+        return Ok(());
+    }
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// An error from loading a binary snapshot, from a reader or a file.
+#[derive(Debug)]
+pub enum BinaryError {
+    /// Reading failed, or a key or tag couldn't be read.
+    Io(io::Error),
+    /// The data isn't a snapshot.
+    NotSnapshot,
+    /// The snapshot has a format version this version of the crate can't load.
+    UnsupportedVersion(u32),
+    /// An entry refers to a tag that isn't in the snapshot.
+    InvalidTag,
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BinaryError::Io(ref err) => err.fmt(f),
+            BinaryError::NotSnapshot => f.write_str("not a tag map snapshot"),
+            BinaryError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            BinaryError::InvalidTag => f.write_str("entry refers to a missing tag"),
+        }
+    }
+}
+
+impl Error for BinaryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            BinaryError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BinaryError {
+    fn from(err: io::Error) -> Self {
+        BinaryError::Io(err)
+    }
+}
+
+impl<T: Ord + BinaryValue, TAG: Eq + BinaryValue> TagMap<T, TAG> {
+    /// Writes a binary snapshot of the entries.
+    ///
+    /// Each distinct tag is only written once. The vocabulary isn't written. For
+    /// performance, the writer should be buffered.
+    pub fn save_binary<W: Write>(&self, mut writer: W) -> io::Result<()>
+        where TAG: Ord
+    {
+        let mut positions = BTreeMap::new();
+        for tag in self.values().flat_map(|tags| tags.iter()) {
+            let next = positions.len();
+            positions.entry(tag).or_insert(next);
+        }
+        let mut tags: Vec<_> = positions.iter().collect();
+        tags.sort_by_key(|&(_, &pos)| pos);
+        writer.write_all(MAGIC)?;
+        VERSION.write_binary(&mut writer)?;
+        write_len(&mut writer, tags.len())?;
+        for (tag, _) in tags {
+            tag.write_binary(&mut writer)?;
+        }
+        write_len(&mut writer, self.len())?;
+        for (key, tags) in self.iter() {
+            key.write_binary(&mut writer)?;
+            write_len(&mut writer, tags.len())?;
+            for tag in tags {
+                write_len(&mut writer, positions[tag])?;
+            }
+        }
+        writer.flush()
+    }
+    /// Reads a snapshot written by `save_binary`.
+    ///
+    /// For performance, the reader should be buffered. See `BinaryEntries` to read the
+    /// entries one at a time instead.
+    pub fn load_binary<R: Read>(reader: R) -> Result<Self, BinaryError>
+        where TAG: Clone
+    {
+        BinaryEntries::new(reader)?.collect()
+    }
+    /// Saves a binary snapshot of the entries to a file, replacing it atomically.
+    ///
+    /// The snapshot is written and synced to a temporary file next to it, with `.tmp`
+    /// appended to the file name, which is then renamed over `path`, and the directory
+    /// is synced so the rename is durable. If saving fails or is interrupted, any
+    /// existing file at `path` is left intact.
+    pub fn save_to_path<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+        where TAG: Ord
+    {
+        let path = path.as_ref();
+        let mut temp_name = OsString::from(path.as_os_str());
+        temp_name.push(".tmp");
+        let temp_path = PathBuf::from(temp_name);
+        let result = File::create(&temp_path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            self.save_binary(&mut writer)?;
+            writer.into_inner().map_err(|err| err.into_error())?.sync_all()
+        });
+        match result {
+            Ok(()) => {
+                fs::rename(&temp_path, path)?;
+                sync_parent(path)
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&temp_path);
+                Err(err)
+            }
+        }
+    }
+    /// Loads a snapshot saved by `save_to_path` or `save_binary` from a file.
+    pub fn load_from_path<P: AsRef<Path>>(path: P) -> Result<Self, BinaryError>
+        where TAG: Clone
+    {
+        TagMap::load_binary(BufReader::new(File::open(path)?))
+    }
+}
+
+/// An iterator reading the entries of a binary snapshot one at a time.
+///
+/// Only the distinct tags of the snapshot are kept in memory, so entries can be
+/// processed without loading the whole map. Entries are read in key order.
+#[derive(Debug)]
+pub struct BinaryEntries<R, T, TAG> {
+    reader: R,
+    tags: Vec<TAG>,
+    remaining: usize,
+    key: PhantomData<T>,
+}
+
+impl<R: Read, T: BinaryValue, TAG: Clone + BinaryValue> BinaryEntries<R, T, TAG> {
+    /// Reads the header and the distinct tags of a snapshot written by
+    /// `TagMap::save_binary`.
+    ///
+    /// For performance, the reader should be buffered.
+    pub fn new(mut reader: R) -> Result<Self, BinaryError> {
+        let mut magic = [0; 6];
+        match reader.read_exact(&mut magic) {
+            Ok(()) if &magic == MAGIC => {}
+            Ok(()) => return Err(BinaryError::NotSnapshot),
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(BinaryError::NotSnapshot)
+            }
+            Err(err) => return Err(err.into()),
+        }
+        let version = u32::read_binary(&mut reader)?;
+        if version != VERSION {
+            return Err(BinaryError::UnsupportedVersion(version));
+        }
+        // Counts aren't trusted for preallocation, so corrupt data can't exhaust memory
+        let tag_count = read_len(&mut reader)?;
+        let mut tags = Vec::with_capacity(tag_count.min(1 << 16));
+        for _ in 0..tag_count {
+            tags.push(TAG::read_binary(&mut reader)?);
+        }
+        let remaining = read_len(&mut reader)?;
+        Ok(BinaryEntries { reader, tags, remaining, key: PhantomData })
+    }
+    fn read_entry(&mut self) -> Result<(T, Vec<TAG>), BinaryError> {
+        let key = T::read_binary(&mut self.reader)?;
+        let count = read_len(&mut self.reader)?;
+        let mut tags = Vec::with_capacity(count.min(self.tags.len()));
+        for _ in 0..count {
+            let tag = self.tags.get(read_len(&mut self.reader)?).ok_or(BinaryError::InvalidTag)?;
+            tags.push(tag.clone());
+        }
+        Ok((key, tags))
+    }
+}
+
+/// After an error, no more entries are read.
+impl<R: Read, T: BinaryValue, TAG: Clone + BinaryValue> Iterator for BinaryEntries<R, T, TAG> {
+    type Item = Result<(T, Vec<TAG>), BinaryError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let entry = self.read_entry();
+        self.remaining = if entry.is_ok() { self.remaining - 1 } else { 0 };
+        Some(entry)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
+
+#[test]
+fn test_binary() {
+    let mut map = TagMap::new();
+    map.insert("a.png".to_string(), vec!["cat".to_string(), "cute".to_string()]);
+    map.insert("b.png".to_string(), vec!["cute".to_string()]);
+    map.insert("c.png".to_string(), vec![]);
+    let mut out = Vec::new();
+    map.save_binary(&mut out).unwrap();
+    assert_eq!(out[..10], b"TAGMAP\x01\0\0\0"[..]);
+    let loaded: TagMap<String, String> = TagMap::load_binary(&out[..]).unwrap();
+    assert_eq!(loaded, map);
+    let mut numbers = TagMap::new();
+    numbers.insert(300u32, (0..200u16).collect());
+    let mut out = Vec::new();
+    numbers.save_binary(&mut out).unwrap();
+    assert_eq!(TagMap::load_binary(&out[..]).unwrap(), numbers);
+    let mut entries = BinaryEntries::<_, u32, u16>::new(&out[..]).unwrap();
+    assert_eq!(entries.next().unwrap().unwrap().0, 300);
+    assert!(entries.next().is_none());
+    let mut future = out.clone();
+    future[6] = 2;
+    let err = TagMap::<u32, u16>::load_binary(&future[..]).unwrap_err();
+    assert!(matches!(err, BinaryError::UnsupportedVersion(2)));
+    let err = TagMap::<u32, u16>::load_binary(&b"{}"[..]).unwrap_err();
+    assert!(matches!(err, BinaryError::NotSnapshot));
+    let err = TagMap::<u32, u16>::load_binary(&out[..out.len() - 1]).unwrap_err();
+    assert!(matches!(err, BinaryError::Io(_)));
+}
+
+#[test]
+fn test_save_to_path() {
+    let dir = ::std::env::temp_dir().join(format!("tagmap-test-save-{}", ::std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("map");
+    let mut map = TagMap::new();
+    map.insert(1u32, vec!["a".to_string()]);
+    map.save_to_path(&path).unwrap();
+    map.insert(2, vec!["b".to_string()]);
+    map.save_to_path(&path).unwrap();
+    assert_eq!(TagMap::load_from_path(&path).unwrap(), map);
+    assert!(!dir.join("map.tmp").exists());
+    let err = TagMap::<u32, String>::load_from_path(dir.join("missing")).unwrap_err();
+    assert!(matches!(err, BinaryError::Io(ref err) if err.kind() == io::ErrorKind::NotFound));
+    fs::write(&path, "not a snapshot").unwrap();
+    let err = TagMap::<u32, String>::load_from_path(&path).unwrap_err();
+    assert_eq!(err.to_string(), "not a tag map snapshot");
+    fs::remove_dir_all(&dir).unwrap();
+}
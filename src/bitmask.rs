@@ -0,0 +1,156 @@
+//! Rules evaluated with bit operations, for small `Copy` tags.
+
+use {MatchRule, Matcher, SharedPattern, TagPredicate};
+
+/// A tag that can be represented by a single bit of a `u64`.
+///
+/// Meant for small sets of tags, like fieldless enums with at most 64 variants.
+/// Rules on such tags can be converted to a `BitRule`.
+pub trait BitTag: Copy + Eq {
+    /// Returns the number of the bit representing the tag, less than 64.
+    ///
+    /// Distinct tags must have distinct bits.
+    fn bit(self) -> u32;
+}
+
+/// A `MatchRule` on `BitTag`s, evaluated with bit operations.
+///
+/// The tags of an entry are turned into a bitmask once, and rules on listed tags
+/// test that mask against the mask of their tags, instead of comparing tags.
+///
+/// Created by `MatchRule::to_bit_rule`.
+#[derive(Debug, Clone)]
+pub struct BitRule<TAG>(Node<TAG>);
+
+#[derive(Debug, Clone)]
+enum Node<TAG> {
+    All(u64),
+    NotAny(u64),
+    Any(u64),
+    AtLeast(usize, u64),
+    Exactly(usize, u64),
+    AtMost(usize, u64),
+    AllRules(Vec<Node<TAG>>),
+    NotAnyRule(Vec<Node<TAG>>),
+    AnyRule(Vec<Node<TAG>>),
+    Xor(Vec<Node<TAG>>),
+    Not(Box<Node<TAG>>),
+    Anything,
+    Nothing,
+    Predicate(TagPredicate<TAG>),
+    Pattern(SharedPattern<TAG>),
+}
+
+impl<TAG: BitTag> MatchRule<TAG> {
+    /// Converts the rule to a `BitRule`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a listed tag has a bit of 64 or more.
+    pub fn to_bit_rule(&self) -> BitRule<TAG> {
+        BitRule(node(self))
+    }
+}
+
+fn mask<TAG: BitTag>(tags: &[TAG]) -> u64 {
+    tags.iter().fold(0, |mask, tag| {
+        let bit = tag.bit();
+        assert!(bit < 64, "tag bit out of range: {}", bit);
+        mask | 1 << bit
+    })
+}
+
+fn node<TAG: BitTag>(rule: &MatchRule<TAG>) -> Node<TAG> {
+    use MatchRule::*;
+    let nodes = |rules: &[MatchRule<TAG>]| rules.iter().map(node).collect();
+    match *rule {
+        Tags(ref tags) => Node::All(mask(tags)),
+        NotTags(ref tags) => Node::NotAny(mask(tags)),
+        AnyTag(ref tags) => Node::Any(mask(tags)),
+        AtLeast(n, ref tags) => Node::AtLeast(n, mask(tags)),
+        Exactly(n, ref tags) => Node::Exactly(n, mask(tags)),
+        AtMost(n, ref tags) => Node::AtMost(n, mask(tags)),
+        Rules(ref rules) => Node::AllRules(nodes(rules)),
+        NotRules(ref rules) => Node::NotAnyRule(nodes(rules)),
+        AnyRule(ref rules) => Node::AnyRule(nodes(rules)),
+        Xor(ref rules) => Node::Xor(nodes(rules)),
+        Not(ref rule) => Node::Not(Box::new(node(rule))),
+        Anything => Node::Anything,
+        Nothing => Node::Nothing,
+        Predicate(ref pred) => Node::Predicate(pred.clone()),
+        Pattern(ref pattern) => Node::Pattern(pattern.clone()),
+    }
+}
+
+impl<TAG> Node<TAG> {
+    fn eval(&self, present: u64, tags: &[TAG]) -> bool {
+        let count = |mask: u64| (present & mask).count_ones() as usize;
+        match *self {
+            Node::All(mask) => present & mask == mask,
+            Node::NotAny(mask) => present & mask == 0,
+            Node::Any(mask) => present & mask != 0,
+            Node::AtLeast(n, mask) => count(mask) >= n,
+            Node::Exactly(n, mask) => count(mask) == n,
+            Node::AtMost(n, mask) => count(mask) <= n,
+            Node::AllRules(ref nodes) => nodes.iter().all(|node| node.eval(present, tags)),
+            Node::NotAnyRule(ref nodes) => !nodes.iter().any(|node| node.eval(present, tags)),
+            Node::AnyRule(ref nodes) => nodes.iter().any(|node| node.eval(present, tags)),
+            Node::Xor(ref nodes) => {
+                let mut matching = nodes.iter().filter(|node| node.eval(present, tags));
+                matching.next().is_some() && matching.next().is_none()
+            }
+            Node::Not(ref node) => !node.eval(present, tags),
+            Node::Anything => true,
+            Node::Nothing => false,
+            Node::Predicate(ref pred) => (pred.0)(tags),
+            Node::Pattern(ref pattern) => tags.iter().any(|tag| pattern.matches_tag(tag)),
+        }
+    }
+}
+
+impl<TAG: BitTag> Matcher<TAG> for BitRule<TAG> {
+    fn matches(&self, tags: &[TAG]) -> bool {
+        // Tags out of range can't be listed in the rule, so they are left out
+        let present = tags.iter()
+            .filter(|tag| tag.bit() < 64)
+            .fold(0, |mask, tag| mask | 1 << tag.bit());
+        self.0.eval(present, tags)
+    }
+}
+
+#[test]
+fn test_bit_rule() {
+    use MatchRule::*;
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Kind {
+        Player,
+        Enemy,
+        Flying,
+        Boss,
+        Far = 100,
+    }
+    impl BitTag for Kind {
+        fn bit(self) -> u32 {
+            self as u32
+        }
+    }
+    let (player, enemy, flying, boss) = (Kind::Player, Kind::Enemy, Kind::Flying, Kind::Boss);
+    let kinds = [player, enemy, flying, boss];
+    let rules = [Tags(vec![enemy, flying]), NotTags(vec![player, player]),
+                 AnyTag(vec![boss, flying]), AtLeast(2, vec![enemy, enemy, boss, flying]),
+                 Exactly(1, vec![player, boss]), AtMost(0, vec![flying]),
+                 Rules(vec![Tags(vec![enemy]), Not(Box::new(AnyTag(vec![boss])))]),
+                 Xor(vec![Tags(vec![player]), Tags(vec![enemy]), Anything]),
+                 NotRules(vec![Nothing, AnyRule(vec![Tags(vec![boss])])]),
+                 MatchRule::predicate(|tags: &[Kind]| tags.contains(&Kind::Far))];
+    for subset in 0..32 {
+        let mut entry: Vec<_> =
+            (0..4).filter(|i| subset & (1 << i) != 0).map(|i| kinds[i]).collect();
+        if subset & 16 != 0 {
+            entry.push(Kind::Far);
+        }
+        for rule in &rules {
+            assert_eq!(rule.to_bit_rule().matches(&entry), rule.matches(&entry), "{:?}", rule);
+        }
+    }
+}
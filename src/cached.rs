@@ -0,0 +1,181 @@
+//! A tag map caching the results of recent queries.
+
+use std::borrow::Borrow;
+use std::ops::Deref;
+use {MatchRule, Matcher, TagMap};
+
+/// A `TagMap` that memoizes the matching keys of recently used rules.
+///
+/// Cached results are kept up to date as the map changes: modifying an entry
+/// only re-evaluates the cached rules against that entry. At most `capacity`
+/// rules are cached, evicting the least recently used one. The map can be read
+/// through `Deref`, but must be modified through the methods of this type.
+#[derive(Debug, Clone)]
+pub struct CachedTagMap<T: Ord, TAG: Eq> {
+    map: TagMap<T, TAG>,
+    /// Rules with their matching keys in order, most recently used first.
+    cache: Vec<(MatchRule<TAG>, Vec<T>)>,
+    capacity: usize,
+}
+
+impl<T: Ord + Clone, TAG: Eq + Clone> CachedTagMap<T, TAG> {
+    /// Creates a new empty map, caching up to 16 rules.
+    pub fn new() -> Self {
+        Self::with_capacity(16)
+    }
+    /// Creates a new empty map, caching up to `capacity` rules.
+    pub fn with_capacity(capacity: usize) -> Self {
+        CachedTagMap {
+            map: TagMap::new(),
+            cache: Vec::new(),
+            capacity,
+        }
+    }
+    /// Returns the underlying map.
+    pub fn into_inner(self) -> TagMap<T, TAG> {
+        self.map
+    }
+    /// Returns the number of cached rules.
+    pub fn cached_rules(&self) -> usize {
+        self.cache.len()
+    }
+    /// Discards all cached results.
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+    /// Removes all entries from the map.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        for (_, keys) in &mut self.cache {
+            keys.clear();
+        }
+    }
+    /// Removes duplicate tags within entries, and frees the unused capacity of the tag lists.
+    ///
+    /// Cached results stay valid, since duplicate tags don't affect matching.
+    pub fn compact(&mut self) {
+        self.map.compact();
+    }
+    /// Inserts an entry with the given tags.
+    ///
+    /// If the key was already present, its old tags are replaced and returned.
+    pub fn insert(&mut self, key: T, tags: Vec<TAG>) -> Option<Vec<TAG>> {
+        let old = self.map.insert(key.clone(), tags);
+        self.update(&key);
+        old
+    }
+    /// Removes an entry, returning its tags if it was present.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<Vec<TAG>>
+        where T: Borrow<Q>
+    {
+        let tags = self.map.remove(key)?;
+        for (_, keys) in &mut self.cache {
+            if let Ok(i) = keys.binary_search_by(|k| k.borrow().cmp(key)) {
+                keys.remove(i);
+            }
+        }
+        Some(tags)
+    }
+    /// Adds a tag to an entry, inserting the entry if it's not present.
+    ///
+    /// Returns false if the entry already had the tag.
+    pub fn add_tag(&mut self, key: T, tag: TAG) -> bool {
+        let added = self.map.add_tag(key.clone(), tag);
+        self.update(&key);
+        added
+    }
+    /// Removes a tag from an entry.
+    ///
+    /// Returns false if the entry is not present or didn't have the tag.
+    pub fn remove_tag<Q: ?Sized + Ord>(&mut self, key: &Q, tag: &TAG) -> bool
+        where T: Borrow<Q>
+    {
+        let removed = self.map.remove_tag(key, tag);
+        if removed {
+            self.update(key);
+        }
+        removed
+    }
+    /// Returns the keys of the entries matching the given rule, in order.
+    ///
+    /// The result is computed only if the rule isn't cached.
+    pub fn matching(&mut self, rule: &MatchRule<TAG>) -> &[T] {
+        match self.cache.iter().position(|(cached, _)| cached == rule) {
+            Some(i) => self.cache[..=i].rotate_right(1),
+            None => {
+                let keys = self.map.matching(rule).cloned().collect();
+                self.cache.insert(0, (rule.clone(), keys));
+                self.cache.truncate(self.capacity.max(1));
+            }
+        }
+        &self.cache[0].1
+    }
+    /// Re-evaluates the cached rules against the entry for `key`.
+    fn update<Q: ?Sized + Ord>(&mut self, key: &Q)
+        where T: Borrow<Q>
+    {
+        let entry = self.map.entries.get_key_value(key);
+        for (rule, keys) in &mut self.cache {
+            let matches = entry.is_some_and(|(_, tags)| rule.matches(tags));
+            match (keys.binary_search_by(|k| k.borrow().cmp(key)), entry) {
+                (Ok(i), _) if !matches => {
+                    keys.remove(i);
+                }
+                (Err(i), Some((key, _))) if matches => keys.insert(i, key.clone()),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<T: Ord + Clone, TAG: Eq + Clone> Default for CachedTagMap<T, TAG> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord + Clone, TAG: Eq + Clone> From<TagMap<T, TAG>> for CachedTagMap<T, TAG> {
+    fn from(map: TagMap<T, TAG>) -> Self {
+        CachedTagMap { map, ..Self::new() }
+    }
+}
+
+impl<T: Ord, TAG: Eq> Deref for CachedTagMap<T, TAG> {
+    type Target = TagMap<T, TAG>;
+    fn deref(&self) -> &TagMap<T, TAG> {
+        &self.map
+    }
+}
+
+#[test]
+fn test_cached() {
+    use MatchRule::*;
+    let mut map = CachedTagMap::with_capacity(2);
+    map.insert("a", vec!["red"]);
+    map.insert("b", vec!["red", "large"]);
+    map.insert("c", vec!["blue"]);
+    let red = Tags(vec!["red"]);
+    let small = NotTags(vec!["large"]);
+    assert_eq!(map.matching(&red), ["a", "b"]);
+    assert_eq!(map.matching(&small), ["a", "c"]);
+    map.add_tag("c", "red");
+    map.remove_tag(&"a", &"red");
+    map.insert("d", vec!["large"]);
+    map.remove("b");
+    assert_eq!(map.cached_rules(), 2);
+    assert_eq!(map.matching(&red), ["c"]);
+    assert_eq!(map.matching(&small), ["a", "c"]);
+    assert_eq!(map.matching(&Anything), ["a", "c", "d"]);
+    assert_eq!(map.cached_rules(), 2);
+    map.add_tag("e", "red");
+    assert_eq!(map.matching(&small), ["a", "c", "e"]);
+    assert_eq!(map.matching(&red), ["c", "e"]);
+    let mut map = CachedTagMap::new();
+    map.insert("a".to_string(), vec!["red"]);
+    assert_eq!(map.matching(&small), ["a"]);
+    map.add_tag("a".to_string(), "large");
+    assert!(map.matching(&small).is_empty());
+    assert!(map.remove_tag("a", &"large"));
+    assert!(!map.remove_tag("b", &"large"));
+    assert_eq!(map.matching(&small), ["a"]);
+}
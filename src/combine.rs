@@ -0,0 +1,214 @@
+//! Combining the entries of two maps.
+
+use std::cmp::Ordering;
+use TagMap;
+
+/// How to combine the tags of a key present in both maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Keep the tags of both entries, skipping the ones the first entry already has.
+    Union,
+    /// Keep the tags of the entry in `self`.
+    PreferSelf,
+    /// Keep the tags of the entry in `other`.
+    PreferOther,
+}
+
+impl MergeStrategy {
+    /// Combines the tags of the entry in `self` with the ones of the entry in `other`.
+    fn combine<TAG: Eq>(self, tags: &mut Vec<TAG>, other: Vec<TAG>) {
+        match self {
+            MergeStrategy::Union => {
+                for tag in other {
+                    if !tags.contains(&tag) {
+                        tags.push(tag);
+                    }
+                }
+            }
+            MergeStrategy::PreferSelf => {}
+            MergeStrategy::PreferOther => *tags = other,
+        }
+    }
+}
+
+impl<T: Ord, TAG: Eq> TagMap<T, TAG> {
+    /// Moves the entries of `other` into the map, combining the tags of the keys present
+    /// in both according to `strategy`.
+    ///
+    /// The vocabulary of `other` is discarded.
+    pub fn merge(&mut self, other: TagMap<T, TAG>, strategy: MergeStrategy) {
+        self.merge_with(other, |_, tags, other| strategy.combine(tags, other));
+    }
+    /// Moves the entries of `other` into the map, calling `combine` with the key, the
+    /// tags in the map and the tags from `other` for the keys present in both.
+    ///
+    /// The vocabulary of `other` is discarded.
+    pub fn merge_with<F>(&mut self, other: TagMap<T, TAG>, mut combine: F)
+        where F: FnMut(&T, &mut Vec<TAG>, Vec<TAG>)
+    {
+        for (key, other_tags) in other {
+            match self.entries.get_mut(&key) {
+                Some(tags) => combine(&key, tags, other_tags),
+                None => {
+                    self.entries.insert(key, other_tags);
+                }
+            }
+        }
+    }
+    /// Returns a map of the keys present in both maps, with their tags combined
+    /// according to `strategy`.
+    pub fn intersection(&self, other: &TagMap<T, TAG>, strategy: MergeStrategy) -> Self
+        where T: Clone,
+              TAG: Clone
+    {
+        self.intersection_with(other, |_, tags, other| match strategy {
+            MergeStrategy::Union => {
+                let mut tags = tags.to_vec();
+                for tag in other {
+                    if !tags.contains(tag) {
+                        tags.push(tag.clone());
+                    }
+                }
+                tags
+            }
+            MergeStrategy::PreferSelf => tags.to_vec(),
+            MergeStrategy::PreferOther => other.to_vec(),
+        })
+    }
+    /// Returns a map of the keys present in both maps, with the tags returned by
+    /// `combine` for the key, its tags in the map and its tags in `other`.
+    pub fn intersection_with<F>(&self, other: &TagMap<T, TAG>, mut combine: F) -> Self
+        where T: Clone,
+              F: FnMut(&T, &[TAG], &[TAG]) -> Vec<TAG>
+    {
+        let mut entries = Vec::new();
+        join(self, other, |key, tags, other_tags| {
+            if let (Some(tags), Some(other_tags)) = (tags, other_tags) {
+                entries.push((key.clone(), combine(key, tags, other_tags)));
+            }
+        });
+        TagMap::from_sorted_iter(entries)
+    }
+    /// Returns a map of the entries whose keys aren't present in `other`.
+    pub fn difference(&self, other: &TagMap<T, TAG>) -> Self
+        where T: Clone,
+              TAG: Clone
+    {
+        let mut entries = Vec::new();
+        join(self, other, |key, tags, other_tags| {
+            if let (Some(tags), None) = (tags, other_tags) {
+                entries.push((key.clone(), tags.to_vec()));
+            }
+        });
+        TagMap::from_sorted_iter(entries)
+    }
+    /// Returns a map of the entries whose keys are present in only one of the maps.
+    ///
+    /// Whether an entry comes from `self` or `other` can be told with `contains_key`.
+    pub fn symmetric_difference(&self, other: &TagMap<T, TAG>) -> Self
+        where T: Clone,
+              TAG: Clone
+    {
+        let mut entries = Vec::new();
+        join(self, other, |key, tags, other_tags| {
+            match (tags, other_tags) {
+                (Some(tags), None) | (None, Some(tags)) => {
+                    entries.push((key.clone(), tags.to_vec()))
+                }
+                _ => {}
+            }
+        });
+        TagMap::from_sorted_iter(entries)
+    }
+}
+
+/// Visits the keys of both maps in order, with their tags in each map.
+pub(crate) fn join<'a, T, TAG, F>(ours: &'a TagMap<T, TAG>,
+                                  theirs: &'a TagMap<T, TAG>,
+                                  mut visit: F)
+    where T: Ord,
+          TAG: Eq,
+          F: FnMut(&'a T, Option<&'a [TAG]>, Option<&'a [TAG]>)
+{
+    let (mut ours, mut theirs) = (ours.iter().peekable(), theirs.iter().peekable());
+    loop {
+        let order = match (ours.peek(), theirs.peek()) {
+            (Some(&(a, _)), Some(&(b, _))) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return,
+        };
+        match order {
+            Ordering::Less => {
+                let (key, tags) = ours.next().unwrap();
+                visit(key, Some(tags), None);
+            }
+            Ordering::Greater => {
+                let (key, tags) = theirs.next().unwrap();
+                visit(key, None, Some(tags));
+            }
+            Ordering::Equal => {
+                let ((key, tags), (_, other_tags)) = (ours.next().unwrap(), theirs.next().unwrap());
+                visit(key, Some(tags), Some(other_tags));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_merge() {
+    let make = |entries: &[(u32, &[&'static str])]| -> TagMap<u32, &'static str> {
+        entries.iter().map(|&(key, tags)| (key, tags.to_vec())).collect()
+    };
+    let ours = make(&[(1, &["a", "b"]), (2, &["c"])]);
+    let theirs = || make(&[(2, &["c", "d"]), (3, &["e"])]);
+    let mut union = ours.clone();
+    union.merge(theirs(), MergeStrategy::Union);
+    assert_eq!(union, make(&[(1, &["a", "b"]), (2, &["c", "d"]), (3, &["e"])]));
+    let mut kept = ours.clone();
+    kept.merge(theirs(), MergeStrategy::PreferSelf);
+    assert_eq!(kept, make(&[(1, &["a", "b"]), (2, &["c"]), (3, &["e"])]));
+    let mut replaced = ours.clone();
+    replaced.merge(theirs(), MergeStrategy::PreferOther);
+    assert_eq!(replaced.get_tags(&2), Some(&["c", "d"][..]));
+    let mut custom = ours;
+    custom.merge_with(theirs(), |&key, tags, other| {
+        assert_eq!(key, 2);
+        tags.retain(|tag| other.contains(tag));
+    });
+    assert_eq!(custom.get_tags(&2), Some(&["c"][..]));
+    assert_eq!(custom.len(), 3);
+}
+
+#[test]
+fn test_intersection() {
+    let ours: TagMap<_, _> = vec![(1, vec!["a"]), (2, vec!["b", "c"]), (4, vec![])]
+        .into_iter()
+        .collect();
+    let theirs: TagMap<_, _> = vec![(0, vec!["x"]), (2, vec!["c", "d"]), (4, vec!["e"])]
+        .into_iter()
+        .collect();
+    let both = ours.intersection(&theirs, MergeStrategy::Union);
+    assert_eq!(both.iter().collect::<Vec<_>>(),
+               [(&2, &["b", "c", "d"][..]), (&4, &["e"][..])]);
+    let theirs_only = ours.intersection(&theirs, MergeStrategy::PreferOther);
+    assert_eq!(theirs_only.get_tags(&2), Some(&["c", "d"][..]));
+    let common = ours.intersection_with(&theirs, |_, a, b| {
+        a.iter().filter(|tag| b.contains(tag)).cloned().collect()
+    });
+    assert_eq!(common.get_tags(&2), Some(&["c"][..]));
+    assert_eq!(common.keys().collect::<Vec<_>>(), [&2, &4]);
+}
+
+#[test]
+fn test_difference() {
+    let ours: TagMap<_, _> =
+        vec![(1, vec!["a"]), (2, vec!["b"]), (5, vec!["c"])].into_iter().collect();
+    let theirs: TagMap<_, _> = vec![(2, vec!["x"]), (3, vec!["y"])].into_iter().collect();
+    assert_eq!(ours.difference(&theirs).keys().collect::<Vec<_>>(), [&1, &5]);
+    assert_eq!(theirs.difference(&ours).get_tags(&3), Some(&["y"][..]));
+    let either = ours.symmetric_difference(&theirs);
+    assert_eq!(either.iter().collect::<Vec<_>>(),
+               [(&1, &["a"][..]), (&3, &["y"][..]), (&5, &["c"][..])]);
+    assert!(ours.symmetric_difference(&ours).is_empty());
+}
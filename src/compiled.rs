@@ -0,0 +1,215 @@
+//! Rules compiled for repeated evaluation.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use {MatchRule, Matcher, SharedPattern, TagPredicate};
+
+/// A `MatchRule` compiled for fast repeated evaluation.
+///
+/// Every distinct tag listed in the rule is numbered once, and evaluating the rule
+/// looks up each tag of the entry a single time, recording which listed tags are
+/// present in a bitset. The rule tree is flattened into a list of nodes that test
+/// that bitset, instead of searching the entry's tags again for every listed tag.
+///
+/// Created by `MatchRule::compile`. Rules on interned tags can be compiled with
+/// `InternedTagMap::compile`.
+#[derive(Debug, Clone)]
+pub struct CompiledRule<TAG> {
+    tags: HashMap<TAG, usize>,
+    nodes: Vec<Node<TAG>>,
+    /// Tag numbers of the tag nodes, and node numbers of the rule nodes.
+    operands: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+enum Node<TAG> {
+    All(usize, usize),
+    NotAny(usize, usize),
+    Any(usize, usize),
+    AtLeast(usize, usize, usize),
+    Exactly(usize, usize, usize),
+    AtMost(usize, usize, usize),
+    AllRules(usize, usize),
+    NotAnyRule(usize, usize),
+    AnyRule(usize, usize),
+    Xor(usize, usize),
+    Not(usize),
+    Anything,
+    Nothing,
+    Predicate(TagPredicate<TAG>),
+    Pattern(SharedPattern<TAG>),
+}
+
+impl<TAG: Hash + Eq + Clone> MatchRule<TAG> {
+    /// Compiles the rule for fast repeated evaluation.
+    pub fn compile(&self) -> CompiledRule<TAG> {
+        let mut compiled = CompiledRule {
+            tags: HashMap::new(),
+            nodes: Vec::new(),
+            operands: Vec::new(),
+        };
+        compiled.add(self);
+        compiled
+    }
+}
+
+impl<TAG: Hash + Eq + Clone> CompiledRule<TAG> {
+    /// Adds the node for a rule, after the nodes of its subrules, and returns its number.
+    fn add(&mut self, rule: &MatchRule<TAG>) -> usize {
+        use MatchRule::*;
+        let node = match *rule {
+            Tags(ref tags) => self.tag_node(tags, Node::All),
+            NotTags(ref tags) => self.tag_node(tags, Node::NotAny),
+            AnyTag(ref tags) => self.tag_node(tags, Node::Any),
+            AtLeast(n, ref tags) => self.tag_node(tags, |s, e| Node::AtLeast(n, s, e)),
+            Exactly(n, ref tags) => self.tag_node(tags, |s, e| Node::Exactly(n, s, e)),
+            AtMost(n, ref tags) => self.tag_node(tags, |s, e| Node::AtMost(n, s, e)),
+            Rules(ref rules) => self.rule_node(rules, inline_all, Node::AllRules),
+            AnyRule(ref rules) => self.rule_node(rules, inline_any, Node::AnyRule),
+            NotRules(ref rules) => self.rule_node(rules, |_| None, Node::NotAnyRule),
+            Xor(ref rules) => self.rule_node(rules, |_| None, Node::Xor),
+            Not(ref rule) => Node::Not(self.add(rule)),
+            Anything => Node::Anything,
+            Nothing => Node::Nothing,
+            Predicate(ref pred) => Node::Predicate(pred.clone()),
+            Pattern(ref pattern) => Node::Pattern(pattern.clone()),
+        };
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+    /// Numbers the distinct listed tags, and stores their numbers as operands.
+    fn tag_node<F>(&mut self, tags: &[TAG], node: F) -> Node<TAG>
+        where F: FnOnce(usize, usize) -> Node<TAG>
+    {
+        let start = self.operands.len();
+        for tag in tags {
+            let next = self.tags.len();
+            let number = *self.tags.entry(tag.clone()).or_insert(next);
+            if !self.operands[start..].contains(&number) {
+                self.operands.push(number);
+            }
+        }
+        node(start, self.operands.len())
+    }
+    /// Adds the subrules, replacing those `inline` returns subrules for by their subrules.
+    fn rule_node<F>(&mut self, rules: &[MatchRule<TAG>], inline: Inline<TAG>, node: F) -> Node<TAG>
+        where F: FnOnce(usize, usize) -> Node<TAG>
+    {
+        let mut flat = Vec::new();
+        flatten(rules, inline, &mut flat);
+        let children: Vec<_> = flat.into_iter().map(|rule| self.add(rule)).collect();
+        let start = self.operands.len();
+        self.operands.extend(children);
+        node(start, self.operands.len())
+    }
+}
+
+type Inline<TAG> = fn(&MatchRule<TAG>) -> Option<&[MatchRule<TAG>]>;
+
+fn inline_all<TAG>(rule: &MatchRule<TAG>) -> Option<&[MatchRule<TAG>]> {
+    match *rule {
+        MatchRule::Rules(ref rules) => Some(rules),
+        _ => None,
+    }
+}
+
+fn inline_any<TAG>(rule: &MatchRule<TAG>) -> Option<&[MatchRule<TAG>]> {
+    match *rule {
+        MatchRule::AnyRule(ref rules) => Some(rules),
+        _ => None,
+    }
+}
+
+fn flatten<'a, TAG>(rules: &'a [MatchRule<TAG>],
+                    inline: Inline<TAG>,
+                    out: &mut Vec<&'a MatchRule<TAG>>) {
+    for rule in rules {
+        match inline(rule) {
+            Some(inner) => flatten(inner, inline, out),
+            None => out.push(rule),
+        }
+    }
+}
+
+impl<TAG> CompiledRule<TAG> {
+    /// Returns the number of distinct tags listed in the rule.
+    pub fn tag_count(&self) -> usize {
+        self.tags.len()
+    }
+    fn eval(&self, node: usize, tags: &[TAG], present: &[u64]) -> bool {
+        let is_present = |&number: &usize| present[number / 64] & (1 << (number % 64)) != 0;
+        let count = |start, end| self.operands[start..end].iter().filter(|n| is_present(n)).count();
+        let children = |start, end| {
+            self.operands[start..end].iter().map(move |&child| self.eval(child, tags, present))
+        };
+        match self.nodes[node] {
+            Node::All(start, end) => self.operands[start..end].iter().all(is_present),
+            Node::NotAny(start, end) => !self.operands[start..end].iter().any(is_present),
+            Node::Any(start, end) => self.operands[start..end].iter().any(is_present),
+            Node::AtLeast(n, start, end) => count(start, end) >= n,
+            Node::Exactly(n, start, end) => count(start, end) == n,
+            Node::AtMost(n, start, end) => count(start, end) <= n,
+            Node::AllRules(start, end) => children(start, end).all(|m| m),
+            Node::NotAnyRule(start, end) => !children(start, end).any(|m| m),
+            Node::AnyRule(start, end) => children(start, end).any(|m| m),
+            Node::Xor(start, end) => {
+                let mut matching = children(start, end).filter(|&m| m);
+                matching.next().is_some() && matching.next().is_none()
+            }
+            Node::Not(child) => !self.eval(child, tags, present),
+            Node::Anything => true,
+            Node::Nothing => false,
+            Node::Predicate(ref pred) => (pred.0)(tags),
+            Node::Pattern(ref pattern) => tags.iter().any(|tag| pattern.matches_tag(tag)),
+        }
+    }
+}
+
+impl<TAG: Hash + Eq> Matcher<TAG> for CompiledRule<TAG> {
+    fn matches(&self, tags: &[TAG]) -> bool {
+        let words = self.tags.len().div_ceil(64);
+        let mut inline = [0u64; 4];
+        let mut heap = Vec::new();
+        let present = if words <= inline.len() {
+            &mut inline[..words]
+        } else {
+            heap.resize(words, 0);
+            &mut heap[..]
+        };
+        for tag in tags {
+            if let Some(&number) = self.tags.get(tag) {
+                present[number / 64] |= 1 << (number % 64);
+            }
+        }
+        self.eval(self.nodes.len() - 1, tags, present)
+    }
+}
+
+#[test]
+fn test_compile() {
+    use MatchRule::*;
+    let tags = ["a", "b", "c", "d"];
+    let mut rules = vec![Tags(vec!["a", "b"]), NotTags(vec!["c", "c"]), AnyTag(vec!["d", "e"]),
+                         AtLeast(2, vec!["a", "a", "b", "e"]), Exactly(1, vec!["c", "d"]),
+                         AtMost(1, vec!["a", "b"]), Anything, Nothing,
+                         MatchRule::predicate(|tags: &[&str]| tags.len() == 2)];
+    rules.push(Rules(vec![Rules(vec![rules[0].clone(), rules[2].clone()]), rules[1].clone()]));
+    rules.push(AnyRule(vec![AnyRule(vec![rules[4].clone()]), Not(Box::new(rules[3].clone()))]));
+    rules.push(Xor(rules.clone()));
+    rules.push(NotRules(vec![rules[5].clone(), rules[11].clone()]));
+    let many: Vec<String> = (0..200).map(|i| i.to_string()).collect();
+    let wide = AtLeast(2, many.clone());
+    let wide_compiled = wide.compile();
+    for subset in 0..16 {
+        let entry: Vec<_> = (0..4).filter(|i| subset & (1 << i) != 0).map(|i| tags[i]).collect();
+        for rule in &rules {
+            assert_eq!(rule.compile().matches(&entry), rule.matches(&entry), "{:?}", rule);
+        }
+        let entry: Vec<_> = (0..4)
+            .filter(|i| subset & (1 << i) != 0)
+            .map(|i| many[i * 50 + 10].clone())
+            .collect();
+        assert_eq!(wide_compiled.matches(&entry), wide.matches(&entry));
+    }
+    assert_eq!(rules[3].compile().tag_count(), 3);
+}
@@ -0,0 +1,224 @@
+//! A tag map that can be shared between threads.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use {Matcher, TagMap, Vocabulary};
+
+/// A `TagMap` split into shards, each behind its own lock, for use from multiple threads.
+///
+/// Each entry belongs to a shard picked by hashing its key. Changing an entry only
+/// locks its shard for writing, and queries lock one shard at a time for reading, so
+/// readers don't block each other, and only wait for writers to the shard they read.
+///
+/// Queries return owned keys and tags, since the locks are released before returning.
+/// A query sees each shard as it was when the shard was read, so changes made to
+/// several shards while it runs may be partially visible.
+///
+/// The vocabulary of a map converted with `From` is kept aside, unchanged, and given
+/// back by `into_inner`. Rules can be resolved against it with `vocabulary`.
+#[derive(Debug)]
+pub struct ConcurrentTagMap<T: Ord, TAG: Eq, S = RandomState> {
+    shards: Vec<RwLock<TagMap<T, TAG>>>,
+    vocabulary: Vocabulary<TAG>,
+    hash_builder: S,
+}
+
+impl<T: Ord + Hash, TAG: Eq> ConcurrentTagMap<T, TAG> {
+    /// Creates a new empty map, with a shard per available CPU, rounded up to a power
+    /// of two.
+    pub fn new() -> Self {
+        let cpus = ::std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_shards(cpus.next_power_of_two())
+    }
+    /// Creates a new empty map with the given number of shards, at least one.
+    pub fn with_shards(shards: usize) -> Self {
+        Self::with_shards_and_hasher(shards, RandomState::new())
+    }
+}
+
+impl<T: Ord + Hash, TAG: Eq, S: BuildHasher> ConcurrentTagMap<T, TAG, S> {
+    /// Creates a new empty map with the given number of shards, at least one, hashing
+    /// keys with `hash_builder`.
+    pub fn with_shards_and_hasher(shards: usize, hash_builder: S) -> Self {
+        ConcurrentTagMap {
+            shards: (0..shards.max(1)).map(|_| RwLock::new(TagMap::new())).collect(),
+            vocabulary: Vocabulary::new(),
+            hash_builder,
+        }
+    }
+    /// Returns the vocabulary of the map.
+    pub fn vocabulary(&self) -> &Vocabulary<TAG> {
+        &self.vocabulary
+    }
+    /// Returns the number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        (0..self.shards.len()).map(|i| self.read(i).len()).sum()
+    }
+    /// Returns whether the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        (0..self.shards.len()).all(|i| self.read(i).is_empty())
+    }
+    /// Removes all entries from the map.
+    pub fn clear(&self) {
+        for i in 0..self.shards.len() {
+            self.write(i).clear();
+        }
+    }
+    /// Inserts an entry with the given tags.
+    ///
+    /// If the key was already present, its old tags are replaced and returned.
+    pub fn insert(&self, key: T, tags: Vec<TAG>) -> Option<Vec<TAG>> {
+        let shard = self.shard(&key);
+        self.write(shard).insert(key, tags)
+    }
+    /// Removes an entry, returning its tags if it was present.
+    pub fn remove<Q: ?Sized + Ord + Hash>(&self, key: &Q) -> Option<Vec<TAG>>
+        where T: Borrow<Q>
+    {
+        self.write(self.shard(key)).remove(key)
+    }
+    /// Adds a tag to an entry, inserting the entry if it's not present.
+    ///
+    /// Returns false if the entry already had the tag.
+    pub fn add_tag(&self, key: T, tag: TAG) -> bool {
+        let shard = self.shard(&key);
+        self.write(shard).add_tag(key, tag)
+    }
+    /// Removes a tag from an entry.
+    ///
+    /// Returns false if the entry is not present or didn't have the tag.
+    pub fn remove_tag<Q: ?Sized + Ord + Hash>(&self, key: &Q, tag: &TAG) -> bool
+        where T: Borrow<Q>
+    {
+        self.write(self.shard(key)).remove_tag(key, tag)
+    }
+    /// Returns a copy of the tags of an entry, if it is present.
+    pub fn get_tags<Q: ?Sized + Ord + Hash>(&self, key: &Q) -> Option<Vec<TAG>>
+        where T: Borrow<Q>,
+              TAG: Clone
+    {
+        self.read(self.shard(key)).get_tags(key).map(|tags| tags.to_vec())
+    }
+    /// Returns whether the map contains an entry for the given key.
+    pub fn contains_key<Q: ?Sized + Ord + Hash>(&self, key: &Q) -> bool
+        where T: Borrow<Q>
+    {
+        self.read(self.shard(key)).contains_key(key)
+    }
+    /// Returns whether an entry has the given tag.
+    pub fn has_tag<Q: ?Sized + Ord + Hash>(&self, key: &Q, tag: &TAG) -> bool
+        where T: Borrow<Q>
+    {
+        self.read(self.shard(key)).has_tag(key, tag)
+    }
+    /// Returns the keys of the entries matching the given rule, in order.
+    pub fn matching<R: ?Sized + Matcher<TAG>>(&self, rule: &R) -> Vec<T>
+        where T: Clone
+    {
+        let mut keys = Vec::new();
+        for i in 0..self.shards.len() {
+            keys.extend(self.read(i).matching(rule).cloned());
+        }
+        keys.sort_unstable();
+        keys
+    }
+    /// Returns the number of entries matching the given rule.
+    pub fn count_matching<R: ?Sized + Matcher<TAG>>(&self, rule: &R) -> usize {
+        (0..self.shards.len()).map(|i| self.read(i).count_matching(rule)).sum()
+    }
+    /// Returns whether any entry matches the given rule.
+    pub fn any_matching<R: ?Sized + Matcher<TAG>>(&self, rule: &R) -> bool {
+        (0..self.shards.len()).any(|i| self.read(i).any_matching(rule))
+    }
+    /// Returns the entries and the vocabulary in a single map.
+    pub fn into_inner(self) -> TagMap<T, TAG> {
+        let mut map = TagMap::new();
+        map.vocabulary = self.vocabulary;
+        for shard in self.shards {
+            let mut shard = shard.into_inner().unwrap_or_else(PoisonError::into_inner);
+            map.entries.append(&mut shard.entries);
+        }
+        map
+    }
+    /// Returns the shard of the given key.
+    fn shard<Q: ?Sized + Hash>(&self, key: &Q) -> usize {
+        (self.hash_builder.hash_one(key) % self.shards.len() as u64) as usize
+    }
+    /// Locks a shard for reading.
+    ///
+    /// Entries are only changed by single calls on `TagMap`, so a shard stays valid
+    /// even if a thread panicked while holding its lock.
+    fn read(&self, shard: usize) -> RwLockReadGuard<'_, TagMap<T, TAG>> {
+        self.shards[shard].read().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Locks a shard for writing.
+    fn write(&self, shard: usize) -> RwLockWriteGuard<'_, TagMap<T, TAG>> {
+        self.shards[shard].write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T: Ord + Hash, TAG: Eq> Default for ConcurrentTagMap<T, TAG> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord + Hash, TAG: Eq> From<TagMap<T, TAG>> for ConcurrentTagMap<T, TAG> {
+    fn from(map: TagMap<T, TAG>) -> Self {
+        let mut concurrent = Self::new();
+        concurrent.vocabulary = map.vocabulary;
+        for (key, tags) in map.entries {
+            let shard = concurrent.shard(&key);
+            concurrent.write(shard).entries.insert(key, tags);
+        }
+        concurrent
+    }
+}
+
+#[test]
+fn test_concurrent() {
+    use std::sync::Arc;
+    use std::thread;
+    use MatchRule::*;
+    let map = Arc::new(ConcurrentTagMap::with_shards(4));
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let map = map.clone();
+            thread::spawn(move || {
+                for i in 0..100 {
+                    map.insert(t * 100 + i, vec![if i % 2 == 0 { "even" } else { "odd" }]);
+                    map.count_matching(&Tags(vec!["even"]));
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert_eq!(map.len(), 400);
+    let even = map.matching(&Tags(vec!["even"]));
+    assert_eq!(even.len(), 200);
+    assert!(even.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(map.add_tag(1, "prime"));
+    assert_eq!(map.get_tags(&1), Some(vec!["odd", "prime"]));
+    assert!(map.remove_tag(&1, &"odd"));
+    assert_eq!(map.remove(&1).unwrap().len(), 1);
+    assert!(!map.contains_key(&1));
+    let map = Arc::try_unwrap(map).unwrap().into_inner();
+    assert_eq!(map.len(), 399);
+    assert_eq!(map.keys().next(), Some(&0));
+    let mut map = TagMap::new();
+    map.insert(1, vec!["car"]);
+    map.add_alias_group(vec!["car", "auto"]);
+    let concurrent = ConcurrentTagMap::from(map);
+    let auto = Tags(vec!["auto"]);
+    assert_eq!(concurrent.matching(&concurrent.vocabulary().resolve(&auto)), [1]);
+    let map = concurrent.into_inner();
+    assert_eq!(map.matching(&map.resolve(&auto)).collect::<Vec<_>>(), [&1]);
+}
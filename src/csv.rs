@@ -0,0 +1,159 @@
+//! Reading and writing the entries of a map as CSV.
+
+use csv_crate::{self, ReaderBuilder, Trim, WriterBuilder};
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+use TagMap;
+
+/// The layout of CSV data, with one entry per record.
+///
+/// Each record has two fields: the key, and the tags separated by `tag_delimiter`.
+/// For example, with the default format, `a.png,cat;cute`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvFormat {
+    /// The byte separating the fields.
+    pub delimiter: u8,
+    /// The character separating the tags.
+    pub tag_delimiter: char,
+    /// Whether the first record is a header, skipped when reading, and written as
+    /// `key,tags`.
+    pub has_headers: bool,
+}
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        CsvFormat {
+            delimiter: b',',
+            tag_delimiter: ';',
+            has_headers: false,
+        }
+    }
+}
+
+/// An error from reading or writing CSV data.
+#[derive(Debug)]
+pub enum CsvError {
+    /// The data couldn't be read or written, or isn't valid CSV.
+    Csv(csv_crate::Error),
+    /// A record isn't a valid entry.
+    Invalid {
+        /// The line of the record, starting from 1.
+        line: u64,
+        /// A description of the problem.
+        message: String,
+    },
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CsvError::Csv(ref err) => err.fmt(f),
+            CsvError::Invalid { line, ref message } => write!(f, "{} on line {}", message, line),
+        }
+    }
+}
+
+impl Error for CsvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            CsvError::Csv(ref err) => Some(err),
+            CsvError::Invalid { .. } => None,
+        }
+    }
+}
+
+impl From<csv_crate::Error> for CsvError {
+    fn from(err: csv_crate::Error) -> Self {
+        CsvError::Csv(err)
+    }
+}
+
+impl<T, TAG> TagMap<T, TAG>
+    where T: Ord + FromStr + fmt::Display,
+          T::Err: fmt::Display,
+          TAG: Eq + FromStr + fmt::Display,
+          TAG::Err: fmt::Display
+{
+    /// Reads entries from CSV data in the given format.
+    ///
+    /// Whitespace around fields and tags is ignored, and the tags field may be left
+    /// out. If a key is repeated, its last tags are kept.
+    pub fn from_csv_reader<R: Read>(reader: R, format: &CsvFormat) -> Result<Self, CsvError> {
+        let mut reader = ReaderBuilder::new()
+            .delimiter(format.delimiter)
+            .has_headers(format.has_headers)
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(reader);
+        let mut map = TagMap::new();
+        for record in reader.records() {
+            let record = record?;
+            let line = record.position().map_or(0, |pos| pos.line());
+            let invalid = |message: String| CsvError::Invalid { line, message };
+            if record.len() > 2 {
+                return Err(invalid(format!("expected 2 fields, found {}", record.len())));
+            }
+            let key = record[0].parse().map_err(|err| invalid(format!("invalid key: {}", err)))?;
+            let mut tags = Vec::new();
+            for tag in record.get(1).unwrap_or("").split(format.tag_delimiter) {
+                let tag = tag.trim();
+                if !tag.is_empty() {
+                    tags.push(tag.parse().map_err(|err| invalid(format!("invalid tag: {}", err)))?);
+                }
+            }
+            map.insert(key, tags);
+        }
+        Ok(map)
+    }
+    /// Writes the entries as CSV data in the given format.
+    ///
+    /// Fails if a tag contains the tag delimiter.
+    pub fn to_csv_writer<W: Write>(&self, writer: W, format: &CsvFormat) -> Result<(), CsvError> {
+        let mut writer = WriterBuilder::new().delimiter(format.delimiter).from_writer(writer);
+        if format.has_headers {
+            writer.write_record(["key", "tags"])?;
+        }
+        let delimiter = format.tag_delimiter.to_string();
+        for (i, (key, tags)) in self.iter().enumerate() {
+            let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+            if let Some(tag) = tags.iter().find(|tag| tag.contains(format.tag_delimiter)) {
+                let line = i as u64 + 1 + format.has_headers as u64;
+                let message = format!("tag {:?} contains the tag delimiter", tag);
+                return Err(CsvError::Invalid { line, message });
+            }
+            writer.write_record([key.to_string(), tags.join(&delimiter)])?;
+        }
+        writer.flush().map_err(csv_crate::Error::from)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_csv() {
+    let data = "a.png, cat;cute\n\"b,c.png\",dog\nd.png\ne.png,;bird;;\n";
+    let format = CsvFormat::default();
+    let map: TagMap<String, String> = TagMap::from_csv_reader(data.as_bytes(), &format).unwrap();
+    assert_eq!(map.get_tags("a.png").unwrap(), ["cat", "cute"]);
+    assert_eq!(map.get_tags("b,c.png").unwrap(), ["dog"]);
+    assert_eq!(map.tag_count("d.png"), Some(0));
+    assert_eq!(map.get_tags("e.png").unwrap(), ["bird"]);
+    let format = CsvFormat { delimiter: b'\t', tag_delimiter: '|', has_headers: true };
+    let mut out = Vec::new();
+    map.to_csv_writer(&mut out, &format).unwrap();
+    assert_eq!(String::from_utf8(out.clone()).unwrap(),
+               "key\ttags\na.png\tcat|cute\nb,c.png\tdog\nd.png\t\ne.png\tbird\n");
+    let loaded: TagMap<String, String> = TagMap::from_csv_reader(&out[..], &format).unwrap();
+    assert_eq!(loaded, map);
+    let err = TagMap::<u32, String>::from_csv_reader(&b"1,a\nx,b\n"[..], &CsvFormat::default())
+        .unwrap_err();
+    assert_eq!(err.to_string(), "invalid key: invalid digit found in string on line 2");
+    let err = TagMap::<u32, String>::from_csv_reader(&b"1,a,b\n"[..], &CsvFormat::default())
+        .unwrap_err();
+    assert_eq!(err.to_string(), "expected 2 fields, found 3 on line 1");
+    let mut bad = TagMap::new();
+    bad.insert(1u32, vec!["a;b".to_string()]);
+    let err = bad.to_csv_writer(Vec::new(), &CsvFormat::default()).unwrap_err();
+    assert_eq!(err.to_string(), "tag \"a;b\" contains the tag delimiter on line 1");
+}
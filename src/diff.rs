@@ -0,0 +1,120 @@
+//! Differences between two maps.
+
+use combine::join;
+use TagMap;
+
+/// The changes turning one map into another, created by `TagMap::diff`.
+///
+/// Tags are compared as sets: reordering or repeating the tags of an entry isn't a
+/// change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagMapDiff<T, TAG> {
+    added: Vec<(T, Vec<TAG>)>,
+    removed: Vec<(T, Vec<TAG>)>,
+    changed: Vec<EntryChange<T, TAG>>,
+}
+
+/// The tags added to and removed from an entry present in both maps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryChange<T, TAG> {
+    /// The key of the entry.
+    pub key: T,
+    /// The tags the entry gained.
+    pub added: Vec<TAG>,
+    /// The tags the entry lost.
+    pub removed: Vec<TAG>,
+}
+
+impl<T, TAG> TagMapDiff<T, TAG> {
+    /// Returns whether the maps have the same entries.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+    /// Returns the entries only present in the new map, in order.
+    pub fn added(&self) -> impl Iterator<Item = (&T, &[TAG])> {
+        self.added.iter().map(|(key, tags)| (key, &tags[..]))
+    }
+    /// Returns the entries only present in the old map, in order.
+    pub fn removed(&self) -> impl Iterator<Item = (&T, &[TAG])> {
+        self.removed.iter().map(|(key, tags)| (key, &tags[..]))
+    }
+    /// Returns the changes to the entries present in both maps with different tags,
+    /// in order.
+    pub fn changed(&self) -> impl Iterator<Item = &EntryChange<T, TAG>> {
+        self.changed.iter()
+    }
+}
+
+impl<T: Ord, TAG: Eq> TagMapDiff<T, TAG> {
+    /// Applies the changes to a map, turning the old map into the new one.
+    ///
+    /// Changes to entries that are missing from `map` insert them.
+    pub fn apply(self, map: &mut TagMap<T, TAG>) {
+        for (key, _) in self.removed {
+            map.remove(&key);
+        }
+        for (key, tags) in self.added {
+            map.insert(key, tags);
+        }
+        for change in self.changed {
+            for tag in &change.removed {
+                map.remove_tag(&change.key, tag);
+            }
+            map.add_tags(change.key, change.added);
+        }
+    }
+}
+
+impl<T: Ord + Clone, TAG: Eq + Clone> TagMap<T, TAG> {
+    /// Returns the changes turning this map into `other`.
+    pub fn diff(&self, other: &TagMap<T, TAG>) -> TagMapDiff<T, TAG> {
+        let mut diff = TagMapDiff {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+        let missing = |tags: &[TAG], from: &[TAG]| -> Vec<TAG> {
+            let mut missing = Vec::new();
+            for tag in tags {
+                if !from.contains(tag) && !missing.contains(tag) {
+                    missing.push(tag.clone());
+                }
+            }
+            missing
+        };
+        join(self, other, |key, old, new| {
+            match (old, new) {
+                (Some(old), Some(new)) => {
+                    let (added, removed) = (missing(new, old), missing(old, new));
+                    if !added.is_empty() || !removed.is_empty() {
+                        diff.changed.push(EntryChange { key: key.clone(), added, removed });
+                    }
+                }
+                (Some(old), None) => diff.removed.push((key.clone(), old.to_vec())),
+                (None, Some(new)) => diff.added.push((key.clone(), new.to_vec())),
+                (None, None) => {}
+            }
+        });
+        diff
+    }
+}
+
+#[test]
+fn test_diff() {
+    let old: TagMap<_, _> = vec![(1, vec!["a", "b"]), (2, vec!["c"]), (3, vec!["d", "e"])]
+        .into_iter()
+        .collect();
+    let new: TagMap<_, _> = vec![(2, vec!["c", "c"]), (3, vec!["e", "f", "f"]), (4, vec!["g"])]
+        .into_iter()
+        .collect();
+    let diff = old.diff(&new);
+    assert_eq!(diff.added().collect::<Vec<_>>(), [(&4, &["g"][..])]);
+    assert_eq!(diff.removed().collect::<Vec<_>>(), [(&1, &["a", "b"][..])]);
+    assert_eq!(diff.changed().collect::<Vec<_>>(),
+               [&EntryChange { key: 3, added: vec!["f"], removed: vec!["d"] }]);
+    assert!(new.diff(&new).is_empty());
+    let mut synced = old.clone();
+    diff.apply(&mut synced);
+    assert!(synced.diff(&new).is_empty());
+    assert_eq!(synced.get_tags(&3), Some(&["e", "f"][..]));
+}
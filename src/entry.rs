@@ -0,0 +1,113 @@
+//! The entry API of `TagMap`.
+
+use std::collections::BTreeMap;
+use storage::{OccupiedStorage, Storage, TagList, VacantStorage};
+
+/// A view into a single entry of a `TagMap`, which may be vacant or occupied.
+///
+/// Constructed by `TagMap::entry`.
+pub enum Entry<'a, T: 'a, TAG: 'a, B = BTreeMap<T, Vec<TAG>>>
+    where B: 'a + Storage<T, TAG>
+{
+    /// A vacant entry.
+    Vacant(VacantEntry<'a, T, TAG, B>),
+    /// An occupied entry.
+    Occupied(OccupiedEntry<'a, T, TAG, B>),
+}
+
+/// A view into a vacant entry of a `TagMap`.
+pub struct VacantEntry<'a, T: 'a, TAG: 'a, B = BTreeMap<T, Vec<TAG>>>
+    where B: 'a + Storage<T, TAG>
+{
+    inner: B::Vacant<'a>,
+}
+
+/// A view into an occupied entry of a `TagMap`.
+pub struct OccupiedEntry<'a, T: 'a, TAG: 'a, B = BTreeMap<T, Vec<TAG>>>
+    where B: 'a + Storage<T, TAG>
+{
+    inner: B::Occupied<'a>,
+}
+
+impl<'a, T, TAG, B: Storage<T, TAG>> Entry<'a, T, TAG, B> {
+    /// Returns the key of this entry.
+    pub fn key(&self) -> &T {
+        match *self {
+            Entry::Vacant(ref e) => e.key(),
+            Entry::Occupied(ref e) => e.key(),
+        }
+    }
+    /// Inserts the given tags if the entry is vacant, and returns the entry's tags.
+    pub fn or_insert_tags(self, tags: Vec<TAG>) -> &'a mut B::List {
+        match self {
+            Entry::Vacant(e) => e.insert(tags),
+            Entry::Occupied(e) => e.into_tags_mut(),
+        }
+    }
+    /// Inserts an empty tag list if the entry is vacant, and returns the entry's tags.
+    pub fn or_default(self) -> &'a mut B::List {
+        self.or_insert_tags(Vec::new())
+    }
+    /// Calls `f` on the entry's tags if the entry is occupied.
+    pub fn and_modify_tags<F: FnOnce(&mut B::List)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut e) => {
+                f(e.tags_mut());
+                Entry::Occupied(e)
+            }
+            Entry::Vacant(e) => Entry::Vacant(e),
+        }
+    }
+}
+
+impl<'a, T, TAG, B: Storage<T, TAG>> VacantEntry<'a, T, TAG, B> {
+    pub(crate) fn new(inner: B::Vacant<'a>) -> Self {
+        VacantEntry { inner }
+    }
+    /// Returns the key of this entry.
+    pub fn key(&self) -> &T {
+        self.inner.key()
+    }
+    /// Takes ownership of the key.
+    pub fn into_key(self) -> T {
+        self.inner.into_key()
+    }
+    /// Inserts the entry with the given tags, and returns them.
+    pub fn insert(self, tags: Vec<TAG>) -> &'a mut B::List {
+        self.inner.insert(B::List::from_vec(tags))
+    }
+}
+
+impl<'a, T, TAG, B: Storage<T, TAG>> OccupiedEntry<'a, T, TAG, B> {
+    pub(crate) fn new(inner: B::Occupied<'a>) -> Self {
+        OccupiedEntry { inner }
+    }
+    /// Returns the key of this entry.
+    pub fn key(&self) -> &T {
+        self.inner.key()
+    }
+    /// Returns the tags of this entry.
+    pub fn tags(&self) -> &[TAG] {
+        self.inner.get()
+    }
+    /// Returns the tags of this entry mutably.
+    pub fn tags_mut(&mut self) -> &mut B::List {
+        self.inner.get_mut()
+    }
+    /// Converts the entry into a mutable reference to its tags.
+    pub fn into_tags_mut(self) -> &'a mut B::List {
+        self.inner.into_mut()
+    }
+    /// Replaces the tags of this entry, returning the old ones.
+    pub fn insert(&mut self, tags: Vec<TAG>) -> B::List {
+        self.inner.insert(B::List::from_vec(tags))
+    }
+    /// Removes the entry, returning its tags.
+    pub fn remove(self) -> B::List {
+        self.inner.remove_entry().1
+    }
+    /// Removes the entry, returning its key and tags.
+    pub fn remove_entry(self) -> (T, B::List) {
+        self.inner.remove_entry()
+    }
+}
@@ -0,0 +1,183 @@
+//! A tag map whose tags can expire.
+
+use std::borrow::{Borrow, Cow};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use {Matcher, TagMap};
+
+/// A `TagMap` where tags can be added with a time to live.
+///
+/// Expired tags are treated as absent, but are only removed from the map by
+/// `purge_expired`. Methods taking the current time as `now` have counterparts
+/// using `Instant::now()`.
+#[derive(Debug, Clone)]
+pub struct ExpiringTagMap<T: Ord, TAG: Eq> {
+    map: TagMap<T, TAG>,
+    deadlines: BTreeMap<T, Vec<(TAG, Instant)>>,
+}
+
+impl<T: Ord, TAG: Eq> Default for ExpiringTagMap<T, TAG> {
+    fn default() -> Self {
+        ExpiringTagMap {
+            map: TagMap::new(),
+            deadlines: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone, TAG: Eq + Clone> ExpiringTagMap<T, TAG> {
+    /// Creates a new empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Returns the underlying map, including expired tags that weren't purged yet.
+    pub fn inner(&self) -> &TagMap<T, TAG> {
+        &self.map
+    }
+    /// Inserts an entry with the given tags, which don't expire.
+    ///
+    /// If the key was already present, its old tags are replaced and returned.
+    pub fn insert(&mut self, key: T, tags: Vec<TAG>) -> Option<Vec<TAG>> {
+        self.deadlines.remove(&key);
+        self.map.insert(key, tags)
+    }
+    /// Removes an entry, returning its tags if it was present.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<Vec<TAG>>
+        where T: Borrow<Q>
+    {
+        self.deadlines.remove(key);
+        self.map.remove(key)
+    }
+    /// Removes duplicate tags within entries, and frees unused capacity.
+    pub fn compact(&mut self) {
+        self.map.compact();
+        for deadlines in self.deadlines.values_mut() {
+            deadlines.shrink_to_fit();
+        }
+    }
+    /// Adds a tag that doesn't expire to an entry, inserting the entry if it's not present.
+    ///
+    /// If the entry had the tag with a time to live, it no longer expires.
+    pub fn add_tag(&mut self, key: T, tag: TAG) {
+        if let Some(deadlines) = self.deadlines.get_mut(&key) {
+            deadlines.retain(|(t, _)| *t != tag);
+        }
+        self.map.add_tag(key, tag);
+    }
+    /// Adds a tag that expires after `ttl`, inserting the entry if it's not present.
+    ///
+    /// If the entry already had the tag, its expiry time is replaced.
+    pub fn add_tag_with_ttl(&mut self, key: T, tag: TAG, ttl: Duration) {
+        self.add_tag_with_ttl_at(key, tag, ttl, Instant::now());
+    }
+    /// Adds a tag that expires after `ttl`, counting from `now`.
+    ///
+    /// If the expiry time can't be represented, as with `Duration::MAX`, the tag
+    /// doesn't expire.
+    pub fn add_tag_with_ttl_at(&mut self, key: T, tag: TAG, ttl: Duration, now: Instant) {
+        let deadline = match now.checked_add(ttl) {
+            Some(deadline) => deadline,
+            None => return self.add_tag(key, tag),
+        };
+        let deadlines = self.deadlines.entry(key.clone()).or_default();
+        deadlines.retain(|(t, _)| *t != tag);
+        deadlines.push((tag.clone(), deadline));
+        self.map.add_tag(key, tag);
+    }
+    /// Returns the unexpired tags of an entry, if it is present.
+    pub fn get_tags_at<Q: ?Sized + Ord>(&self, key: &Q, now: Instant) -> Option<Cow<'_, [TAG]>>
+        where T: Borrow<Q>
+    {
+        let tags = self.map.get_tags(key)?;
+        Some(live_tags(tags, self.deadlines.get(key), now))
+    }
+    /// Returns the unexpired tags of an entry, if it is present.
+    pub fn get_tags<Q: ?Sized + Ord>(&self, key: &Q) -> Option<Cow<'_, [TAG]>>
+        where T: Borrow<Q>
+    {
+        self.get_tags_at(key, Instant::now())
+    }
+    /// Returns the keys of the entries whose unexpired tags match the given rule.
+    pub fn matching_at<'a, R>(&'a self,
+                              rule: &'a R,
+                              now: Instant)
+                              -> impl Iterator<Item = &'a T> + 'a
+        where R: ?Sized + Matcher<TAG>
+    {
+        self.map.iter().filter_map(move |(key, tags)| {
+            if rule.matches(&live_tags(tags, self.deadlines.get(key), now)) {
+                Some(key)
+            } else {
+                None
+            }
+        })
+    }
+    /// Returns the keys of the entries whose unexpired tags match the given rule.
+    pub fn matching<'a, R>(&'a self, rule: &'a R) -> impl Iterator<Item = &'a T> + 'a
+        where R: ?Sized + Matcher<TAG>
+    {
+        self.matching_at(rule, Instant::now())
+    }
+    /// Removes the tags that expired by `now`, returning how many were removed.
+    pub fn purge_expired_at(&mut self, now: Instant) -> usize {
+        let mut count = 0;
+        let map = &mut self.map;
+        self.deadlines.retain(|key, deadlines| {
+            deadlines.retain(|(tag, deadline)| {
+                if *deadline > now {
+                    return true;
+                }
+                if map.remove_tag(key, tag) {
+                    count += 1;
+                }
+                false
+            });
+            !deadlines.is_empty()
+        });
+        count
+    }
+    /// Removes the tags that have expired, returning how many were removed.
+    pub fn purge_expired(&mut self) -> usize {
+        self.purge_expired_at(Instant::now())
+    }
+}
+
+fn live_tags<'a, TAG: Eq + Clone>(tags: &'a [TAG],
+                                  deadlines: Option<&Vec<(TAG, Instant)>>,
+                                  now: Instant)
+                                  -> Cow<'a, [TAG]> {
+    let expired = |tag: &TAG| {
+        deadlines.is_some_and(|deadlines| deadlines.iter().any(|(t, d)| t == tag && *d <= now))
+    };
+    if tags.iter().any(&expired) {
+        Cow::Owned(tags.iter().filter(|tag| !expired(tag)).cloned().collect())
+    } else {
+        Cow::Borrowed(tags)
+    }
+}
+
+#[test]
+fn test_expiring() {
+    use MatchRule;
+    let now = Instant::now();
+    let minute = Duration::from_secs(60);
+    let mut map = ExpiringTagMap::new();
+    map.insert("eu-1", vec!["up"]);
+    map.insert("us-1", vec!["up"]);
+    map.add_tag_with_ttl_at("eu-1", "degraded", minute, now);
+    map.add_tag_with_ttl_at("us-1", "degraded", 2 * minute, now);
+    let healthy = MatchRule::NotTags(vec!["degraded"]);
+    assert_eq!(map.matching_at(&healthy, now).count(), 0);
+    let later = now + Duration::from_secs(90);
+    assert_eq!(map.matching_at(&healthy, later).cloned().collect::<Vec<_>>(), ["eu-1"]);
+    assert_eq!(map.get_tags_at("eu-1", later).unwrap(), &["up"][..]);
+    map.add_tag("us-1", "degraded");
+    assert_eq!(map.purge_expired_at(later), 1);
+    assert_eq!(map.inner().get_tags("eu-1"), Some(&["up"][..]));
+    assert_eq!(map.purge_expired_at(now + 10 * minute), 0);
+    assert_eq!(map.matching_at(&healthy, now + 10 * minute).count(), 1);
+    map.add_tag_with_ttl_at("eu-1", "degraded", minute, now);
+    map.add_tag_with_ttl_at("eu-1", "degraded", Duration::MAX, now);
+    assert_eq!(map.purge_expired_at(now + 10 * minute), 0);
+    assert_eq!(map.matching_at(&healthy, now + 10 * minute).count(), 0);
+}
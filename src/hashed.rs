@@ -0,0 +1,211 @@
+//! A tag map backed by a `HashMap`.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::collections::hash_map::{self, RandomState};
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use storage::{EntryFilter, Lookup, OccupiedStorage, Storage, TagList, VacantStorage};
+use {Entry, OccupiedEntry, TagMap, VacantEntry, Vocabulary};
+
+/// A `TagMap` storing its entries in a `HashMap`.
+///
+/// Keys only need to implement `Hash` and `Eq`, and lookups by key are faster than
+/// with the default `BTreeMap` storage, but entries are visited in arbitrary order.
+/// The methods relying on key order, like `matching_in_range`, aren't available.
+/// Neither are merging, diffing, `into_shared`, parallel matching, serialization, and
+/// the binary, JSON, CSV and SQLite formats, which are only implemented for the
+/// default storage. Collecting into the map reserves room for the lower bound of the
+/// iterator's size hint up front.
+pub type HashTagMap<T, TAG, S = RandomState> = TagMap<T, TAG, HashMap<T, Vec<TAG>, S>>;
+
+impl<T: Hash + Eq, TAG: Eq, L, S: BuildHasher> TagMap<T, TAG, HashMap<T, L, S>> {
+    /// Creates a new empty map using the given hasher builder.
+    pub fn with_hasher(hasher: S) -> Self {
+        TagMap {
+            entries: HashMap::with_hasher(hasher),
+            vocabulary: Vocabulary::new(),
+            key: PhantomData,
+        }
+    }
+    /// Creates a new empty map with room for at least `capacity` entries, using the
+    /// given hasher builder.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        TagMap {
+            entries: HashMap::with_capacity_and_hasher(capacity, hasher),
+            vocabulary: Vocabulary::new(),
+            key: PhantomData,
+        }
+    }
+    /// Returns the number of entries the map can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+    /// Reserves room for at least `additional` more entries.
+    ///
+    /// Useful before large imports, so the map isn't rehashed repeatedly.
+    pub fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+    }
+}
+
+impl<T: Hash + Eq, TAG: Eq, L, S: BuildHasher + Default> TagMap<T, TAG, HashMap<T, L, S>> {
+    /// Creates a new empty map with room for at least `capacity` entries.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, S::default())
+    }
+}
+
+impl<T: Hash + Eq, TAG, L: TagList<TAG>, S: BuildHasher> Storage<T, TAG> for HashMap<T, L, S> {
+    type List = L;
+    type Iter<'a> = hash_map::Iter<'a, T, L> where S: 'a, T: 'a, L: 'a;
+    type IterMut<'a> = hash_map::IterMut<'a, T, L> where S: 'a, T: 'a, L: 'a;
+    type IntoIter = hash_map::IntoIter<T, L>;
+    type ExtractIf<'a> = hash_map::ExtractIf<'a, T, L, EntryFilter<'a, T, L>>
+        where S: 'a,
+              T: 'a,
+              L: 'a;
+    type Vacant<'a> = hash_map::VacantEntry<'a, T, L> where S: 'a, T: 'a, L: 'a;
+    type Occupied<'a> = hash_map::OccupiedEntry<'a, T, L>
+        where S: 'a,
+              T: 'a,
+              L: 'a;
+    fn len(&self) -> usize {
+        self.len()
+    }
+    fn is_empty(&self) -> bool {
+        self.is_empty()
+    }
+    fn clear(&mut self) {
+        self.clear();
+    }
+    fn iter(&self) -> Self::Iter<'_> {
+        self.iter()
+    }
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        self.iter_mut()
+    }
+    fn into_entries(self) -> Self::IntoIter {
+        IntoIterator::into_iter(self)
+    }
+    fn insert(&mut self, key: T, tags: L) -> Option<L> {
+        self.insert(key, tags)
+    }
+    fn entry(&mut self, key: T) -> Entry<'_, T, TAG, Self> {
+        match self.entry(key) {
+            hash_map::Entry::Vacant(inner) => Entry::Vacant(VacantEntry::new(inner)),
+            hash_map::Entry::Occupied(inner) => Entry::Occupied(OccupiedEntry::new(inner)),
+        }
+    }
+    fn retain<F: FnMut(&T, &mut L) -> bool>(&mut self, f: F) {
+        self.retain(f);
+    }
+    fn extract_if<'a>(&'a mut self, filter: EntryFilter<'a, T, L>) -> Self::ExtractIf<'a> {
+        self.extract_if(filter)
+    }
+    fn shrink_to_fit(&mut self) {
+        self.shrink_to_fit();
+    }
+}
+
+impl<T, Q: ?Sized, TAG, L, S> Lookup<Q, T, TAG> for HashMap<T, L, S>
+    where T: Hash + Eq + Borrow<Q>,
+          L: TagList<TAG>,
+          Q: Hash + Eq,
+          S: BuildHasher
+{
+    fn get(&self, key: &Q) -> Option<&L> {
+        self.get(key)
+    }
+    fn get_mut(&mut self, key: &Q) -> Option<&mut L> {
+        self.get_mut(key)
+    }
+    fn remove(&mut self, key: &Q) -> Option<L> {
+        self.remove(key)
+    }
+}
+
+impl<'a, T, L> VacantStorage<'a, T, L> for hash_map::VacantEntry<'a, T, L> {
+    fn key(&self) -> &T {
+        self.key()
+    }
+    fn into_key(self) -> T {
+        self.into_key()
+    }
+    fn insert(self, tags: L) -> &'a mut L {
+        self.insert(tags)
+    }
+}
+
+impl<'a, T, L> OccupiedStorage<'a, T, L> for hash_map::OccupiedEntry<'a, T, L> {
+    fn key(&self) -> &T {
+        self.key()
+    }
+    fn get(&self) -> &L {
+        self.get()
+    }
+    fn get_mut(&mut self) -> &mut L {
+        self.get_mut()
+    }
+    fn into_mut(self) -> &'a mut L {
+        self.into_mut()
+    }
+    fn insert(&mut self, tags: L) -> L {
+        self.insert(tags)
+    }
+    fn remove_entry(self) -> (T, L) {
+        self.remove_entry()
+    }
+}
+
+#[test]
+fn test_hash_tag_map() {
+    use MatchRule::*;
+    // Keys without `Ord`
+    #[derive(Debug, PartialEq, Eq, Hash)]
+    struct Id(u32);
+    let mut map: HashTagMap<_, _> = HashTagMap::default();
+    map.insert(Id(1), vec!["red", "round"]);
+    map.insert(Id(2), vec!["green"]);
+    assert!(map.add_tag(Id(2), "round"));
+    assert!(map.remove_tag(&Id(1), &"red"));
+    assert!(map.has_tag(&Id(2), &"green"));
+    assert_eq!(map.get_tags(&Id(1)), Some(&["round"][..]));
+    let mut round: Vec<_> = map.matching(&Tags(vec!["round"])).map(|id| id.0).collect();
+    round.sort();
+    assert_eq!(round, [1, 2]);
+    map.retain_matching(&NotTags(vec!["green"]));
+    assert_eq!(map.len(), 1);
+    assert_eq!(map.remove(&Id(1)).as_deref(), Some(&["round"][..]));
+    assert!(map.is_empty());
+    let mut map: HashTagMap<u32, &str> = HashTagMap::with_capacity(10);
+    assert!(map.capacity() >= 10);
+    map.reserve(100);
+    assert!(map.capacity() >= 100);
+    let mut map: HashTagMap<_, _> = (0..50).map(|i| (i, vec![i % 3])).collect();
+    assert!(map.capacity() >= 50);
+    map.extend(vec![(50, vec![2]), (51, vec![0])]);
+    map.entry(1).or_default().push(2);
+    assert_eq!(map.get_tags(&1), Some(&[1, 2][..]));
+    let both = Tags(vec![1, 2]);
+    let entries = map.matching_entries(&both).map(|(&k, tags)| (k, tags.to_vec()));
+    assert_eq!(entries.collect::<Vec<_>>(), [(1, vec![1, 2])]);
+    let removed = map.remove_matching(&Tags(vec![0]));
+    let mut removed: Vec<_> = removed.into_iter().map(|(k, _)| k).collect();
+    removed.sort();
+    assert_eq!(removed, (0..52).step_by(3).collect::<Vec<_>>());
+    assert_eq!(map.len(), 34);
+    let mut keys: Vec<_> = map.into_iter().map(|(k, _)| k).collect();
+    keys.sort();
+    assert_eq!(keys[..3], [1, 2, 4]);
+    let mut map: HashTagMap<_, _> = HashTagMap::default();
+    map.insert("a", vec!["lang:rust", "cli"]);
+    map.insert("b", vec!["lang:rust", "cli", "tui"]);
+    map.insert("c", vec!["lang:go"]);
+    assert_eq!(map.tags().len(), 4);
+    assert_eq!(map.tag_counts()[&"cli"], 2);
+    assert_eq!(map.tags_with_prefix("lang:"), [&"lang:go", &"lang:rust"]);
+    assert_eq!(map.tags_in_namespace("lang").len(), 2);
+    assert_eq!(map.similar_to("a", 0.5), [(&"b", 2.0 / 3.0)]);
+    assert_eq!(map.matching_ranked(&AnyTag(vec!["cli", "tui"])), [(&"b", 2), (&"a", 1)]);
+}
@@ -0,0 +1,90 @@
+//! Hierarchical string tags, like `animal/mammal/feline`.
+//!
+//! A tag is under a path if it's equal to it, or a descendant of it, so both
+//! `animal/mammal` and `animal/mammal/feline` are under `animal/mammal`.
+
+use parse::write_tag;
+use pattern::write_prefix;
+use std::fmt;
+use TagPattern;
+
+/// The separator between the segments of a tag path.
+pub const SEPARATOR: char = '/';
+
+/// Returns the segments of a tag path.
+pub fn split_path(tag: &str) -> ::std::str::Split<'_, char> {
+    tag.split(SEPARATOR)
+}
+
+/// Joins segments into a tag path.
+pub fn join_path<I: IntoIterator>(segments: I) -> String
+    where I::Item: AsRef<str>
+{
+    let mut path = String::new();
+    for (i, segment) in segments.into_iter().enumerate() {
+        if i != 0 {
+            path.push(SEPARATOR);
+        }
+        path.push_str(segment.as_ref());
+    }
+    path
+}
+
+/// Returns the parent path of a tag, if it has one.
+pub fn parent(tag: &str) -> Option<&str> {
+    tag.rfind(SEPARATOR).map(|i| &tag[..i])
+}
+
+/// Returns whether a tag is equal to, or a descendant of a path.
+pub fn is_under(tag: &str, path: &str) -> bool {
+    tag.starts_with(path) &&
+    (tag.len() == path.len() || tag[path.len()..].starts_with(SEPARATOR))
+}
+
+/// Matches tags under a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagUnder {
+    path: String,
+}
+
+impl TagUnder {
+    /// Creates a new pattern.
+    pub fn new(path: &str) -> Self {
+        TagUnder { path: path.to_owned() }
+    }
+    /// Returns the path.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl<TAG: AsRef<str>> TagPattern<TAG> for TagUnder {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        is_under(tag.as_ref(), &self.path)
+    }
+    fn fmt_query(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("(")?;
+        write_tag(&self.path, f)?;
+        f.write_str(" | ")?;
+        write_prefix(&format!("{}{}", self.path, SEPARATOR), f)?;
+        f.write_str(")")
+    }
+}
+
+#[test]
+fn test_hierarchy() {
+    use {MatchRule, TagMap};
+    assert_eq!(split_path("animal/mammal/feline").collect::<Vec<_>>(),
+               ["animal", "mammal", "feline"]);
+    assert_eq!(join_path(&["animal", "mammal"]), "animal/mammal");
+    assert_eq!(parent("animal/mammal/feline"), Some("animal/mammal"));
+    assert_eq!(parent("animal"), None);
+    assert!(!is_under("animal/mammalia", "animal/mammal"));
+    let mut map = TagMap::new();
+    map.insert("cat", vec!["animal/mammal/feline"]);
+    map.insert("whale", vec!["animal/mammal"]);
+    map.insert("shark", vec!["animal/fish"]);
+    let rule = MatchRule::tag_under("animal/mammal");
+    assert_eq!(map.matching(&rule).cloned().collect::<Vec<_>>(), ["cat", "whale"]);
+    assert_eq!(rule.to_string(), "(animal/mammal | animal/mammal/*)");
+}
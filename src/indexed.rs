@@ -0,0 +1,749 @@
+//! A tag map with an inverted index from tags to keys.
+
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::mem::size_of;
+use std::ops::{Bound, Deref};
+use std::vec;
+use stats::{by_frequency, count_tags, refinements_of};
+use {MatchRule, Matcher, MemoryReport, TagMap, rename_in};
+
+/// A `TagMap` that maintains an index from each tag to the keys having it.
+///
+/// `matching` answers the parts of a rule made of `Tags`, `AnyTag` and `NotTags`
+/// rules, and boolean combinations of them, from the index. Only the entries the
+/// index can't rule out are checked against the rest of the rule, instead of
+/// scanning every entry. The map can be read through `Deref`,
+/// but must be modified through the methods of this type, to keep the index in sync.
+///
+/// Internally, each key is assigned a numeric id, and the index stores sets of ids.
+/// With the `roaring` feature, these sets are compressed bitmaps.
+///
+/// By default, the index is updated by every modification. For bulk edits, it can
+/// be deferred with `set_deferred`: modifications then only mark the index as stale,
+/// and queries scan the map until the index is rebuilt with `rebuild_index`.
+#[derive(Debug, Clone)]
+pub struct IndexedTagMap<T: Ord, TAG: Eq> {
+    map: TagMap<T, TAG>,
+    ids: BTreeMap<T, u32>,
+    keys: Vec<Option<T>>,
+    free_ids: Vec<u32>,
+    index: BTreeMap<TAG, IdSet>,
+    deferred: bool,
+    stale: bool,
+}
+
+impl<T: Ord + Clone, TAG: Ord + Clone + Borrow<str>> IndexedTagMap<T, TAG> {
+    /// Returns the distinct tags in use starting with the given prefix, in order.
+    ///
+    /// Unless the index is stale, only the matching range of the index is visited.
+    pub fn tags_with_prefix(&self, prefix: &str) -> Vec<&TAG> {
+        if self.stale {
+            return self.map
+                .tags()
+                .into_iter()
+                .filter(|tag| (*tag).borrow().starts_with(prefix))
+                .collect();
+        }
+        self.index
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(tag, _)| tag)
+            .take_while(|tag| (*tag).borrow().starts_with(prefix))
+            .collect()
+    }
+}
+
+#[cfg(feature = "roaring")]
+type IdSet = ::roaring::RoaringBitmap;
+#[cfg(not(feature = "roaring"))]
+type IdSet = BTreeSet<u32>;
+
+/// Operations on sets of entry ids, implemented by each kind of `IdSet`.
+trait Ids: Default + Clone + fmt::Debug {
+    fn add(&mut self, id: u32);
+    fn discard(&mut self, id: u32);
+    fn count(&self) -> u64;
+    fn intersect(&mut self, other: &Self);
+    fn unite(&mut self, other: &Self);
+    fn subtract(&mut self, other: &Self);
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item = u32> + 'a>;
+    fn heap_size(&self) -> usize;
+}
+
+#[cfg(feature = "roaring")]
+impl Ids for ::roaring::RoaringBitmap {
+    fn add(&mut self, id: u32) {
+        self.insert(id);
+    }
+    fn discard(&mut self, id: u32) {
+        self.remove(id);
+    }
+    fn count(&self) -> u64 {
+        self.len()
+    }
+    fn intersect(&mut self, other: &Self) {
+        *self &= other;
+    }
+    fn unite(&mut self, other: &Self) {
+        *self |= other;
+    }
+    fn subtract(&mut self, other: &Self) {
+        *self -= other;
+    }
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item = u32> + 'a> {
+        Box::new(self.iter())
+    }
+    fn heap_size(&self) -> usize {
+        self.serialized_size()
+    }
+}
+
+#[cfg(not(feature = "roaring"))]
+impl Ids for BTreeSet<u32> {
+    fn add(&mut self, id: u32) {
+        self.insert(id);
+    }
+    fn discard(&mut self, id: u32) {
+        self.remove(&id);
+    }
+    fn count(&self) -> u64 {
+        self.len() as u64
+    }
+    fn intersect(&mut self, other: &Self) {
+        self.retain(|id| other.contains(id));
+    }
+    fn unite(&mut self, other: &Self) {
+        self.extend(other);
+    }
+    fn subtract(&mut self, other: &Self) {
+        self.retain(|id| !other.contains(id));
+    }
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item = u32> + 'a> {
+        Box::new(self.iter().cloned())
+    }
+    fn heap_size(&self) -> usize {
+        self.len() * size_of::<u32>()
+    }
+}
+
+impl<T: Ord + Clone, TAG: Ord + Clone> IndexedTagMap<T, TAG> {
+    /// Creates a new empty map.
+    pub fn new() -> Self {
+        IndexedTagMap {
+            map: TagMap::new(),
+            ids: BTreeMap::new(),
+            keys: Vec::new(),
+            free_ids: Vec::new(),
+            index: BTreeMap::new(),
+            deferred: false,
+            stale: false,
+        }
+    }
+    /// Returns the underlying map, dropping the index.
+    pub fn into_inner(self) -> TagMap<T, TAG> {
+        self.map
+    }
+    /// Removes all entries.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.ids.clear();
+        self.keys.clear();
+        self.free_ids.clear();
+        self.index.clear();
+        self.stale = false;
+    }
+    /// Sets whether updating the index is deferred until `rebuild_index` is called.
+    ///
+    /// Turning deferral off rebuilds the index if it's stale.
+    pub fn set_deferred(&mut self, deferred: bool) {
+        self.deferred = deferred;
+        if !deferred && self.stale {
+            self.rebuild_index();
+        }
+    }
+    /// Returns whether the index is out of date, because updating it was deferred.
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+    /// Removes duplicate tags within entries, frees unused capacity, and rebuilds the
+    /// index with densely numbered ids.
+    pub fn compact(&mut self) {
+        self.map.compact();
+        self.rebuild_index();
+    }
+    /// Replaces every tag of every entry by the result of `f`, and rebuilds the index.
+    ///
+    /// See `TagMap::map_tags`.
+    pub fn map_tags<F: FnMut(TAG) -> TAG>(&mut self, f: F) {
+        self.map.map_tags(f);
+        self.reindex();
+    }
+    /// Replaces every tag of every entry by the result of `f`, removing the tags for
+    /// which it returns `None`, and rebuilds the index.
+    ///
+    /// See `TagMap::filter_map_tags`.
+    pub fn filter_map_tags<F: FnMut(TAG) -> Option<TAG>>(&mut self, f: F) {
+        self.map.filter_map_tags(f);
+        self.reindex();
+    }
+    /// Rebuilds the index after a change to every entry, unless updating it is deferred.
+    fn reindex(&mut self) {
+        if self.deferred {
+            self.stale = true;
+        } else {
+            self.rebuild_index();
+        }
+    }
+    /// Creates a map from entries sorted by key, building the index in one pass.
+    ///
+    /// See `TagMap::from_sorted_iter`.
+    pub fn from_sorted_iter<I: IntoIterator<Item = (T, Vec<TAG>)>>(iter: I) -> Self {
+        IndexedTagMap::from(TagMap::from_sorted_iter(iter))
+    }
+    /// Rebuilds the index from scratch, numbering the keys in order.
+    pub fn rebuild_index(&mut self) {
+        self.stale = false;
+        self.ids = self.map.keys().cloned().zip(0..).collect();
+        self.keys = self.map.keys().cloned().map(Some).collect();
+        self.free_ids = Vec::new();
+        self.index.clear();
+        for (id, tags) in (0..).zip(self.map.values()) {
+            for tag in tags {
+                self.index.entry(tag.clone()).or_default().add(id);
+            }
+        }
+    }
+    /// Inserts an entry with the given tags.
+    ///
+    /// If the key was already present, its old tags are replaced and returned.
+    pub fn insert(&mut self, key: T, tags: Vec<TAG>) -> Option<Vec<TAG>> {
+        if self.deferred {
+            self.stale = true;
+            return self.map.insert(key, tags);
+        }
+        let id = self.id_for(&key);
+        for tag in &tags {
+            self.index.entry(tag.clone()).or_default().add(id);
+        }
+        let old = self.map.insert(key.clone(), tags);
+        if let Some(ref old) = old {
+            for tag in old {
+                if !self.map.has_tag(&key, tag) {
+                    self.unindex(tag, id);
+                }
+            }
+        }
+        old
+    }
+    /// Removes an entry, returning its tags if it was present.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<Vec<TAG>>
+        where T: Borrow<Q>
+    {
+        let tags = self.map.remove(key)?;
+        if self.deferred {
+            self.stale = true;
+            return Some(tags);
+        }
+        let id = self.ids.remove(key).expect("indexed key without an id");
+        self.keys[id as usize] = None;
+        self.free_ids.push(id);
+        for tag in &tags {
+            self.unindex(tag, id);
+        }
+        Some(tags)
+    }
+    /// Adds a tag to an entry, inserting the entry if it's not present.
+    ///
+    /// Returns false if the entry already had the tag.
+    pub fn add_tag(&mut self, key: T, tag: TAG) -> bool {
+        if self.map.has_tag(&key, &tag) {
+            return false;
+        }
+        if self.deferred {
+            self.stale = true;
+            return self.map.add_tag(key, tag);
+        }
+        let id = self.id_for(&key);
+        self.index.entry(tag.clone()).or_default().add(id);
+        self.map.add_tag(key, tag)
+    }
+    /// Removes a tag from an entry.
+    ///
+    /// Returns false if the entry is not present or didn't have the tag.
+    pub fn remove_tag<Q: ?Sized + Ord>(&mut self, key: &Q, tag: &TAG) -> bool
+        where T: Borrow<Q>
+    {
+        if !self.map.remove_tag(key, tag) {
+            return false;
+        }
+        if self.deferred {
+            self.stale = true;
+            return true;
+        }
+        let id = self.ids[key];
+        self.unindex(tag, id);
+        true
+    }
+    /// Removes a tag from every entry, returning the number of entries that had it.
+    pub fn remove_tag_everywhere(&mut self, tag: &TAG) -> usize {
+        if self.deferred {
+            let count = self.map.remove_tag_everywhere(tag);
+            self.stale |= count > 0;
+            return count;
+        }
+        let ids = match self.index.remove(tag) {
+            Some(ids) => ids,
+            None => return 0,
+        };
+        for id in ids.ids() {
+            let key = self.keys[id as usize].as_ref().expect("index refers to a removed key");
+            let tags = self.map.entries.get_mut(key).expect("index refers to a missing entry");
+            tags.retain(|t| t != tag);
+        }
+        ids.count() as usize
+    }
+    /// Replaces a tag by another in every entry, returning the number of entries changed.
+    ///
+    /// See `TagMap::rename_tag`.
+    pub fn rename_tag(&mut self, from: &TAG, to: TAG) -> usize {
+        if self.deferred {
+            let count = self.map.rename_tag(from, to);
+            self.stale |= count > 0;
+            return count;
+        }
+        if *from == to {
+            return 0;
+        }
+        let ids = match self.index.remove(from) {
+            Some(ids) => ids,
+            None => return 0,
+        };
+        for id in ids.ids() {
+            let key = self.keys[id as usize].as_ref().expect("index refers to a removed key");
+            let tags = self.map.entries.get_mut(key).expect("index refers to a missing entry");
+            rename_in(tags, from, &to);
+        }
+        self.index.entry(to).or_default().unite(&ids);
+        ids.count() as usize
+    }
+    /// Returns every distinct tag used by the entries.
+    ///
+    /// Unless the index is stale, the tags are read from the index without scanning
+    /// the entries.
+    pub fn tags(&self) -> BTreeSet<&TAG> {
+        if self.stale {
+            return self.map.tags();
+        }
+        self.index.keys().collect()
+    }
+    /// Returns the number of entries having each tag in use.
+    ///
+    /// Unless the index is stale, the counts are read from the index.
+    pub fn tag_counts(&self) -> BTreeMap<&TAG, usize> {
+        if self.stale {
+            return self.map.tag_counts();
+        }
+        self.index.iter().map(|(tag, ids)| (tag, ids.count() as usize)).collect()
+    }
+    /// Returns the number of entries having each tag in use, most frequent first.
+    ///
+    /// Tags with the same count are in order.
+    pub fn tag_counts_by_frequency(&self) -> Vec<(&TAG, usize)> {
+        by_frequency(self.tag_counts())
+    }
+    /// Returns the tags appearing together with `tag`, with the number of entries having
+    /// both, most frequent first.
+    ///
+    /// Unless the index is stale, only the entries having `tag` are visited.
+    pub fn co_occurring(&self, tag: &TAG) -> Vec<(&TAG, usize)> {
+        if self.stale {
+            return self.map.co_occurring(tag);
+        }
+        let ids = match self.index.get(tag) {
+            Some(ids) => ids,
+            None => return Vec::new(),
+        };
+        let lists = self.resolve_unsorted(ids).into_iter().filter_map(|key| self.map.get_tags(key));
+        let mut counts = count_tags(lists);
+        counts.remove(tag);
+        by_frequency(counts)
+    }
+    /// Returns the number of entries matching the rule having each tag, most frequent
+    /// first.
+    pub fn facets(&self, rule: &MatchRule<TAG>) -> Vec<(&TAG, usize)> {
+        let lists = self.matching(rule).filter_map(|key| self.map.get_tags(key));
+        by_frequency(count_tags(lists))
+    }
+    /// Suggests tags narrowing down the entries matching the rule, best first.
+    ///
+    /// See `TagMap::refinements`.
+    pub fn refinements(&self, rule: &MatchRule<TAG>) -> Vec<(&TAG, usize)> {
+        let matching: Vec<_> =
+            self.matching(rule).filter_map(|key| self.map.get_tags(key)).collect();
+        refinements_of(by_frequency(count_tags(matching.iter().cloned())), matching.len())
+    }
+    /// Returns the number of entries having the given tag.
+    pub fn count_with_tag(&self, tag: &TAG) -> usize {
+        if self.stale {
+            return self.map.values().filter(|tags| tags.contains(tag)).count();
+        }
+        self.index.get(tag).map_or(0, |ids| ids.count() as usize)
+    }
+    /// Returns the keys of the entries having the given tag, in order.
+    pub fn keys_with_tag(&self, tag: &TAG) -> Vec<&T> {
+        if self.stale {
+            return self.map.iter().filter(|(_, tags)| tags.contains(tag)).map(|(k, _)| k).collect();
+        }
+        match self.index.get(tag) {
+            Some(ids) => self.resolve_ids(ids),
+            None => Vec::new(),
+        }
+    }
+    /// Estimates the memory used by the map and its index.
+    pub fn memory_usage(&self) -> MemoryReport {
+        let ids = self.ids.len() * (size_of::<T>() + size_of::<u32>()) +
+                  self.keys.capacity() * size_of::<Option<T>>() +
+                  self.free_ids.capacity() * size_of::<u32>();
+        let index: usize = self.index
+            .values()
+            .map(|ids| size_of::<TAG>() + size_of::<IdSet>() + ids.heap_size())
+            .sum();
+        MemoryReport { indexes: ids + index, ..self.map.memory_usage() }
+    }
+    /// Returns the keys of the entries matching the given rule, in order.
+    pub fn matching<'a>(&'a self, rule: &'a MatchRule<TAG>) -> vec::IntoIter<&'a T> {
+        if self.stale {
+            return self.map.matching(rule).collect::<Vec<_>>().into_iter();
+        }
+        let keys = match self.plan(rule) {
+            Plan::Exact(ids) => self.resolve_ids(&ids),
+            Plan::Candidates(ids) => {
+                let mut keys = self.resolve_ids(&ids);
+                keys.retain(|key| rule.matches(self.map.get_tags(*key).unwrap_or(&[])));
+                keys
+            }
+            Plan::Unknown => self.map.matching(rule).collect(),
+        };
+        keys.into_iter()
+    }
+    /// Returns the keys of the first `limit` entries matching the given rule, in order.
+    ///
+    /// Unlike taking from `matching`, only the keys that are returned are sorted,
+    /// and candidates from the index are only checked until enough match.
+    pub fn matching_limited(&self, rule: &MatchRule<TAG>, limit: usize) -> Vec<&T> {
+        if self.stale {
+            return self.map.matching(rule).take(limit).collect();
+        }
+        match self.plan(rule) {
+            Plan::Exact(ids) => {
+                let mut keys = self.resolve_unsorted(&ids);
+                if limit < keys.len() {
+                    keys.select_nth_unstable(limit);
+                    keys.truncate(limit);
+                }
+                keys.sort();
+                keys
+            }
+            Plan::Candidates(ids) => {
+                self.resolve_ids(&ids)
+                    .into_iter()
+                    .filter(|key| rule.matches(self.map.get_tags(*key).unwrap_or(&[])))
+                    .take(limit)
+                    .collect()
+            }
+            Plan::Unknown => self.map.matching(rule).take(limit).collect(),
+        }
+    }
+    /// Returns the number of entries matching the given rule.
+    pub fn count_matching(&self, rule: &MatchRule<TAG>) -> usize {
+        if self.stale {
+            return self.map.count_matching(rule);
+        }
+        match self.plan(rule) {
+            Plan::Exact(ids) => ids.count() as usize,
+            Plan::Candidates(ids) => ids.ids().filter(|&id| self.id_matches(id, rule)).count(),
+            Plan::Unknown => self.map.count_matching(rule),
+        }
+    }
+    /// Returns whether any entry matches the given rule.
+    ///
+    /// Stops checking candidates from the index at the first match.
+    pub fn any_matching(&self, rule: &MatchRule<TAG>) -> bool {
+        if self.stale {
+            return self.map.any_matching(rule);
+        }
+        match self.plan(rule) {
+            Plan::Exact(ids) => ids.count() != 0,
+            Plan::Candidates(ids) => ids.ids().any(|id| self.id_matches(id, rule)),
+            Plan::Unknown => self.map.any_matching(rule),
+        }
+    }
+    /// Returns the first entry, in key order, matching the given rule.
+    ///
+    /// Keys from the index are compared as they are found, without being collected.
+    pub fn first_matching(&self, rule: &MatchRule<TAG>) -> Option<&T> {
+        if self.stale {
+            return self.map.first_matching(rule);
+        }
+        match self.plan(rule) {
+            Plan::Exact(ids) => ids.ids().map(|id| self.key(id)).min(),
+            Plan::Candidates(ids) => {
+                ids.ids().filter(|&id| self.id_matches(id, rule)).map(|id| self.key(id)).min()
+            }
+            Plan::Unknown => self.map.first_matching(rule),
+        }
+    }
+    /// Determines which ids the index can tell match the rule.
+    fn plan(&self, rule: &MatchRule<TAG>) -> Plan {
+        use MatchRule::*;
+        match *rule {
+            Tags(ref tags) => Plan::Exact(self.with_all(tags).unwrap_or_else(|| self.all_ids())),
+            AnyTag(ref tags) => Plan::Exact(self.with_any(tags)),
+            NotTags(ref tags) => self.complement(Plan::Exact(self.with_any(tags))),
+            Anything => Plan::Exact(self.all_ids()),
+            Nothing => Plan::Exact(IdSet::default()),
+            Rules(ref rules) => {
+                let (mut sets, mut exact) = (Vec::with_capacity(rules.len()), true);
+                for rule in rules {
+                    let set = match self.plan(rule) {
+                        Plan::Exact(set) => set,
+                        Plan::Candidates(set) => {
+                            exact = false;
+                            set
+                        }
+                        Plan::Unknown => {
+                            exact = false;
+                            continue;
+                        }
+                    };
+                    if set.count() == 0 {
+                        return Plan::Exact(IdSet::default());
+                    }
+                    sets.push(set);
+                }
+                // Start from the smallest set, so the intersection is cheap
+                sets.sort_by_key(|set| set.count());
+                let mut sets = sets.into_iter();
+                let ids = match sets.next() {
+                    Some(mut ids) => {
+                        for set in sets {
+                            ids.intersect(&set);
+                            if ids.count() == 0 {
+                                return Plan::Exact(ids);
+                            }
+                        }
+                        ids
+                    }
+                    None if exact => self.all_ids(),
+                    None => return Plan::Unknown,
+                };
+                if exact { Plan::Exact(ids) } else { Plan::Candidates(ids) }
+            }
+            AnyRule(ref rules) => self.plan_any(rules),
+            NotRules(ref rules) => self.complement(self.plan_any(rules)),
+            Not(ref rule) => self.complement(self.plan(rule)),
+            _ => Plan::Unknown,
+        }
+    }
+    fn plan_any(&self, rules: &[MatchRule<TAG>]) -> Plan {
+        let (mut ids, mut exact) = (IdSet::default(), true);
+        for rule in rules {
+            match self.plan(rule) {
+                Plan::Exact(set) => ids.unite(&set),
+                Plan::Candidates(set) => {
+                    ids.unite(&set);
+                    exact = false;
+                }
+                Plan::Unknown => return Plan::Unknown,
+            }
+        }
+        if exact { Plan::Exact(ids) } else { Plan::Candidates(ids) }
+    }
+    fn complement(&self, plan: Plan) -> Plan {
+        match plan {
+            Plan::Exact(ids) => {
+                let mut all = self.all_ids();
+                all.subtract(&ids);
+                Plan::Exact(all)
+            }
+            _ => Plan::Unknown,
+        }
+    }
+    fn all_ids(&self) -> IdSet {
+        let mut ids = IdSet::default();
+        for &id in self.ids.values() {
+            ids.add(id);
+        }
+        ids
+    }
+    fn id_for(&mut self, key: &T) -> u32 {
+        if let Some(&id) = self.ids.get(key) {
+            return id;
+        }
+        let id = match self.free_ids.pop() {
+            Some(id) => {
+                self.keys[id as usize] = Some(key.clone());
+                id
+            }
+            None => {
+                assert!(self.keys.len() < u32::MAX as usize, "too many entries to index");
+                self.keys.push(Some(key.clone()));
+                (self.keys.len() - 1) as u32
+            }
+        };
+        self.ids.insert(key.clone(), id);
+        id
+    }
+    fn resolve_ids(&self, ids: &IdSet) -> Vec<&T> {
+        let mut keys = self.resolve_unsorted(ids);
+        keys.sort();
+        keys
+    }
+    fn resolve_unsorted(&self, ids: &IdSet) -> Vec<&T> {
+        ids.ids().map(|id| self.key(id)).collect()
+    }
+    fn key(&self, id: u32) -> &T {
+        self.keys[id as usize].as_ref().expect("index refers to a removed key")
+    }
+    fn id_matches(&self, id: u32, rule: &MatchRule<TAG>) -> bool {
+        rule.matches(self.map.get_tags(self.key(id)).unwrap_or(&[]))
+    }
+    /// Returns the ids having all the tags, or `None` for all ids.
+    fn with_all(&self, tags: &[TAG]) -> Option<IdSet> {
+        let mut sets = Vec::with_capacity(tags.len());
+        for tag in tags {
+            match self.index.get(tag) {
+                Some(set) => sets.push(set),
+                None => return Some(IdSet::default()),
+            }
+        }
+        // Start from the smallest set, so the intersection is cheap
+        sets.sort_by_key(|set| set.count());
+        let (first, rest) = sets.split_first()?;
+        let mut ids = (*first).clone();
+        for set in rest {
+            ids.intersect(set);
+        }
+        Some(ids)
+    }
+    fn with_any(&self, tags: &[TAG]) -> IdSet {
+        let mut ids = IdSet::default();
+        for set in tags.iter().filter_map(|tag| self.index.get(tag)) {
+            ids.unite(set);
+        }
+        ids
+    }
+    fn unindex(&mut self, tag: &TAG, id: u32) {
+        let now_empty = match self.index.get_mut(tag) {
+            Some(ids) => {
+                ids.discard(id);
+                ids.count() == 0
+            }
+            None => false,
+        };
+        if now_empty {
+            self.index.remove(tag);
+        }
+    }
+}
+
+/// The result of planning a rule against the index.
+enum Plan {
+    /// Exactly these ids match.
+    Exact(IdSet),
+    /// Only these ids can match, but the rule must still be checked.
+    Candidates(IdSet),
+    /// The index can't narrow down the matches.
+    Unknown,
+}
+
+impl<T: Ord + Clone, TAG: Ord + Clone> Default for IndexedTagMap<T, TAG> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord + Clone, TAG: Ord + Clone> From<TagMap<T, TAG>> for IndexedTagMap<T, TAG> {
+    fn from(map: TagMap<T, TAG>) -> Self {
+        let mut indexed = IndexedTagMap { map, ..IndexedTagMap::new() };
+        indexed.rebuild_index();
+        indexed
+    }
+}
+
+impl<T: Ord, TAG: Eq> Deref for IndexedTagMap<T, TAG> {
+    type Target = TagMap<T, TAG>;
+    fn deref(&self) -> &TagMap<T, TAG> {
+        &self.map
+    }
+}
+
+#[test]
+fn test_indexed() {
+    use MatchRule::*;
+    let mut map = IndexedTagMap::new();
+    for i in 0..100u32 {
+        let tags = (0..6).filter(|bit| i & (1 << bit) != 0).collect();
+        map.insert(i, tags);
+    }
+    map.insert(3, vec![0, 2]);
+    map.remove(&5);
+    map.add_tag(7, 5);
+    map.remove_tag(&9, &3);
+    let odd = MatchRule::predicate(|tags: &[u32]| tags.len() % 2 == 1);
+    let rules = [Tags(vec![0, 2]), Tags(vec![]), AnyTag(vec![1, 5]), NotTags(vec![0, 3]),
+                 Tags(vec![7]), AnyRule(vec![Tags(vec![1]), NotTags(vec![2])]),
+                 Rules(vec![Tags(vec![1]), odd.clone(), Not(Box::new(AnyTag(vec![4])))]),
+                 AnyRule(vec![Rules(vec![Tags(vec![3]), odd.clone()]), Tags(vec![5])]),
+                 NotRules(vec![Tags(vec![0]), Xor(vec![Tags(vec![1]), Tags(vec![2])])]),
+                 Rules(vec![odd, Anything]),
+                 Rules(vec![Tags(vec![1]), Nothing])];
+    for rule in &rules {
+        let expected: Vec<_> = map.deref().matching(rule).collect();
+        assert_eq!(map.matching(rule).collect::<Vec<_>>(), expected, "{:?}", rule);
+        for &limit in &[0, 1, 5, 100] {
+            let limited: Vec<_> = expected.iter().cloned().take(limit).collect();
+            assert_eq!(map.matching_limited(rule, limit), limited, "{:?}", rule);
+        }
+        assert_eq!(map.count_matching(rule), expected.len(), "{:?}", rule);
+        assert_eq!(map.any_matching(rule), map.deref().any_matching(rule), "{:?}", rule);
+        assert_eq!(map.first_matching(rule), map.deref().first_matching(rule), "{:?}", rule);
+    }
+    assert!(!map.keys_with_tag(&3).contains(&&9));
+    assert_eq!(map.count_with_tag(&3), map.keys_with_tag(&3).len());
+    let rebuilt = IndexedTagMap::from(map.clone().into_inner());
+    let entries = map.iter().map(|(&key, tags)| (key, tags.to_vec()));
+    let loaded = IndexedTagMap::from_sorted_iter(entries);
+    for tag in 0..8 {
+        assert_eq!(rebuilt.keys_with_tag(&tag), map.keys_with_tag(&tag));
+        assert_eq!(loaded.keys_with_tag(&tag), map.keys_with_tag(&tag));
+    }
+}
+
+#[test]
+fn test_deferred_index() {
+    use MatchRule::*;
+    let mut map = IndexedTagMap::new();
+    map.insert(100, vec!["x"]);
+    map.set_deferred(true);
+    for i in 0..10 {
+        map.insert(i, vec![if i % 2 == 0 { "x" } else { "y" }]);
+    }
+    map.remove(&0);
+    map.add_tag(1, "x");
+    assert!(map.is_stale());
+    let x = Tags(vec!["x"]);
+    let expected: Vec<_> = map.deref().matching(&x).cloned().collect();
+    assert_eq!(map.matching(&x).cloned().collect::<Vec<_>>(), expected);
+    assert_eq!(map.count_with_tag(&"x"), expected.len());
+    assert_eq!(map.count_matching(&x), expected.len());
+    assert_eq!(map.first_matching(&x), expected.first());
+    map.rebuild_index();
+    assert!(!map.is_stale());
+    assert_eq!(map.matching(&x).cloned().collect::<Vec<_>>(), expected);
+    map.remove_tag(&100, &"x");
+    map.set_deferred(false);
+    assert!(!map.is_stale());
+    assert_eq!(map.keys_with_tag(&"x").len(), expected.len() - 1);
+}
@@ -0,0 +1,257 @@
+//! A tag map storing string tags as small integer IDs.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::ops::Deref;
+use std::sync::Arc;
+use {CompiledRule, MatchRule, Matcher, MemoryReport, TagMap};
+
+/// The ID of a tag interned by an `Interner`.
+///
+/// IDs are only meaningful for the interner that created them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TagId(u32);
+
+impl TagId {
+    /// Returns the ID as an integer.
+    pub fn get(self) -> u32 {
+        self.0
+    }
+}
+
+/// A registry mapping string tags to `TagId`s and back.
+///
+/// Each distinct string is stored once, and is never removed.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    strings: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, TagId>,
+}
+
+impl Interner {
+    /// Creates a new empty interner.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Returns the number of interned strings.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+    /// Returns whether no string was interned.
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+    /// Returns the ID of a string, interning it if it's new.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more than `u32::MAX` strings are interned.
+    pub fn intern(&mut self, tag: &str) -> TagId {
+        if let Some(&id) = self.ids.get(tag) {
+            return id;
+        }
+        assert!(self.strings.len() < u32::MAX as usize, "too many interned tags");
+        let id = TagId(self.strings.len() as u32);
+        let string: Arc<str> = Arc::from(tag);
+        self.strings.push(string.clone());
+        self.ids.insert(string, id);
+        id
+    }
+    /// Returns the ID of a string, if it was interned.
+    pub fn get(&self, tag: &str) -> Option<TagId> {
+        self.ids.get(tag).cloned()
+    }
+    /// Returns the string with the given ID, if it was interned by this interner.
+    pub fn resolve(&self, id: TagId) -> Option<&str> {
+        self.strings.get(id.0 as usize).map(|string| &string[..])
+    }
+    /// Returns the memory used by the interner, in bytes.
+    fn memory_usage(&self) -> usize {
+        // Each string is stored once, after the reference counts of its `Arc`
+        let strings: usize = self.strings.iter().map(|s| s.len() + 2 * size_of::<usize>()).sum();
+        self.strings.capacity() * size_of::<Arc<str>>() + strings +
+        self.ids.capacity() * (size_of::<Arc<str>>() + size_of::<TagId>())
+    }
+    /// Converts a rule on string tags to a rule on IDs.
+    ///
+    /// Tags that were never interned can't be present on any entry, so they are
+    /// dropped or make their rule `Nothing`, as appropriate. Returns `None` if the
+    /// rule contains predicates or patterns, which can't be evaluated on IDs.
+    pub fn intern_rule<S: AsRef<str>>(&self, rule: &MatchRule<S>) -> Option<MatchRule<TagId>> {
+        use MatchRule::*;
+        let known = |tags: &[S]| -> Vec<TagId> {
+            tags.iter().filter_map(|tag| self.get(tag.as_ref())).collect()
+        };
+        let rules = |rules: &[MatchRule<S>]| -> Option<Vec<MatchRule<TagId>>> {
+            rules.iter().map(|rule| self.intern_rule(rule)).collect()
+        };
+        Some(match *rule {
+            Tags(ref tags) => {
+                let ids = known(tags);
+                if ids.len() == tags.len() {
+                    Tags(ids)
+                } else {
+                    Nothing
+                }
+            }
+            NotTags(ref tags) => NotTags(known(tags)),
+            AnyTag(ref tags) => AnyTag(known(tags)),
+            Rules(ref r) => Rules(rules(r)?),
+            NotRules(ref r) => NotRules(rules(r)?),
+            AnyRule(ref r) => AnyRule(rules(r)?),
+            Xor(ref r) => Xor(rules(r)?),
+            AtLeast(n, ref tags) => AtLeast(n, known(tags)),
+            Exactly(n, ref tags) => Exactly(n, known(tags)),
+            AtMost(n, ref tags) => AtMost(n, known(tags)),
+            Anything => Anything,
+            Nothing => Nothing,
+            Not(ref rule) => Not(Box::new(self.intern_rule(rule)?)),
+            Predicate(_) | Pattern(_) => return None,
+        })
+    }
+}
+
+/// A `TagMap` with string tags, storing each entry's tags as `TagId`s.
+///
+/// Every distinct tag string is stored once in the map's `Interner`, and rules are
+/// evaluated by comparing integers. The map can be read through `Deref`, but must
+/// be modified through the methods of this type.
+#[derive(Debug, Clone)]
+pub struct InternedTagMap<T: Ord> {
+    map: TagMap<T, TagId>,
+    interner: Interner,
+}
+
+impl<T: Ord> InternedTagMap<T> {
+    /// Creates a new empty map.
+    pub fn new() -> Self {
+        InternedTagMap {
+            map: TagMap::new(),
+            interner: Interner::new(),
+        }
+    }
+    /// Returns the interner owned by the map.
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+    /// Removes duplicate tags within entries, and frees the unused capacity of the tag lists.
+    ///
+    /// Interned strings are kept, so existing `TagId`s stay valid.
+    pub fn compact(&mut self) {
+        self.map.compact();
+    }
+    /// Estimates the memory used by the map and its interner.
+    pub fn memory_usage(&self) -> MemoryReport {
+        MemoryReport { indexes: self.interner.memory_usage(), ..self.map.memory_usage() }
+    }
+    /// Inserts an entry with the given tags.
+    ///
+    /// If the key was already present, its old tags are replaced and returned.
+    pub fn insert<I>(&mut self, key: T, tags: I) -> Option<Vec<TagId>>
+        where I: IntoIterator,
+              I::Item: AsRef<str>
+    {
+        let ids: Vec<_> = tags.into_iter().map(|tag| self.interner.intern(tag.as_ref())).collect();
+        self.map.insert(key, ids)
+    }
+    /// Removes an entry, returning its tags if it was present.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<Vec<TagId>>
+        where T: Borrow<Q>
+    {
+        self.map.remove(key)
+    }
+    /// Returns the tags of an entry as strings, if it is present.
+    pub fn tags<Q: ?Sized + Ord>(&self, key: &Q) -> Option<Vec<&str>>
+        where T: Borrow<Q>
+    {
+        let ids = self.map.get_tags(key)?;
+        Some(ids.iter().filter_map(|&id| self.interner.resolve(id)).collect())
+    }
+    /// Returns whether the entry for the given key has the given tag.
+    pub fn has_tag<Q: ?Sized + Ord>(&self, key: &Q, tag: &str) -> bool
+        where T: Borrow<Q>
+    {
+        self.interner.get(tag).is_some_and(|id| self.map.has_tag(key, &id))
+    }
+    /// Adds a tag to an entry, inserting the entry if it's not present.
+    ///
+    /// Returns false if the entry already had the tag.
+    pub fn add_tag(&mut self, key: T, tag: &str) -> bool {
+        let id = self.interner.intern(tag);
+        self.map.add_tag(key, id)
+    }
+    /// Removes a tag from an entry.
+    ///
+    /// Returns false if the entry is not present or didn't have the tag.
+    pub fn remove_tag<Q: ?Sized + Ord>(&mut self, key: &Q, tag: &str) -> bool
+        where T: Borrow<Q>
+    {
+        match self.interner.get(tag) {
+            Some(id) => self.map.remove_tag(key, &id),
+            None => false,
+        }
+    }
+    /// Interns and compiles a rule on string tags, for matching against the map.
+    ///
+    /// Returns `None` if the rule can't be interned; see `Interner::intern_rule`.
+    pub fn compile<S: AsRef<str>>(&self, rule: &MatchRule<S>) -> Option<CompiledRule<TagId>> {
+        Some(self.interner.intern_rule(rule)?.compile())
+    }
+    /// Returns the keys of the entries matching a rule on string tags, in order.
+    ///
+    /// Returns `None` if the rule can't be interned; see `Interner::intern_rule`.
+    pub fn matching<S: AsRef<str>>(&self, rule: &MatchRule<S>) -> Option<Vec<&T>> {
+        let rule = self.compile(rule)?;
+        Some(self.map.iter().filter(|(_, ids)| rule.matches(ids)).map(|(key, _)| key).collect())
+    }
+}
+
+impl<T: Ord> Default for InternedTagMap<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord, S: AsRef<str> + Eq> From<TagMap<T, S>> for InternedTagMap<T> {
+    fn from(map: TagMap<T, S>) -> Self {
+        let mut interned = InternedTagMap::new();
+        for (key, tags) in map {
+            interned.insert(key, tags);
+        }
+        interned
+    }
+}
+
+impl<T: Ord> Deref for InternedTagMap<T> {
+    type Target = TagMap<T, TagId>;
+    fn deref(&self) -> &TagMap<T, TagId> {
+        &self.map
+    }
+}
+
+#[test]
+fn test_interned() {
+    use MatchRule::*;
+    let mut strings = TagMap::new();
+    strings.insert("a.png", vec!["cat", "cute"]);
+    strings.insert("b.png", vec!["dog", "cute"]);
+    let mut map = InternedTagMap::from(strings.clone());
+    assert!(map.add_tag("c.png", "cat"));
+    strings.add_tag("c.png", "cat");
+    assert!(!map.add_tag("a.png", "cute"));
+    assert_eq!(map.interner().len(), 3);
+    assert_eq!(map.tags("b.png"), Some(vec!["dog", "cute"]));
+    assert!(map.has_tag("c.png", "cat"));
+    assert!(!map.has_tag("c.png", "bird"));
+    assert!(!map.remove_tag("c.png", "bird"));
+    let cat = map.interner().get("cat").unwrap();
+    assert_eq!(map.interner().resolve(cat), Some("cat"));
+    let rules = [Tags(vec!["cat", "cute"]), Tags(vec!["cat", "bird"]), NotTags(vec!["bird", "dog"]),
+                 AtLeast(1, vec!["bird", "dog", "cat"]), Not(Box::new(AnyTag(vec!["cute"])))];
+    for rule in &rules {
+        let expected: Vec<_> = strings.matching(rule).collect();
+        assert_eq!(map.matching(rule), Some(expected), "{:?}", rule);
+    }
+    assert_eq!(map.matching(&MatchRule::<&str>::tag_prefix("c")), None);
+}
@@ -0,0 +1,157 @@
+//! Reading and writing the entries of a map as JSON.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::de::IoRead;
+use serde_json::{self, Deserializer, StreamDeserializer};
+use serialize::Entries;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use TagMap;
+
+impl<T: Ord, TAG: Eq> TagMap<T, TAG> {
+    /// Writes the entries as a JSON object, mapping each key to an array of its tags.
+    ///
+    /// For example, `{"a.png": ["cat", "cute"], "b.png": []}`. Keys must serialize as
+    /// strings or numbers, which are written as strings. Unlike serializing the map,
+    /// the vocabulary isn't written.
+    pub fn to_json_writer<W: Write>(&self, writer: W) -> serde_json::Result<()>
+        where T: Serialize,
+              TAG: Serialize
+    {
+        serde_json::to_writer(writer, &Entries(self))
+    }
+    /// Reads entries written by `to_json_writer`, or in the same format.
+    pub fn from_json_reader<R: Read>(reader: R) -> serde_json::Result<Self>
+        where T: for<'de> Deserialize<'de>,
+              TAG: for<'de> Deserialize<'de>
+    {
+        let entries: BTreeMap<T, Vec<TAG>> = serde_json::from_reader(reader)?;
+        Ok(TagMap::from_sorted_iter(entries))
+    }
+}
+
+impl<T: Ord, TAG: Eq> TagMap<T, TAG> {
+    /// Writes the entries as JSON lines, one `[key, [tag, ...]]` array per line.
+    ///
+    /// See `JsonLinesWriter`. For performance, the writer should be buffered.
+    pub fn to_json_lines_writer<W: Write>(&self, writer: W) -> serde_json::Result<()>
+        where T: Serialize,
+              TAG: Serialize
+    {
+        let mut writer = JsonLinesWriter::new(writer);
+        for (key, tags) in self.iter() {
+            writer.write_entry(key, tags)?;
+        }
+        writer.flush()
+    }
+    /// Reads entries written as JSON lines, inserting them one at a time.
+    ///
+    /// Only one entry is held in memory besides the map. See `JsonLinesReader`.
+    pub fn from_json_lines_reader<R: Read>(reader: R) -> serde_json::Result<Self>
+        where T: DeserializeOwned,
+              TAG: DeserializeOwned
+    {
+        let mut map = TagMap::new();
+        for entry in JsonLinesReader::new(reader) {
+            let (key, tags) = entry?;
+            map.insert(key, tags);
+        }
+        Ok(map)
+    }
+}
+
+/// Writes entries one at a time as JSON lines, one `[key, [tag, ...]]` array per line.
+///
+/// For example, `["a.png",["cat","cute"]]`. Unlike `TagMap::to_json_writer`, keys can
+/// be any serializable value.
+#[derive(Debug)]
+pub struct JsonLinesWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    /// Creates a writer writing to `writer`, which should be buffered for performance.
+    pub fn new(writer: W) -> Self {
+        JsonLinesWriter { writer }
+    }
+    /// Writes an entry.
+    pub fn write_entry<T, TAG>(&mut self, key: &T, tags: &[TAG]) -> serde_json::Result<()>
+        where T: ?Sized + Serialize,
+              TAG: Serialize
+    {
+        serde_json::to_writer(&mut self.writer, &(key, tags))?;
+        self.writer.write_all(b"\n").map_err(serde_json::Error::io)
+    }
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> serde_json::Result<()> {
+        self.writer.flush().map_err(serde_json::Error::io)
+    }
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// An iterator reading entries one at a time from JSON lines, as written by
+/// `JsonLinesWriter`.
+///
+/// Any whitespace can separate the entries. For performance, the reader should be
+/// buffered.
+pub struct JsonLinesReader<R: Read, T, TAG> {
+    entries: StreamDeserializer<'static, IoRead<R>, (T, Vec<TAG>)>,
+}
+
+impl<R: Read, T: DeserializeOwned, TAG: DeserializeOwned> JsonLinesReader<R, T, TAG> {
+    /// Creates a reader reading from `reader`.
+    pub fn new(reader: R) -> Self {
+        JsonLinesReader { entries: Deserializer::from_reader(reader).into_iter() }
+    }
+}
+
+impl<R, T, TAG> Iterator for JsonLinesReader<R, T, TAG>
+    where R: Read,
+          T: DeserializeOwned,
+          TAG: DeserializeOwned
+{
+    type Item = serde_json::Result<(T, Vec<TAG>)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next()
+    }
+}
+
+#[test]
+fn test_json() {
+    let json = r#"{"b.png": ["dog"], "a.png": ["cat", "cute"], "c.png": []}"#;
+    let map: TagMap<String, String> = TagMap::from_json_reader(json.as_bytes()).unwrap();
+    assert_eq!(map.keys().collect::<Vec<_>>(), ["a.png", "b.png", "c.png"]);
+    assert_eq!(map.get_tags("a.png").unwrap(), ["cat", "cute"]);
+    let mut out = Vec::new();
+    map.to_json_writer(&mut out).unwrap();
+    assert_eq!(out, br#"{"a.png":["cat","cute"],"b.png":["dog"],"c.png":[]}"#);
+    let ids: TagMap<u32, &str> = vec![(7, vec!["x"])].into_iter().collect();
+    let mut out = Vec::new();
+    ids.to_json_writer(&mut out).unwrap();
+    assert_eq!(out, br#"{"7":["x"]}"#);
+    assert!(TagMap::<String, String>::from_json_reader(&b"[1, 2]"[..]).is_err());
+}
+
+#[test]
+fn test_json_lines() {
+    let mut map = TagMap::new();
+    map.insert(vec![1, 2], vec!["cat".to_string(), "cute".to_string()]);
+    map.insert(vec![3], vec![]);
+    let mut out = Vec::new();
+    map.to_json_lines_writer(&mut out).unwrap();
+    assert_eq!(out, &b"[[1,2],[\"cat\",\"cute\"]]\n[[3],[]]\n"[..]);
+    assert_eq!(TagMap::from_json_lines_reader(&out[..]).unwrap(), map);
+    let mut writer = JsonLinesWriter::new(Vec::new());
+    writer.write_entry("a", &["x"]).unwrap();
+    writer.write_entry("b", &["y", "z"]).unwrap();
+    let out = writer.into_inner();
+    let mut reader = JsonLinesReader::<_, String, String>::new(&out[..]);
+    assert_eq!(reader.next().unwrap().unwrap(), ("a".to_string(), vec!["x".to_string()]));
+    assert_eq!(reader.count(), 1);
+    let bad = TagMap::<String, String>::from_json_lines_reader(&b"[\"a\", []]\n[1]"[..]);
+    assert!(bad.is_err());
+}
@@ -0,0 +1,185 @@
+//! Structured tags of the form `key=value`, and patterns matching them.
+
+use parse::write_tag;
+use pattern::write_prefix;
+use std::fmt;
+use TagPattern;
+
+/// A tag made of a key and an optional value.
+///
+/// String tags are split at the first `=`, so `"year=2021"` has the key `"year"`
+/// and the value `"2021"`, and `"mammal"` has the key `"mammal"` and no value.
+/// Pairs of strings are a key and a value.
+pub trait KeyValueTag {
+    /// Returns the key of the tag.
+    fn key(&self) -> &str;
+    /// Returns the value of the tag, if any.
+    fn value(&self) -> Option<&str>;
+}
+
+impl KeyValueTag for str {
+    fn key(&self) -> &str {
+        self.split('=').next().unwrap_or(self)
+    }
+    fn value(&self) -> Option<&str> {
+        self.split_once('=').map(|(_, value)| value)
+    }
+}
+
+impl KeyValueTag for String {
+    fn key(&self) -> &str {
+        self[..].key()
+    }
+    fn value(&self) -> Option<&str> {
+        self[..].value()
+    }
+}
+
+impl KeyValueTag for &str {
+    fn key(&self) -> &str {
+        (*self).key()
+    }
+    fn value(&self) -> Option<&str> {
+        (*self).value()
+    }
+}
+
+impl<K: AsRef<str>, V: AsRef<str>> KeyValueTag for (K, V) {
+    fn key(&self) -> &str {
+        self.0.as_ref()
+    }
+    fn value(&self) -> Option<&str> {
+        Some(self.1.as_ref())
+    }
+}
+
+/// Matches tags with the given key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HasKey {
+    key: String,
+}
+
+impl HasKey {
+    /// Creates a new pattern.
+    pub fn new(key: &str) -> Self {
+        HasKey { key: key.to_owned() }
+    }
+    /// Returns the key.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+impl<TAG: KeyValueTag> TagPattern<TAG> for HasKey {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        tag.key() == self.key && tag.value().is_some()
+    }
+    fn fmt_query(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_prefix(&format!("{}=", self.key), f)
+    }
+}
+
+/// Matches tags with the given key and value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyEquals {
+    key: String,
+    value: String,
+}
+
+impl KeyEquals {
+    /// Creates a new pattern.
+    pub fn new(key: &str, value: &str) -> Self {
+        KeyEquals {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+    /// Returns the key.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+    /// Returns the value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl<TAG: KeyValueTag> TagPattern<TAG> for KeyEquals {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        tag.key() == self.key && tag.value() == Some(&self.value[..])
+    }
+    fn fmt_query(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_tag(&format!("{}={}", self.key, self.value), f)
+    }
+}
+
+/// Matches tags with the given key, and any of the given values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyIn {
+    key: String,
+    values: Vec<String>,
+}
+
+impl KeyIn {
+    /// Creates a new pattern.
+    pub fn new<I: IntoIterator>(key: &str, values: I) -> Self
+        where I::Item: Into<String>
+    {
+        KeyIn {
+            key: key.to_owned(),
+            values: values.into_iter().map(Into::into).collect(),
+        }
+    }
+    /// Returns the key.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+    /// Returns the values.
+    pub fn values(&self) -> &[String] {
+        &self.values
+    }
+}
+
+impl<TAG: KeyValueTag> TagPattern<TAG> for KeyIn {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        match tag.value() {
+            Some(value) => tag.key() == self.key && self.values.iter().any(|v| v == value),
+            None => false,
+        }
+    }
+    fn fmt_query(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.values.len() {
+            0 => f.write_str("!*"),
+            1 => write_tag(&format!("{}={}", self.key, self.values[0]), f),
+            _ => {
+                f.write_str("(")?;
+                for (i, value) in self.values.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(" | ")?;
+                    }
+                    write_tag(&format!("{}={}", self.key, value), f)?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+#[test]
+fn test_key_value() {
+    use {MatchRule, TagMap};
+    let mut map = TagMap::new();
+    map.insert("film", vec!["year=2021", "rating=pg", "color"]);
+    map.insert("book", vec!["year=1999", "rating=r"]);
+    map.insert("song", vec!["year", "rating=g"]);
+    let keys = |rule: MatchRule<&'static str>| map.matching(&rule).cloned().collect::<Vec<_>>();
+    assert_eq!(keys(MatchRule::has_key("year")), ["book", "film"]);
+    assert_eq!(keys(MatchRule::key_equals("year", "2021")), ["film"]);
+    assert_eq!(keys(MatchRule::key_in("rating", vec!["g", "pg"])), ["film", "song"]);
+    assert_eq!(keys(MatchRule::has_key("color")), Vec::<&str>::new());
+    let rule: MatchRule<&str> = MatchRule::key_in("rating", vec!["g", "pg"]) &
+                                MatchRule::has_key("year");
+    assert_eq!(rule.to_string(), "(rating=g | rating=pg) & year=*");
+    let pairs: TagMap<u8, (&str, &str)> = vec![(1, vec![("year", "2021")])].into_iter().collect();
+    assert_eq!(pairs.count_matching(&MatchRule::key_equals("year", "2021")), 1);
+}
@@ -2,100 +2,516 @@
 
 #![warn(missing_docs)]
 
+#[cfg(feature = "csv")]
+extern crate csv as csv_crate;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "regex")]
+extern crate regex;
+#[cfg(feature = "roaring")]
+extern crate roaring;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(any(feature = "json", all(test, feature = "serde")))]
+extern crate serde_json;
+#[cfg(feature = "smallvec")]
+extern crate smallvec;
+
+use std::borrow::Borrow;
 use std::collections::BTreeMap;
-use std::collections::btree_map::Iter;
+use std::collections::btree_map;
+use std::fmt;
+use std::iter::{FromIterator, FusedIterator, Rev};
+use std::marker::PhantomData;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+pub use arena::ArenaTagMap;
+pub use binary::{BinaryEntries, BinaryError, BinaryValue};
+pub use bitmask::{BitRule, BitTag};
+pub use cached::CachedTagMap;
+pub use combine::MergeStrategy;
+pub use compiled::CompiledRule;
+pub use concurrent::ConcurrentTagMap;
+pub use diff::{EntryChange, TagMapDiff};
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use expiring::ExpiringTagMap;
+pub use hashed::HashTagMap;
+pub use indexed::IndexedTagMap;
+pub use interned::{InternedTagMap, Interner, TagId};
+pub use key_value::{HasKey, KeyEquals, KeyIn, KeyValueTag};
+pub use limits::{LimitError, Limits};
+pub use logged::LoggedTagMap;
+pub use matcher::{MatchRuleRef, Matcher};
+pub use memory::MemoryReport;
+pub use namespace::{InNamespace, Namespaced};
+pub use parse::ParseRuleError;
+pub use pattern::{AnyPrefix, GreaterThan, InRange, LessThan, Prefix, SharedPattern, TagPattern};
+pub use persistent::PersistentTagMap;
+pub use pool::TagPool;
+pub use provenance::{FromSource, Sourced};
+pub use rank::{Scorer, TagOverlap};
+pub use similarity::jaccard;
+pub use snapshot::{CowTagMap, TagMapSnapshot};
+pub use sorted::SortedTagMap;
+pub use storage::{EntryFilter, Lookup, OccupiedStorage, Storage, TagList, VacantStorage};
+pub use vocabulary::{Resolved, Vocabulary};
+pub use watch::{ChangeEvent, MatchChange, WatchedTagMap};
+#[cfg(feature = "csv")]
+pub use csv::{CsvError, CsvFormat};
+#[cfg(feature = "glob")]
+pub use pattern::Glob;
+#[cfg(feature = "json")]
+pub use json::{JsonLinesReader, JsonLinesWriter};
+#[cfg(feature = "hierarchy")]
+pub use hierarchy::TagUnder;
+#[cfg(feature = "fuzzy")]
+pub use pattern::{Fuzzy, levenshtein};
+
+#[macro_use]
+mod macros;
+mod arena;
+mod binary;
+mod bitmask;
+mod cached;
+mod combine;
+mod compiled;
+mod concurrent;
+#[cfg(feature = "csv")]
+mod csv;
+mod diff;
+mod entry;
+mod expiring;
+mod hashed;
+#[cfg(feature = "hierarchy")]
+pub mod hierarchy;
+mod indexed;
+mod interned;
+#[cfg(feature = "json")]
+mod json;
+mod key_value;
+mod limits;
+mod logged;
+mod matcher;
+mod memory;
+mod namespace;
+pub mod parse;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod pattern;
+mod persistent;
+mod pool;
+mod provenance;
+mod rank;
+#[cfg(feature = "serde")]
+mod serialize;
+mod similarity;
+mod simplify;
+mod snapshot;
+mod sorted;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stats;
+mod storage;
+mod vocabulary;
+mod watch;
 
 /// A container that allows item lookup based on tag matching.
-#[derive(Debug)]
-pub struct TagMap<T: Ord, TAG: Eq> {
-    /// The inner BTreeMap used for the implementation.
-    pub entries: BTreeMap<T, Vec<TAG>>,
+///
+/// The entries are stored in a `BTreeMap` by default, keeping them ordered by key.
+/// See `HashTagMap` for a map storing them in a `HashMap` instead.
+pub struct TagMap<T, TAG: Eq, B = BTreeMap<T, Vec<TAG>>> {
+    entries: B,
+    vocabulary: Vocabulary<TAG>,
+    key: PhantomData<T>,
+}
+
+impl<T, TAG: Eq + fmt::Debug, B: fmt::Debug> fmt::Debug for TagMap<T, TAG, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TagMap")
+            .field("entries", &self.entries)
+            .field("vocabulary", &self.vocabulary)
+            .finish()
+    }
+}
+
+impl<T, TAG: Eq + Clone, B: Clone> Clone for TagMap<T, TAG, B> {
+    fn clone(&self) -> Self {
+        TagMap {
+            entries: self.entries.clone(),
+            vocabulary: self.vocabulary.clone(),
+            key: PhantomData,
+        }
+    }
+}
+
+impl<T, TAG: Eq, B: PartialEq> PartialEq for TagMap<T, TAG, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries && self.vocabulary == other.vocabulary
+    }
+}
+
+impl<T, TAG: Eq, B: Eq> Eq for TagMap<T, TAG, B> {}
+
+/// Iterator over the entries of a `TagMap`.
+#[derive(Clone)]
+pub struct Iter<'a, T: 'a, TAG: 'a, B: 'a + Storage<T, TAG> = BTreeMap<T, Vec<TAG>>> {
+    iter: B::Iter<'a>,
+}
+
+/// Mutable iterator over the entries of a `TagMap`.
+pub struct IterMut<'a, T: 'a, TAG: 'a, B: 'a + Storage<T, TAG> = BTreeMap<T, Vec<TAG>>> {
+    iter: B::IterMut<'a>,
+}
+
+/// Owning iterator over the entries of a `TagMap`.
+pub struct IntoIter<T, TAG, B: Storage<T, TAG> = BTreeMap<T, Vec<TAG>>> {
+    iter: B::IntoIter,
+}
+
+/// Iterator over the keys of a `TagMap`.
+#[derive(Clone)]
+pub struct Keys<'a, T: 'a, TAG: 'a, B: 'a + Storage<T, TAG> = BTreeMap<T, Vec<TAG>>> {
+    iter: B::Iter<'a>,
+}
+
+/// Iterator over the tags of the entries of a `TagMap`.
+#[derive(Clone)]
+pub struct Values<'a, T: 'a, TAG: 'a, B: 'a + Storage<T, TAG> = BTreeMap<T, Vec<TAG>>> {
+    iter: B::Iter<'a>,
 }
 
 /// Iterator over entries matching a rule.
 #[derive(Clone)]
-pub struct Matching<'hi, 'r, T: 'static, TAG: 'static> {
-    iter: Iter<'hi, T, Vec<TAG>>,
-    rule: &'r MatchRule<TAG>,
+pub struct Matching<'hi, 'r, T: 'hi, TAG: 'hi, R: 'r + ?Sized = MatchRule<TAG>,
+                    B = BTreeMap<T, Vec<TAG>>>
+    where B: 'hi + Storage<T, TAG>
+{
+    iter: B::Iter<'hi>,
+    rule: &'r R,
 }
 
 /// Iterator over entries matching a rule. Yields both T and its tags.
 #[derive(Clone)]
-pub struct MatchingEntries<'hi, 'r, T: 'static, TAG: 'static> {
-    iter: Iter<'hi, T, Vec<TAG>>,
-    rule: &'r MatchRule<TAG>,
+pub struct MatchingEntries<'hi, 'r, T: 'hi, TAG: 'hi, R: 'r + ?Sized = MatchRule<TAG>,
+                           B = BTreeMap<T, Vec<TAG>>>
+    where B: 'hi + Storage<T, TAG>
+{
+    iter: B::Iter<'hi>,
+    rule: &'r R,
+}
+
+/// Iterator over entries within a key range matching a rule.
+#[derive(Clone)]
+pub struct MatchingRange<'a, 'r, T: 'a, TAG: 'a, R: 'r + ?Sized = MatchRule<TAG>,
+                         L: 'a = Vec<TAG>> {
+    iter: btree_map::Range<'a, T, L>,
+    tag: PhantomData<TAG>,
+    rule: &'r R,
+}
+
+/// Iterator over entries matching a rule. Yields T and mutable access to its tags.
+pub struct MatchingTagsMut<'a, 'r, T: 'a, TAG: 'a, R: 'r + ?Sized = MatchRule<TAG>,
+                           B = BTreeMap<T, Vec<TAG>>>
+    where B: 'a + Storage<T, TAG>
+{
+    iter: B::IterMut<'a>,
+    rule: &'r R,
+}
+
+/// Owning iterator over entries matching a rule. Yields both T and its tags.
+pub struct IntoMatching<'r, T, TAG, R: 'r + ?Sized = MatchRule<TAG>, B = BTreeMap<T, Vec<TAG>>>
+    where B: Storage<T, TAG>
+{
+    iter: B::IntoIter,
+    rule: &'r R,
+}
+
+/// Draining iterator over entries matching a rule.
+///
+/// Matching entries are removed from the map as they are yielded. If the
+/// iterator is dropped early, the remaining matching entries are kept.
+pub struct DrainMatching<'a, T: 'a, TAG: 'a, B: 'a + Storage<T, TAG> = BTreeMap<T, Vec<TAG>>> {
+    inner: B::ExtractIf<'a>,
+}
+
+/// Removes repeated tags, keeping the first occurrence of each.
+fn dedup_tags<TAG: Eq, L: TagList<TAG>>(tags: &mut L) {
+    let mut i = 1;
+    while i < tags.len() {
+        if tags[..i].contains(&tags[i]) {
+            tags.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Replaces `from` by `to` in an entry's tags, returning whether it had `from`.
+fn rename_in<TAG, L>(tags: &mut L, from: &TAG, to: &TAG) -> bool
+    where TAG: Eq + Clone,
+          L: TagList<TAG>
+{
+    let pos = match tags.iter().position(|tag| tag == from) {
+        Some(pos) => pos,
+        None => return false,
+    };
+    if !tags.contains(to) {
+        tags[pos] = to.clone();
+    }
+    tags.retain(|tag| tag != from);
+    true
+}
+
+fn has_all<TAG: Eq>(tags: &[TAG], m_tags: &[TAG]) -> bool {
+    m_tags.iter().all(|m_tag| tags.contains(m_tag))
+}
+
+fn has_none<TAG: Eq>(tags: &[TAG], m_tags: &[TAG]) -> bool {
+    for m_tag in m_tags {
+        for tag in tags {
+            if *tag == *m_tag {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn has_any<TAG: Eq>(tags: &[TAG], m_tags: &[TAG]) -> bool {
+    for m_tag in m_tags {
+        for tag in tags {
+            if *tag == *m_tag {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Counts the distinct listed tags that are present, stopping once `limit` are found.
+fn count_present<TAG: Eq>(tags: &[TAG], m_tags: &[TAG], limit: usize) -> usize {
+    let mut count = 0;
+    for (i, m_tag) in m_tags.iter().enumerate() {
+        if count == limit {
+            break;
+        }
+        if tags.contains(m_tag) && !m_tags[..i].contains(m_tag) {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// A subrule of `Rules`, `NotRules` or `AnyRule`.
+trait Subrule<TAG>: Matcher<TAG> {
+    /// Returns whether the rule only looks at listed tags, making it cheap to evaluate.
+    fn is_shallow(&self) -> bool;
+}
+
+impl<TAG: Eq> Subrule<TAG> for MatchRule<TAG> {
+    fn is_shallow(&self) -> bool {
+        use MatchRule::*;
+        matches!(*self, Tags(_) | NotTags(_) | AnyTag(_) | AtLeast(..) | Exactly(..) |
+                        AtMost(..) | Anything | Nothing)
+    }
+}
+
+// Shallow subrules are evaluated first, so nested rules, predicates and patterns
+// are skipped when a listed tag already decides the result.
+fn all_match<TAG, R: Subrule<TAG>>(tags: &[TAG], rules: &[R]) -> bool {
+    rules.iter().filter(|rule| rule.is_shallow()).all(|rule| rule.matches(tags)) &&
+    rules.iter().filter(|rule| !rule.is_shallow()).all(|rule| rule.matches(tags))
+}
+
+fn any_match<TAG, R: Subrule<TAG>>(tags: &[TAG], rules: &[R]) -> bool {
+    rules.iter().filter(|rule| rule.is_shallow()).any(|rule| rule.matches(tags)) ||
+    rules.iter().filter(|rule| !rule.is_shallow()).any(|rule| rule.matches(tags))
+}
+
+fn one_matches<TAG, R: Matcher<TAG>>(tags: &[TAG], rules: &[R]) -> bool {
+    let mut count = 0;
+    for rule in rules {
+        if rule.matches(tags) {
+            count += 1;
+            if count > 1 {
+                return false;
+            }
+        }
+    }
+    count == 1
 }
 
 fn tags_match_rule<TAG: Eq>(tags: &[TAG], rule: &MatchRule<TAG>) -> bool {
     use MatchRule::*;
     match *rule {
-        Tags(ref m_tags) => {
-            let mut count = 0;
-            for m_tag in m_tags {
-                for tag in tags {
-                    if *tag == *m_tag {
-                        count += 1;
+        Tags(ref m_tags) => has_all(tags, m_tags),
+        NotTags(ref m_tags) => has_none(tags, m_tags),
+        AnyTag(ref m_tags) => has_any(tags, m_tags),
+        Rules(ref rules) => all_match(tags, rules),
+        NotRules(ref rules) => !any_match(tags, rules),
+        AnyRule(ref rules) => any_match(tags, rules),
+        Xor(ref rules) => one_matches(tags, rules),
+        AtLeast(n, ref m_tags) => count_present(tags, m_tags, n) >= n,
+        Exactly(n, ref m_tags) => count_present(tags, m_tags, n.saturating_add(1)) == n,
+        AtMost(n, ref m_tags) => count_present(tags, m_tags, n.saturating_add(1)) <= n,
+        Anything => true,
+        Nothing => false,
+        Not(ref rule) => !tags_match_rule(tags, rule),
+        Predicate(ref pred) => (pred.0)(tags),
+        Pattern(ref pattern) => tags.iter().any(|tag| pattern.matches_tag(tag)),
+    }
+}
+
+impl<'a, 'b, T, TAG, R, B> Iterator for Matching<'a, 'b, T, TAG, R, B>
+    where R: ?Sized + Matcher<TAG>,
+          B: Storage<T, TAG>
+{
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.iter.next() {
+                Some((v, tags)) => {
+                    if self.rule.matches(tags) {
+                        return Some(v);
+                    } else {
+                        continue;
                     }
                 }
+                None => return None,
             }
-            count == m_tags.len()
         }
-        NotTags(ref m_tags) => {
-            for m_tag in m_tags {
-                for tag in tags {
-                    if *tag == *m_tag {
-                        return false;
-                    }
-                }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<'a, 'b, T, TAG, R, B> DoubleEndedIterator for Matching<'a, 'b, T, TAG, R, B>
+    where R: ?Sized + Matcher<TAG>,
+          B: Storage<T, TAG>,
+          B::Iter<'a>: DoubleEndedIterator
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while let Some((v, tags)) = self.iter.next_back() {
+            if self.rule.matches(tags) {
+                return Some(v);
             }
-            true
         }
-        AnyTag(ref m_tags) => {
-            for m_tag in m_tags {
-                for tag in tags {
-                    if *tag == *m_tag {
-                        return true;
+        None
+    }
+}
+
+impl<'a, 'b, T, TAG, R, B> FusedIterator for Matching<'a, 'b, T, TAG, R, B>
+    where R: ?Sized + Matcher<TAG>,
+          B: Storage<T, TAG>,
+          B::Iter<'a>: FusedIterator
+{
+}
+
+impl<'a, 'b, T, TAG, R, B> Iterator for MatchingEntries<'a, 'b, T, TAG, R, B>
+    where R: ?Sized + Matcher<TAG>,
+          B: Storage<T, TAG>
+{
+    type Item = (&'a T, &'a [TAG]);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.iter.next() {
+                Some((v, tags)) => {
+                    if self.rule.matches(tags) {
+                        return Some((v, tags));
+                    } else {
+                        continue;
                     }
                 }
+                None => return None,
             }
-            false
         }
-        Rules(ref rules) => {
-            let mut count = 0;
-            for rule in rules {
-                if tags_match_rule(tags, rule) {
-                    count += 1;
-                }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<'a, 'b, T, TAG, R, B> DoubleEndedIterator for MatchingEntries<'a, 'b, T, TAG, R, B>
+    where R: ?Sized + Matcher<TAG>,
+          B: Storage<T, TAG>,
+          B::Iter<'a>: DoubleEndedIterator
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while let Some((v, tags)) = self.iter.next_back() {
+            if self.rule.matches(tags) {
+                return Some((v, tags));
             }
-            count == rules.len()
         }
-        NotRules(ref rules) => {
-            for rule in rules {
-                if tags_match_rule(tags, rule) {
-                    return false;
+        None
+    }
+}
+
+impl<'a, 'b, T, TAG, R, B> FusedIterator for MatchingEntries<'a, 'b, T, TAG, R, B>
+    where R: ?Sized + Matcher<TAG>,
+          B: Storage<T, TAG>,
+          B::Iter<'a>: FusedIterator
+{
+}
+
+impl<'a, 'r, T, TAG, R, L> Iterator for MatchingRange<'a, 'r, T, TAG, R, L>
+    where R: ?Sized + Matcher<TAG>,
+          L: TagList<TAG>
+{
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.iter.next() {
+                Some((v, tags)) => {
+                    if self.rule.matches(tags) {
+                        return Some(v);
+                    } else {
+                        continue;
+                    }
                 }
+                None => return None,
             }
-            true
         }
-        AnyRule(ref rules) => {
-            for rule in rules {
-                if tags_match_rule(tags, rule) {
-                    return true;
-                }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<'a, 'r, T, TAG, R, L> DoubleEndedIterator for MatchingRange<'a, 'r, T, TAG, R, L>
+    where R: ?Sized + Matcher<TAG>,
+          L: TagList<TAG>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while let Some((v, tags)) = self.iter.next_back() {
+            if self.rule.matches(tags) {
+                return Some(v);
             }
-            false
         }
+        None
     }
 }
 
-impl<'a, 'b, T: 'a, TAG: 'a + Eq> Iterator for Matching<'a, 'b, T, TAG> {
-    type Item = &'a T;
+impl<'a, 'r, T, TAG, R, L> FusedIterator for MatchingRange<'a, 'r, T, TAG, R, L>
+    where R: ?Sized + Matcher<TAG>,
+          L: TagList<TAG>
+{
+}
+
+impl<'a, 'r, T, TAG, R, B> Iterator for MatchingTagsMut<'a, 'r, T, TAG, R, B>
+    where R: ?Sized + Matcher<TAG>,
+          B: Storage<T, TAG>
+{
+    type Item = (&'a T, &'a mut B::List);
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             match self.iter.next() {
                 Some((v, tags)) => {
-                    if tags_match_rule(tags, self.rule) {
-                        return Some(v);
+                    if self.rule.matches(tags) {
+                        return Some((v, tags));
                     } else {
                         continue;
                     }
@@ -104,15 +520,43 @@ impl<'a, 'b, T: 'a, TAG: 'a + Eq> Iterator for Matching<'a, 'b, T, TAG> {
             }
         }
     }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
 }
 
-impl<'a, 'b, T: 'a, TAG: 'a + Eq> Iterator for MatchingEntries<'a, 'b, T, TAG> {
-    type Item = (&'a T, &'a [TAG]);
+impl<'a, 'r, T, TAG, R, B> DoubleEndedIterator for MatchingTagsMut<'a, 'r, T, TAG, R, B>
+    where R: ?Sized + Matcher<TAG>,
+          B: Storage<T, TAG>,
+          B::IterMut<'a>: DoubleEndedIterator
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while let Some((v, tags)) = self.iter.next_back() {
+            if self.rule.matches(tags) {
+                return Some((v, tags));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, 'r, T, TAG, R, B> FusedIterator for MatchingTagsMut<'a, 'r, T, TAG, R, B>
+    where R: ?Sized + Matcher<TAG>,
+          B: Storage<T, TAG>,
+          B::IterMut<'a>: FusedIterator
+{
+}
+
+impl<'r, T, TAG, R, B> Iterator for IntoMatching<'r, T, TAG, R, B>
+    where R: ?Sized + Matcher<TAG>,
+          B: Storage<T, TAG>
+{
+    type Item = (T, B::List);
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             match self.iter.next() {
                 Some((v, tags)) => {
-                    if tags_match_rule(tags, self.rule) {
+                    if self.rule.matches(&tags) {
                         return Some((v, tags));
                     } else {
                         continue;
@@ -122,33 +566,588 @@ impl<'a, 'b, T: 'a, TAG: 'a + Eq> Iterator for MatchingEntries<'a, 'b, T, TAG> {
             }
         }
     }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<'r, T, TAG, R, B> DoubleEndedIterator for IntoMatching<'r, T, TAG, R, B>
+    where R: ?Sized + Matcher<TAG>,
+          B: Storage<T, TAG>,
+          B::IntoIter: DoubleEndedIterator
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while let Some((v, tags)) = self.iter.next_back() {
+            if self.rule.matches(&tags) {
+                return Some((v, tags));
+            }
+        }
+        None
+    }
+}
+
+impl<'r, T, TAG, R, B> FusedIterator for IntoMatching<'r, T, TAG, R, B>
+    where R: ?Sized + Matcher<TAG>,
+          B: Storage<T, TAG>,
+          B::IntoIter: FusedIterator
+{
+}
+
+impl<'a, T, TAG, B: Storage<T, TAG>> Iterator for Iter<'a, T, TAG, B> {
+    type Item = (&'a T, &'a [TAG]);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(k, tags)| (k, &tags[..]))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, T, TAG, B> DoubleEndedIterator for Iter<'a, T, TAG, B>
+    where B: Storage<T, TAG>,
+          B::Iter<'a>: DoubleEndedIterator
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.iter.next_back().map(|(k, tags)| (k, &tags[..]))
+    }
+}
+
+impl<'a, T, TAG, B: Storage<T, TAG>> ExactSizeIterator for Iter<'a, T, TAG, B> {}
+
+impl<'a, T, TAG, B: Storage<T, TAG>> Iterator for IterMut<'a, T, TAG, B> {
+    type Item = (&'a T, &'a mut B::List);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, T, TAG, B> DoubleEndedIterator for IterMut<'a, T, TAG, B>
+    where B: Storage<T, TAG>,
+          B::IterMut<'a>: DoubleEndedIterator
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.iter.next_back()
+    }
+}
+
+impl<'a, T, TAG, B: Storage<T, TAG>> ExactSizeIterator for IterMut<'a, T, TAG, B> {}
+
+impl<T, TAG, B: Storage<T, TAG>> Iterator for IntoIter<T, TAG, B> {
+    type Item = (T, B::List);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<T, TAG, B> DoubleEndedIterator for IntoIter<T, TAG, B>
+    where B: Storage<T, TAG>,
+          B::IntoIter: DoubleEndedIterator
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.iter.next_back()
+    }
+}
+
+impl<T, TAG, B: Storage<T, TAG>> ExactSizeIterator for IntoIter<T, TAG, B> {}
+
+impl<'a, T, TAG, B: Storage<T, TAG>> Iterator for Keys<'a, T, TAG, B> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(k, _)| k)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, T, TAG, B> DoubleEndedIterator for Keys<'a, T, TAG, B>
+    where B: Storage<T, TAG>,
+          B::Iter<'a>: DoubleEndedIterator
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.iter.next_back().map(|(k, _)| k)
+    }
+}
+
+impl<'a, T, TAG, B: Storage<T, TAG>> ExactSizeIterator for Keys<'a, T, TAG, B> {}
+
+impl<'a, T, TAG, B: Storage<T, TAG>> Iterator for Values<'a, T, TAG, B> {
+    type Item = &'a [TAG];
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(_, tags)| &tags[..])
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, T, TAG, B> DoubleEndedIterator for Values<'a, T, TAG, B>
+    where B: Storage<T, TAG>,
+          B::Iter<'a>: DoubleEndedIterator
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.iter.next_back().map(|(_, tags)| &tags[..])
+    }
+}
+
+impl<'a, T, TAG, B: Storage<T, TAG>> ExactSizeIterator for Values<'a, T, TAG, B> {}
+
+impl<'a, T, TAG, B: Storage<T, TAG>> Iterator for DrainMatching<'a, T, TAG, B> {
+    type Item = (T, B::List);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
 }
 
 impl<T: Ord, TAG: Eq> TagMap<T, TAG> {
     /// Creates a new empty TagMap.
     pub fn new() -> Self {
-        TagMap { entries: BTreeMap::new() }
+        TagMap {
+            entries: BTreeMap::new(),
+            vocabulary: Vocabulary::new(),
+            key: PhantomData,
+        }
+    }
+    /// Creates a map from entries sorted by key.
+    ///
+    /// The tree is built in a single pass, instead of being rebalanced after each
+    /// insertion. Unsorted entries are accepted, but are sorted first. If a key is
+    /// repeated, its last tags are kept.
+    pub fn from_sorted_iter<I: IntoIterator<Item = (T, Vec<TAG>)>>(iter: I) -> Self {
+        // `BTreeMap` builds its tree bottom-up when collecting, after a sort that is
+        // linear for sorted input
+        iter.into_iter().collect()
+    }
+}
+
+impl<T: Ord, TAG: Eq, L: TagList<TAG>> TagMap<T, TAG, BTreeMap<T, L>> {
+    /// Returns the first entry, in key order, matching the given rule.
+    pub fn first_matching<R: ?Sized + Matcher<TAG>>(&self, rule: &R) -> Option<&T> {
+        self.iter().find(|&(_, tags)| rule.matches(tags)).map(|(k, _)| k)
+    }
+    /// Returns the entries matching the given rule, from the largest key down.
+    pub fn matching_rev<'s, 'r, R>(&'s self,
+                                   rule: &'r R)
+                                   -> Rev<Matching<'s, 'r, T, TAG, R, BTreeMap<T, L>>>
+        where R: ?Sized + Matcher<TAG>
+    {
+        self.matching(rule).rev()
+    }
+    /// Returns the entries with keys in the given range matching the given rule.
+    ///
+    /// Only the entries within the range are scanned.
+    pub fn matching_in_range<'s, 'r, Q, B, R>(&'s self,
+                                              range: B,
+                                              rule: &'r R)
+                                              -> MatchingRange<'s, 'r, T, TAG, R, L>
+        where Q: ?Sized + Ord,
+              T: Borrow<Q>,
+              B: RangeBounds<Q>,
+              R: ?Sized + Matcher<TAG>
+    {
+        MatchingRange {
+            iter: self.entries.range(range),
+            tag: PhantomData,
+            rule,
+        }
+    }
+}
+
+impl<T, TAG: Eq, B: Storage<T, TAG>> TagMap<T, TAG, B> {
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    /// Returns whether the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Removes all entries from the map.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+    /// Returns an iterator over the entries, in key order unless stored in a `HashMap`.
+    pub fn iter(&self) -> Iter<'_, T, TAG, B> {
+        Iter { iter: self.entries.iter() }
+    }
+    /// Returns a mutable iterator over the entries, in the same order as `iter`.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, TAG, B> {
+        IterMut { iter: self.entries.iter_mut() }
+    }
+    /// Returns an iterator over the keys, in the same order as `iter`.
+    pub fn keys(&self) -> Keys<'_, T, TAG, B> {
+        Keys { iter: self.entries.iter() }
+    }
+    /// Returns an iterator over the tags of each entry, in the same order as `iter`.
+    pub fn values(&self) -> Values<'_, T, TAG, B> {
+        Values { iter: self.entries.iter() }
+    }
+    /// Inserts an entry with the given tags.
+    ///
+    /// If the key was already present, its old tags are replaced and returned.
+    pub fn insert(&mut self, key: T, tags: Vec<TAG>) -> Option<B::List> {
+        self.entries.insert(key, B::List::from_vec(tags))
+    }
+    /// Removes an entry, returning its tags if it was present.
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<B::List>
+        where B: Lookup<Q, T, TAG>
+    {
+        self.entries.remove(key)
+    }
+    /// Returns the tags of an entry, if it is present.
+    pub fn get_tags<Q: ?Sized>(&self, key: &Q) -> Option<&[TAG]>
+        where B: Lookup<Q, T, TAG>
+    {
+        self.entries.get(key).map(|tags| &tags[..])
+    }
+    /// Returns whether the map contains an entry for the given key.
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+        where B: Lookup<Q, T, TAG>
+    {
+        self.entries.get(key).is_some()
+    }
+    /// Returns whether the entry for the given key has the given tag.
+    pub fn has_tag<Q: ?Sized>(&self, key: &Q, tag: &TAG) -> bool
+        where B: Lookup<Q, T, TAG>
+    {
+        self.entries.get(key).is_some_and(|tags| tags.contains(tag))
+    }
+    /// Returns the number of tags of an entry, if it is present.
+    pub fn tag_count<Q: ?Sized>(&self, key: &Q) -> Option<usize>
+        where B: Lookup<Q, T, TAG>
+    {
+        self.entries.get(key).map(|tags| tags.len())
+    }
+    /// Gets the entry for the given key for in-place manipulation.
+    pub fn entry(&mut self, key: T) -> Entry<'_, T, TAG, B> {
+        self.entries.entry(key)
+    }
+    /// Adds a tag to an entry, inserting the entry if it's not present.
+    ///
+    /// Returns false if the entry already had the tag.
+    pub fn add_tag(&mut self, key: T, tag: TAG) -> bool {
+        let tags = self.entry(key).or_default();
+        if tags.contains(&tag) {
+            false
+        } else {
+            tags.push(tag);
+            true
+        }
+    }
+    /// Adds multiple tags to an entry, inserting the entry if it's not present.
+    ///
+    /// Tags the entry already has are skipped.
+    pub fn add_tags<I: IntoIterator<Item = TAG>>(&mut self, key: T, tags: I) {
+        let entry_tags = self.entry(key).or_default();
+        for tag in tags {
+            if !entry_tags.contains(&tag) {
+                entry_tags.push(tag);
+            }
+        }
+    }
+    /// Removes a tag from an entry.
+    ///
+    /// Returns false if the entry is not present or didn't have the tag.
+    pub fn remove_tag<Q: ?Sized>(&mut self, key: &Q, tag: &TAG) -> bool
+        where B: Lookup<Q, T, TAG>
+    {
+        match self.entries.get_mut(key) {
+            Some(tags) => {
+                let len = tags.len();
+                tags.retain(|t| t != tag);
+                tags.len() != len
+            }
+            None => false,
+        }
     }
     /// Returns the entries matching the given rule.
-    pub fn matching<'s, 'r>(&'s self, rule: &'r MatchRule<TAG>) -> Matching<'s, 'r, T, TAG> {
+    pub fn matching<'s, 'r, R>(&'s self, rule: &'r R) -> Matching<'s, 'r, T, TAG, R, B>
+        where R: ?Sized + Matcher<TAG>
+    {
         Matching {
             iter: self.entries.iter(),
-            rule: rule,
+            rule,
         }
     }
     /// Returns the entries matching the given rule. Yields both T and its tags.
-    pub fn matching_entries<'s, 'r>(&'s self,
-                                    rule: &'r MatchRule<TAG>)
-                                    -> MatchingEntries<'s, 'r, T, TAG> {
+    pub fn matching_entries<'s, 'r, R>(&'s self,
+                                       rule: &'r R)
+                                       -> MatchingEntries<'s, 'r, T, TAG, R, B>
+        where R: ?Sized + Matcher<TAG>
+    {
         MatchingEntries {
             iter: self.entries.iter(),
-            rule: rule,
+            rule,
+        }
+    }
+    /// Returns the number of entries matching the given rule.
+    pub fn count_matching<R: ?Sized + Matcher<TAG>>(&self, rule: &R) -> usize {
+        self.values().filter(|tags| rule.matches(tags)).count()
+    }
+    /// Returns whether any entry matches the given rule.
+    ///
+    /// Stops scanning at the first match.
+    pub fn any_matching<R: ?Sized + Matcher<TAG>>(&self, rule: &R) -> bool {
+        self.values().any(|tags| rule.matches(tags))
+    }
+    /// Returns the keys of the entries without tags, in the same order as `iter`.
+    pub fn untagged(&self) -> impl Iterator<Item = &T> {
+        self.iter().filter(|&(_, tags)| tags.is_empty()).map(|(k, _)| k)
+    }
+    /// Returns the keys of the entries lacking at least one of the given tags, in the same
+    /// order as `iter`.
+    pub fn entries_missing<'a>(&'a self, tags: &'a [TAG]) -> impl Iterator<Item = &'a T> + 'a {
+        self.iter().filter(move |&(_, entry_tags)| !has_all(entry_tags, tags)).map(|(k, _)| k)
+    }
+    /// Returns the entries matching the given rule, with mutable access to their tags.
+    pub fn matching_tags_mut<'s, 'r, R>(&'s mut self,
+                                        rule: &'r R)
+                                        -> MatchingTagsMut<'s, 'r, T, TAG, R, B>
+        where R: ?Sized + Matcher<TAG>
+    {
+        MatchingTagsMut {
+            iter: self.entries.iter_mut(),
+            rule,
+        }
+    }
+    /// Consumes the map, yielding the entries matching the given rule.
+    pub fn into_matching<R>(self, rule: &R) -> IntoMatching<'_, T, TAG, R, B>
+        where R: ?Sized + Matcher<TAG>
+    {
+        IntoMatching {
+            iter: self.entries.into_entries(),
+            rule,
+        }
+    }
+    /// Keeps only the entries matching the given rule.
+    pub fn retain_matching<R: ?Sized + Matcher<TAG>>(&mut self, rule: &R) {
+        self.entries.retain(|_, tags| rule.matches(tags));
+    }
+    /// Removes the entries matching the given rule, yielding them.
+    pub fn drain_matching<'s, R>(&'s mut self, rule: &'s R) -> DrainMatching<'s, T, TAG, B>
+        where R: ?Sized + Matcher<TAG>
+    {
+        let filter: EntryFilter<T, B::List> = Box::new(move |_, tags| rule.matches(tags));
+        DrainMatching { inner: self.entries.extract_if(filter) }
+    }
+    /// Removes the entries matching the given rule, returning them.
+    pub fn remove_matching<R: ?Sized + Matcher<TAG>>(&mut self, rule: &R) -> Vec<(T, B::List)> {
+        self.drain_matching(rule).collect()
+    }
+    /// Moves the entries matching the given rule into a new map, without cloning them.
+    ///
+    /// The new map has an empty vocabulary.
+    pub fn split_off_matching<R: ?Sized + Matcher<TAG>>(&mut self, rule: &R) -> Self
+        where B: FromIterator<(T, B::List)>
+    {
+        TagMap {
+            entries: self.drain_matching(rule).collect(),
+            vocabulary: Vocabulary::new(),
+            key: PhantomData,
+        }
+    }
+    /// Returns a new map with clones of the entries matching the given rule, and of
+    /// the vocabulary.
+    pub fn subset<R: ?Sized + Matcher<TAG>>(&self, rule: &R) -> Self
+        where T: Clone,
+              TAG: Clone,
+              B: FromIterator<(T, B::List)>,
+              B::List: Clone
+    {
+        TagMap {
+            entries: self.entries
+                .iter()
+                .filter(|&(_, tags)| rule.matches(tags))
+                .map(|(key, tags)| (key.clone(), tags.clone()))
+                .collect(),
+            vocabulary: self.vocabulary.clone(),
+            key: PhantomData,
+        }
+    }
+    /// Adds a tag to every entry matching the given rule.
+    ///
+    /// Returns the number of entries that didn't have the tag yet.
+    pub fn add_tag_to_matching<R: ?Sized + Matcher<TAG>>(&mut self, rule: &R, tag: TAG) -> usize
+        where TAG: Clone
+    {
+        let mut count = 0;
+        for (_, tags) in self.entries.iter_mut() {
+            if rule.matches(tags) && !tags.contains(&tag) {
+                tags.push(tag.clone());
+                count += 1;
+            }
+        }
+        count
+    }
+    /// Removes a tag from every entry matching the given rule.
+    ///
+    /// Returns the number of entries the tag was removed from.
+    pub fn remove_tag_from_matching<R>(&mut self, rule: &R, tag: &TAG) -> usize
+        where R: ?Sized + Matcher<TAG>
+    {
+        let mut count = 0;
+        for (_, tags) in self.entries.iter_mut() {
+            if rule.matches(tags) {
+                let len = tags.len();
+                tags.retain(|t| t != tag);
+                if tags.len() != len {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+    /// Removes a tag from every entry, returning the number of entries that had it.
+    pub fn remove_tag_everywhere(&mut self, tag: &TAG) -> usize {
+        let mut count = 0;
+        for (_, tags) in self.entries.iter_mut() {
+            let len = tags.len();
+            tags.retain(|t| t != tag);
+            if tags.len() != len {
+                count += 1;
+            }
         }
+        count
+    }
+    /// Replaces a tag by another in every entry, returning the number of entries changed.
+    ///
+    /// Entries that already have the new tag only lose the old one.
+    pub fn rename_tag(&mut self, from: &TAG, to: TAG) -> usize
+        where TAG: Clone
+    {
+        if *from == to {
+            return 0;
+        }
+        let mut count = 0;
+        for (_, tags) in self.entries.iter_mut() {
+            if rename_in(tags, from, &to) {
+                count += 1;
+            }
+        }
+        count
+    }
+    /// Removes duplicate tags within entries, and frees the unused capacity of the map
+    /// and the tag lists.
+    pub fn compact(&mut self) {
+        for (_, tags) in self.entries.iter_mut() {
+            dedup_tags(tags);
+            tags.shrink_to_fit();
+        }
+        self.entries.shrink_to_fit();
+    }
+    /// Replaces every tag of every entry by the result of `f`.
+    ///
+    /// Tags that become equal to an earlier tag of the same entry are removed.
+    pub fn map_tags<F: FnMut(TAG) -> TAG>(&mut self, mut f: F) {
+        self.filter_map_tags(|tag| Some(f(tag)));
+    }
+    /// Replaces every tag of every entry by the result of `f`, removing the tags for
+    /// which it returns `None`.
+    ///
+    /// Tags that become equal to an earlier tag of the same entry are removed.
+    pub fn filter_map_tags<F: FnMut(TAG) -> Option<TAG>>(&mut self, mut f: F) {
+        for (_, tags) in self.entries.iter_mut() {
+            let old = std::mem::take(tags);
+            *tags = old.into_iter().filter_map(&mut f).collect();
+            dedup_tags(tags);
+        }
+    }
+    /// Returns the vocabulary of the map.
+    pub fn vocabulary(&self) -> &Vocabulary<TAG> {
+        &self.vocabulary
+    }
+    /// Returns the vocabulary of the map mutably.
+    pub fn vocabulary_mut(&mut self) -> &mut Vocabulary<TAG> {
+        &mut self.vocabulary
+    }
+    /// Registers tags as aliases of each other in the vocabulary of the map.
+    pub fn add_alias_group<I: IntoIterator<Item = TAG>>(&mut self, tags: I) {
+        self.vocabulary.add_alias_group(tags);
+    }
+    /// Registers an implication in the vocabulary of the map.
+    ///
+    /// See `Vocabulary::add_implication`.
+    pub fn add_implication(&mut self, from: TAG, to: TAG) -> bool {
+        self.vocabulary.add_implication(from, to)
+    }
+    /// Wraps a rule, so it takes the vocabulary of the map into account.
+    pub fn resolve<'a, R: ?Sized + Matcher<TAG>>(&'a self, rule: &'a R) -> Resolved<'a, TAG, R> {
+        self.vocabulary.resolve(rule)
+    }
+}
+
+impl<T, TAG: Eq, B: Default> Default for TagMap<T, TAG, B> {
+    fn default() -> Self {
+        TagMap {
+            entries: B::default(),
+            vocabulary: Vocabulary::new(),
+            key: PhantomData,
+        }
+    }
+}
+
+impl<T, TAG, B> FromIterator<(T, Vec<TAG>)> for TagMap<T, TAG, B>
+    where TAG: Eq,
+          B: Storage<T, TAG> + FromIterator<(T, B::List)>
+{
+    fn from_iter<I: IntoIterator<Item = (T, Vec<TAG>)>>(iter: I) -> Self {
+        TagMap {
+            entries: iter.into_iter().map(|(key, tags)| (key, B::List::from_vec(tags))).collect(),
+            vocabulary: Vocabulary::new(),
+            key: PhantomData,
+        }
+    }
+}
+
+impl<T, TAG: Eq, B: Storage<T, TAG>> IntoIterator for TagMap<T, TAG, B> {
+    type Item = (T, B::List);
+    type IntoIter = IntoIter<T, TAG, B>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { iter: self.entries.into_entries() }
+    }
+}
+
+impl<'a, T, TAG: Eq, B: Storage<T, TAG>> IntoIterator for &'a TagMap<T, TAG, B> {
+    type Item = (&'a T, &'a [TAG]);
+    type IntoIter = Iter<'a, T, TAG, B>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T, TAG: Eq, B: Storage<T, TAG>> IntoIterator for &'a mut TagMap<T, TAG, B> {
+    type Item = (&'a T, &'a mut B::List);
+    type IntoIter = IterMut<'a, T, TAG, B>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T, TAG, B> Extend<(T, Vec<TAG>)> for TagMap<T, TAG, B>
+    where TAG: Eq,
+          B: Storage<T, TAG> + Extend<(T, B::List)>
+{
+    fn extend<I: IntoIterator<Item = (T, Vec<TAG>)>>(&mut self, iter: I) {
+        self.entries.extend(iter.into_iter().map(|(key, tags)| (key, B::List::from_vec(tags))));
     }
 }
 
 /// A rule of how to match against tags.
-#[derive(Debug, PartialEq)]
+///
+/// Both the tags of an entry and the tags listed in a rule are treated as
+/// sets: a listed tag is present if the entry has it at least once, and
+/// duplicates on either side are ignored. For example, `AtLeast(2, vec!["a", "a"])`
+/// never matches, because it only lists one distinct tag.
+#[derive(Debug, Clone, PartialEq)]
 pub enum MatchRule<TAG> {
     /// Match all given tags.
     Tags(Vec<TAG>),
@@ -162,30 +1161,249 @@ pub enum MatchRule<TAG> {
     NotRules(Vec<MatchRule<TAG>>),
     /// Match any given rule.
     AnyRule(Vec<MatchRule<TAG>>),
+    /// Match exactly one of the given rules.
+    Xor(Vec<MatchRule<TAG>>),
+    /// Match at least the given number of the given tags.
+    AtLeast(usize, Vec<TAG>),
+    /// Match exactly the given number of the given tags.
+    Exactly(usize, Vec<TAG>),
+    /// Match at most the given number of the given tags.
+    AtMost(usize, Vec<TAG>),
+    /// Match every entry.
+    Anything,
+    /// Match no entry.
+    Nothing,
+    /// Don't match the given rule.
+    Not(Box<MatchRule<TAG>>),
+    /// Match if the predicate returns true for the tags.
+    Predicate(TagPredicate<TAG>),
+    /// Match if any tag matches the pattern.
+    Pattern(SharedPattern<TAG>),
+}
+
+impl<TAG> MatchRule<TAG> {
+    /// Creates a rule matching all of the given tags.
+    pub fn all_of<I: IntoIterator<Item = TAG>>(tags: I) -> Self {
+        MatchRule::Tags(tags.into_iter().collect())
+    }
+    /// Creates a rule matching any of the given tags.
+    pub fn any_of<I: IntoIterator<Item = TAG>>(tags: I) -> Self {
+        MatchRule::AnyTag(tags.into_iter().collect())
+    }
+    /// Creates a rule matching none of the given tags.
+    pub fn none_of<I: IntoIterator<Item = TAG>>(tags: I) -> Self {
+        MatchRule::NotTags(tags.into_iter().collect())
+    }
+    /// Combines this rule with another, matching if both match.
+    pub fn and(self, other: Self) -> Self {
+        match self {
+            MatchRule::Rules(mut rules) => {
+                rules.push(other);
+                MatchRule::Rules(rules)
+            }
+            rule => MatchRule::Rules(vec![rule, other]),
+        }
+    }
+    /// Combines this rule with another, matching if either matches.
+    pub fn or(self, other: Self) -> Self {
+        match self {
+            MatchRule::AnyRule(mut rules) => {
+                rules.push(other);
+                MatchRule::AnyRule(rules)
+            }
+            rule => MatchRule::AnyRule(vec![rule, other]),
+        }
+    }
+    /// Creates a `Pattern` rule.
+    pub fn pattern<P: TagPattern<TAG> + 'static>(pattern: P) -> Self {
+        MatchRule::Pattern(SharedPattern::new(pattern))
+    }
+    /// Creates a `Predicate` rule from a closure.
+    pub fn predicate<F>(f: F) -> Self
+        where F: Fn(&[TAG]) -> bool + Send + Sync + 'static
+    {
+        MatchRule::Predicate(TagPredicate::new(f))
+    }
+}
+
+impl<TAG: AsRef<str>> MatchRule<TAG> {
+    /// Creates a rule matching if any tag starts with the given prefix.
+    pub fn tag_prefix(prefix: &str) -> Self {
+        MatchRule::pattern(Prefix::new(prefix))
+    }
+    /// Creates a rule matching if any tag starts with any of the given prefixes.
+    pub fn any_prefix<I: IntoIterator>(prefixes: I) -> Self
+        where I::Item: Into<String>
+    {
+        MatchRule::pattern(AnyPrefix::new(prefixes))
+    }
+}
+
+#[cfg(feature = "glob")]
+impl<TAG: AsRef<str>> MatchRule<TAG> {
+    /// Creates a rule matching if any tag matches the given glob pattern.
+    ///
+    /// See `Glob` for the supported syntax.
+    pub fn glob(pattern: &str) -> Self {
+        MatchRule::pattern(Glob::new(pattern))
+    }
+}
+
+#[cfg(feature = "fuzzy")]
+impl<TAG: AsRef<str>> MatchRule<TAG> {
+    /// Creates a rule matching if any tag is within the given edit distance of `tag`.
+    pub fn fuzzy(tag: &str, max_distance: usize) -> Self {
+        MatchRule::pattern(Fuzzy::new(tag, max_distance))
+    }
+}
+
+#[cfg(feature = "hierarchy")]
+impl<TAG: AsRef<str>> MatchRule<TAG> {
+    /// Creates a rule matching if any tag is under the given path.
+    ///
+    /// See the `hierarchy` module for details.
+    pub fn tag_under(path: &str) -> Self {
+        MatchRule::pattern(TagUnder::new(path))
+    }
+}
+
+#[cfg(feature = "regex")]
+impl<TAG: AsRef<str>> MatchRule<TAG> {
+    /// Creates a rule matching if any tag matches the given regex.
+    pub fn regex(regex: regex::Regex) -> Self {
+        MatchRule::pattern(regex)
+    }
+}
+
+impl<TAG: Ord + fmt::Debug + Send + Sync + 'static> MatchRule<TAG> {
+    /// Creates a rule matching if any tag is greater than the given tag.
+    pub fn tag_greater_than(tag: TAG) -> Self {
+        MatchRule::pattern(GreaterThan(tag))
+    }
+    /// Creates a rule matching if any tag is less than the given tag.
+    pub fn tag_less_than(tag: TAG) -> Self {
+        MatchRule::pattern(LessThan(tag))
+    }
+}
+
+impl<TAG: Ord + Clone + fmt::Debug + Send + Sync + 'static> MatchRule<TAG> {
+    /// Creates a rule matching if any tag falls within the given range.
+    pub fn tag_in_range<B: RangeBounds<TAG>>(range: B) -> Self {
+        MatchRule::pattern(InRange::new(range))
+    }
+}
+
+impl<TAG, S> MatchRule<Sourced<TAG, S>>
+    where TAG: Eq + fmt::Debug + Send + Sync + 'static,
+          S: Eq + fmt::Debug + Send + Sync + 'static
+{
+    /// Creates a rule matching if the given tag was applied by the given source.
+    pub fn tag_from_source(tag: TAG, source: S) -> Self {
+        MatchRule::pattern(FromSource::new(tag, source))
+    }
+}
+
+impl<TAG: Namespaced> MatchRule<TAG> {
+    /// Creates a rule matching if any tag is in the given namespace.
+    pub fn in_namespace(namespace: &str) -> Self {
+        MatchRule::pattern(InNamespace::new(namespace))
+    }
+}
+
+impl<TAG: KeyValueTag> MatchRule<TAG> {
+    /// Creates a rule matching if any tag has the given key and a value.
+    pub fn has_key(key: &str) -> Self {
+        MatchRule::pattern(HasKey::new(key))
+    }
+    /// Creates a rule matching if any tag has the given key and value.
+    pub fn key_equals(key: &str, value: &str) -> Self {
+        MatchRule::pattern(KeyEquals::new(key, value))
+    }
+    /// Creates a rule matching if any tag has the given key, and any of the given values.
+    pub fn key_in<I: IntoIterator>(key: &str, values: I) -> Self
+        where I::Item: Into<String>
+    {
+        MatchRule::pattern(KeyIn::new(key, values))
+    }
+}
+
+impl<TAG> std::ops::BitAnd for MatchRule<TAG> {
+    type Output = Self;
+    fn bitand(self, other: Self) -> Self {
+        self.and(other)
+    }
+}
+
+impl<TAG> std::ops::BitOr for MatchRule<TAG> {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        self.or(other)
+    }
+}
+
+impl<TAG> std::ops::Not for MatchRule<TAG> {
+    type Output = Self;
+    fn not(self) -> Self {
+        MatchRule::Not(Box::new(self))
+    }
+}
+
+/// A user-supplied test over the tags of an entry, used by `MatchRule::Predicate`.
+///
+/// Predicates compare equal only if they are clones of each other.
+pub struct TagPredicate<TAG>(Arc<PredicateFn<TAG>>);
+
+type PredicateFn<TAG> = dyn Fn(&[TAG]) -> bool + Send + Sync;
+
+impl<TAG> TagPredicate<TAG> {
+    /// Creates a new predicate from a closure.
+    pub fn new<F>(f: F) -> Self
+        where F: Fn(&[TAG]) -> bool + Send + Sync + 'static
+    {
+        TagPredicate(Arc::new(f))
+    }
+}
+
+impl<TAG> Clone for TagPredicate<TAG> {
+    fn clone(&self) -> Self {
+        TagPredicate(self.0.clone())
+    }
+}
+
+impl<TAG> PartialEq for TagPredicate<TAG> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<TAG> fmt::Debug for TagPredicate<TAG> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("TagPredicate(..)")
+    }
 }
 
 #[test]
 fn test() {
     use MatchRule::*;
     let mut map = TagMap::new();
-    map.entries.insert("elephant",
-                       vec!["mammal", "herbivore", "large", "intelligent", "friendly"]);
-    map.entries.insert("mouse",
-                       vec!["mammal", "herbivore", "small", "furry", "neutral"]);
-    map.entries.insert("snake",
-                       vec!["reptile", "carnivore", "poisonous", "hostile"]);
-    map.entries.insert("shark", vec!["fish", "carnivore", "large", "hostile"]);
-    map.entries.insert("human",
-                       vec!["mammal", "omnivore", "intelligent", "friendly", "primate"]);
-    map.entries.insert("lion",
-                       vec!["feline", "mammal", "carnivore", "hostile", "furry"]);
-    map.entries.insert("dog",
-                       vec!["canine", "mammal", "carnivore", "friendly", "furry"]);
-    map.entries.insert("chimpanzee",
-                       vec!["mammal", "primate", "neutral", "omnivore", "furry"]);
-    map.entries.insert("goldfish", vec!["fish", "friendly"]);
-    map.entries.insert("carp", vec!["fish", "neutral"]);
-    map.entries.insert("blowfish", vec!["fish", "poisonous"]);
+    map.insert("elephant",
+               vec!["mammal", "herbivore", "large", "intelligent", "friendly"]);
+    map.insert("mouse",
+               vec!["mammal", "herbivore", "small", "furry", "neutral"]);
+    map.insert("snake",
+               vec!["reptile", "carnivore", "poisonous", "hostile"]);
+    map.insert("shark", vec!["fish", "carnivore", "large", "hostile"]);
+    map.insert("human",
+               vec!["mammal", "omnivore", "intelligent", "friendly", "primate"]);
+    map.insert("lion",
+               vec!["feline", "mammal", "carnivore", "hostile", "furry"]);
+    map.insert("dog",
+               vec!["canine", "mammal", "carnivore", "friendly", "furry"]);
+    map.insert("chimpanzee",
+               vec!["mammal", "primate", "neutral", "omnivore", "furry"]);
+    map.insert("goldfish", vec!["fish", "friendly"]);
+    map.insert("carp", vec!["fish", "neutral"]);
+    map.insert("blowfish", vec!["fish", "poisonous"]);
     macro_rules! check {
         ($tags:expr, $expected:expr) => {{
             let mut v: Vec<_> = map.matching($tags).collect();
@@ -198,11 +1416,18 @@ fn test() {
     check!(&Tags(vec!["mammal"]),
            [&"human", &"elephant", &"mouse", &"dog", &"lion", &"chimpanzee"]);
     check!(&Tags(vec!["carnivore", "mammal", "friendly"]), [&"dog"]);
+    assert_eq!(map.count_matching(&Tags(vec!["fish"])), 4);
+    assert_eq!(map.first_matching(&Tags(vec!["furry"])), Some(&"chimpanzee"));
+    assert!(!map.any_matching(&Tags(vec!["fish", "mammal"])));
     check!(&NotTags(vec!["mammal"]),
            [&"snake", &"shark", &"goldfish", &"carp", &"blowfish"]);
     check!(&Rules(vec![Tags(vec!["fish"]), NotTags(vec!["poisonous"])]),
            [&"goldfish", &"carp", &"shark"]);
     check!(&AnyTag(vec!["canine", "reptile"]), [&"dog", &"snake"]);
+    assert_eq!(map.matching(&Tags(vec!["fish"])).next_back(), Some(&"shark"));
+    assert_eq!(map.matching(&Tags(vec!["fish"])).rev().nth(1), Some(&"goldfish"));
+    assert_eq!(map.matching_in_range("c".."h", &Tags(vec!["mammal"])).collect::<Vec<_>>(),
+               [&"chimpanzee", &"dog", &"elephant"]);
     check!(&AnyRule(vec![
             Rules(vec![
                 Tags(vec!["carnivore"]), NotTags(vec!["friendly"]),
@@ -211,5 +1436,389 @@ fn test() {
                 Tags(vec!["fish"]), AnyTag(vec!["friendly", "neutral", "poisonous"])
             ]),
         ]),
-           [&"shark", &"lion", &"goldfish", &"carp", &"blowfish", &"snake"])
+           [&"shark", &"lion", &"goldfish", &"carp", &"blowfish", &"snake"]);
+    check!(&Xor(vec![Tags(vec!["carnivore"]), Tags(vec!["furry"])]),
+           [&"snake", &"shark", &"mouse", &"chimpanzee"]);
+    check!(&AtLeast(2, vec!["furry", "friendly", "primate"]),
+           [&"human", &"dog", &"chimpanzee"]);
+    check!(&Rules(vec![Tags(vec!["fish"]),
+                       Exactly(1, vec!["friendly", "neutral", "hostile"])]),
+           [&"goldfish", &"carp", &"shark"]);
+    check!(&Rules(vec![Tags(vec!["mammal"]), AtMost(1, vec!["furry", "friendly", "primate"])]),
+           [&"elephant", &"mouse", &"lion"]);
+    assert_eq!(map.count_matching(&Anything), map.len());
+    assert_eq!(map.count_matching(&Nothing), 0);
+    check!(&Rules(vec![Tags(vec!["fish"]), Not(Box::new(AnyTag(vec!["friendly", "neutral"])))]),
+           [&"shark", &"blowfish"]);
+    check!(&MatchRule::predicate(|tags| tags.len() == 2), [&"goldfish", &"carp", &"blowfish"]);
+    check!(&MatchRule::tag_prefix("pri"), [&"human", &"chimpanzee"]);
+    check!(&MatchRule::any_prefix(vec!["can", "fel"]), [&"dog", &"lion"]);
+    check!(&MatchRule::all_of(vec!["fish"]).and(!MatchRule::any_of(vec!["friendly", "neutral"])),
+           [&"shark", &"blowfish"]);
+    check!(&MatchRule::none_of(vec!["mammal", "fish"]).or(MatchRule::all_of(vec!["canine"])),
+           [&"snake", &"dog"]);
+    check!(&rule!("carnivore" & !"friendly" | "fish" & ("friendly" | "neutral" | "poisonous")),
+           [&"shark", &"lion", &"goldfish", &"carp", &"blowfish", &"snake"]);
+    {
+        use MatchRuleRef as R;
+        static FISH: [R<&str>; 2] = [R::Tags(&["fish"]), R::NotTags(&["poisonous"])];
+        check!(&R::Rules(&FISH), [&"goldfish", &"carp", &"shark"]);
+    }
+}
+
+#[test]
+fn test_insert_remove() {
+    let mut map = TagMap::new();
+    assert_eq!(map.insert("dog", vec!["mammal"]), None);
+    assert_eq!(map.insert("dog", vec!["mammal", "canine"]).as_deref(),
+               Some(&["mammal"][..]));
+    assert_eq!(map.get_tags("dog"), Some(&["mammal", "canine"][..]));
+    map.insert("cat", vec!["mammal"]);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.keys().collect::<Vec<_>>(), [&"cat", &"dog"]);
+    assert_eq!(map.remove("dog").as_deref(), Some(&["mammal", "canine"][..]));
+    assert_eq!(map.get_tags("dog"), None);
+    assert_eq!(map.remove("dog"), None);
+}
+
+#[test]
+fn test_add_remove_tag() {
+    let mut map = TagMap::new();
+    assert!(map.add_tag("dog", "mammal"));
+    assert!(!map.add_tag("dog", "mammal"));
+    map.add_tags("dog", vec!["canine", "mammal", "furry"]);
+    assert_eq!(map.get_tags("dog"), Some(&["mammal", "canine", "furry"][..]));
+    assert!(map.remove_tag("dog", &"canine"));
+    assert!(!map.remove_tag("dog", &"canine"));
+    assert!(!map.remove_tag("cat", &"mammal"));
+    assert_eq!(map.get_tags("dog"), Some(&["mammal", "furry"][..]));
+}
+
+#[test]
+fn test_entry() {
+    let mut map = TagMap::new();
+    map.entry("dog").or_default().push("canine");
+    map.entry("dog").or_insert_tags(vec!["feline"]).push("mammal");
+    map.entry("cat")
+        .and_modify_tags(|tags| tags.push("unreachable"))
+        .or_insert_tags(vec!["feline"]);
+    assert_eq!(map.get_tags("dog"), Some(&["canine", "mammal"][..]));
+    assert_eq!(map.get_tags("cat"), Some(&["feline"][..]));
+    match map.entry("cat") {
+        Entry::Occupied(e) => {
+            let (key, tags) = e.remove_entry();
+            assert_eq!((key, &tags[..]), ("cat", &["feline"][..]));
+        }
+        Entry::Vacant(_) => panic!("cat should be present"),
+    }
+}
+
+#[test]
+fn test_drain_matching() {
+    use MatchRule::*;
+    let mut map = TagMap::new();
+    map.insert("draft", vec!["temporary"]);
+    map.insert("report", vec!["final"]);
+    map.insert("scratch", vec!["temporary", "old"]);
+    let rule = Tags(vec!["temporary"]);
+    let drained: Vec<_> = map.drain_matching(&rule).map(|(k, tags)| (k, tags.to_vec())).collect();
+    assert_eq!(drained,
+               [("draft", vec!["temporary"]), ("scratch", vec!["temporary", "old"])]);
+    assert_eq!(map.get_tags("report"), Some(&["final"][..]));
+    assert_eq!(map.get_tags("draft"), None);
+    map.insert("old", vec!["archived"]);
+    let archived = map.split_off_matching(&Tags(vec!["archived"]));
+    assert_eq!(archived.keys().collect::<Vec<_>>(), [&"old"]);
+    assert_eq!(map.keys().collect::<Vec<_>>(), [&"report"]);
+    let subset = archived.subset(&Anything);
+    assert_eq!(subset, archived);
+    assert!(archived.subset(&Tags(vec!["final"])).is_empty());
+}
+
+#[test]
+fn test_map_tags() {
+    let strings = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+    let mut map = TagMap::new();
+    map.insert(1, strings(&["Red", "red", "Big"]));
+    map.insert(2, strings(&["old:blue"]));
+    map.map_tags(|tag| tag.to_lowercase());
+    assert_eq!(map.get_tags(&1), Some(&strings(&["red", "big"])[..]));
+    map.filter_map_tags(|tag| if tag.starts_with("old:") { None } else { Some(tag) });
+    assert_eq!(map.tag_count(&2), Some(0));
+    let mut indexed = IndexedTagMap::from(map);
+    indexed.map_tags(|tag| tag.to_uppercase());
+    assert_eq!(indexed.keys_with_tag(&"RED".to_string()), [&1]);
+    assert_eq!(indexed.count_with_tag(&"red".to_string()), 0);
+}
+
+#[test]
+fn test_rename_tag() {
+    let mut map = TagMap::new();
+    map.insert(1, vec!["sci-fi", "book", "sci-fi"]);
+    map.insert(2, vec!["science-fiction", "sci-fi"]);
+    map.insert(3, vec!["film"]);
+    let mut indexed = IndexedTagMap::from(map.clone());
+    assert_eq!(map.rename_tag(&"sci-fi", "science-fiction"), 2);
+    assert_eq!(map.get_tags(&1), Some(&["science-fiction", "book"][..]));
+    assert_eq!(map.get_tags(&2), Some(&["science-fiction"][..]));
+    assert_eq!(map.rename_tag(&"sci-fi", "science-fiction"), 0);
+    assert_eq!(indexed.rename_tag(&"sci-fi", "science-fiction"), 2);
+    assert_eq!(*indexed, map);
+    assert_eq!(indexed.keys_with_tag(&"science-fiction"), [&1, &2]);
+    assert_eq!(indexed.count_with_tag(&"sci-fi"), 0);
+    assert_eq!(map.remove_tag_everywhere(&"science-fiction"), 2);
+    assert_eq!(map.remove_tag_everywhere(&"science-fiction"), 0);
+    assert_eq!(indexed.remove_tag_everywhere(&"science-fiction"), 2);
+    assert_eq!(*indexed, map);
+    assert!(indexed.keys_with_tag(&"science-fiction").is_empty());
+}
+
+#[test]
+fn test_untagged() {
+    let mut map = TagMap::new();
+    map.insert(1, vec!["a", "b"]);
+    map.insert(2, vec![]);
+    map.insert(3, vec!["b"]);
+    map.remove_tag(&3, &"b");
+    map.insert(4, vec!["c", "a"]);
+    assert_eq!(map.untagged().collect::<Vec<_>>(), [&2, &3]);
+    assert_eq!(map.entries_missing(&["a", "b"]).collect::<Vec<_>>(), [&2, &3, &4]);
+    assert_eq!(map.entries_missing(&[]).count(), 0);
+}
+
+#[test]
+fn test_bulk_tag_edits() {
+    use MatchRule::*;
+    let mut map = TagMap::new();
+    map.insert("a", vec!["2023"]);
+    map.insert("b", vec!["2023", "archived"]);
+    map.insert("c", vec!["2024"]);
+    assert_eq!(map.add_tag_to_matching(&Tags(vec!["2023"]), "archived"), 1);
+    assert_eq!(map.get_tags("a"), Some(&["2023", "archived"][..]));
+    assert_eq!(map.remove_tag_from_matching(&AnyTag(vec!["2023", "2024"]), &"archived"),
+               2);
+    assert_eq!(map.get_tags("b"), Some(&["2023"][..]));
+}
+
+#[test]
+fn test_into_iter() {
+    let mut map: TagMap<_, _> = vec![("b", vec![2]), ("a", vec![1])].into_iter().collect();
+    let orig = map.clone();
+    assert_eq!(map, orig);
+    for (_, tags) in &mut map {
+        tags.push(0);
+    }
+    for (_, tags) in &map {
+        assert_eq!(tags.last(), Some(&0));
+    }
+    assert!(map != orig);
+    assert_eq!(map.into_iter().map(|(k, tags)| (k, tags.to_vec())).collect::<Vec<_>>(),
+               [("a", vec![1, 0]), ("b", vec![2, 0])]);
+}
+
+#[test]
+fn test_matching_tags_mut() {
+    use MatchRule::*;
+    let mut map = TagMap::new();
+    map.insert(1, vec!["Fish", "large"]);
+    map.insert(2, vec!["fish"]);
+    map.insert(3, vec!["Bird"]);
+    for (_, tags) in map.matching_tags_mut(&AnyTag(vec!["Fish", "Bird"])) {
+        for tag in tags.iter_mut() {
+            if *tag == "Fish" {
+                *tag = "fish";
+            }
+        }
+    }
+    let fish = Tags(vec!["fish"]);
+    let matching = map.into_matching(&fish).map(|(k, tags)| (k, tags.to_vec()));
+    assert_eq!(matching.collect::<Vec<_>>(),
+               [(1, vec!["fish", "large"]), (2, vec!["fish"])]);
+}
+
+#[test]
+fn test_set_semantics() {
+    use MatchRule::*;
+    let mut map = TagMap::new();
+    map.insert("dup", vec!["a", "a"]);
+    map.insert("ab", vec!["a", "b"]);
+    let matches = |rule: MatchRule<&'static str>| map.matching(&rule).cloned().collect::<Vec<_>>();
+    assert_eq!(matches(Tags(vec!["a"])), ["ab", "dup"]);
+    assert_eq!(matches(Tags(vec!["a", "a"])), ["ab", "dup"]);
+    assert_eq!(matches(Tags(vec!["a", "b", "b"])), ["ab"]);
+    assert_eq!(matches(Exactly(1, vec!["a", "a"])), ["ab", "dup"]);
+    assert_eq!(matches(AtLeast(2, vec!["a", "a"])), [] as [&str; 0]);
+    assert_eq!(matches(AtMost(1, vec!["a", "a", "b"])), ["dup"]);
+}
+
+#[test]
+fn test_compact() {
+    use MatchRule::*;
+    let mut map = TagMap::new();
+    map.insert("a", Vec::with_capacity(64));
+    map.add_tags("a", vec!["x", "y"]);
+    map.insert("b", vec!["y", "x", "y", "z", "x"]);
+    let before = map.memory_usage().tags;
+    map.compact();
+    assert!(map.memory_usage().tags < before);
+    assert_eq!(map.get_tags("b"), Some(&["y", "x", "z"][..]));
+    let mut indexed = IndexedTagMap::from(map.clone());
+    indexed.remove("a");
+    indexed.insert("c", vec!["z", "z"]);
+    indexed.compact();
+    assert_eq!(indexed.get_tags("c"), Some(&["z"][..]));
+    assert_eq!(indexed.matching(&Tags(vec!["z"])).collect::<Vec<_>>(), [&"b", &"c"]);
+}
+
+#[test]
+fn test_short_circuit() {
+    use MatchRule::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let pred = MatchRule::predicate(move |_: &[&str]| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        true
+    });
+    let tags = ["a", "b", "c"];
+    assert!(!Rules(vec![pred.clone(), Tags(vec!["x"])]).matches(&tags));
+    assert!(AnyRule(vec![pred.clone(), AnyTag(vec!["a"])]).matches(&tags));
+    assert!(!NotRules(vec![Not(Box::new(pred.clone())), Tags(vec!["b"])]).matches(&tags));
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+    assert!(Rules(vec![pred, Tags(vec!["a"])]).matches(&tags));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert!(AtLeast(2, vec!["a", "a", "b", "x"]).matches(&tags));
+    assert!(!Exactly(1, vec!["a", "b"]).matches(&tags));
+    assert!(AtMost(usize::MAX, vec!["a"]).matches(&tags));
+    assert!(!AtMost(1, vec!["c", "b", "a"]).matches(&tags));
+}
+
+#[test]
+fn test_retain_matching() {
+    use MatchRule::*;
+    let mut map = TagMap::new();
+    map.insert(1, vec!["temporary"]);
+    map.insert(2, vec!["kept"]);
+    map.insert(3, vec!["temporary", "kept"]);
+    map.retain_matching(&Tags(vec!["kept"]));
+    assert_eq!(map.keys().collect::<Vec<_>>(), [&2, &3]);
+    assert_eq!(map.get_tags(&3), Some(&["temporary", "kept"][..]));
+    map.retain_matching(&Nothing);
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_remove_matching() {
+    use MatchRule::*;
+    let mut map = TagMap::new();
+    map.insert(1, vec!["temporary"]);
+    map.insert(2, vec!["kept"]);
+    map.insert(3, vec!["temporary", "old"]);
+    let removed = map.remove_matching(&Tags(vec!["temporary"]));
+    let removed: Vec<_> = removed.into_iter().map(|(k, tags)| (k, tags.to_vec())).collect();
+    assert_eq!(removed, [(1, vec!["temporary"]), (3, vec!["temporary", "old"])]);
+    assert_eq!(map.keys().collect::<Vec<_>>(), [&2]);
+    assert!(map.remove_matching(&Tags(vec!["temporary"])).is_empty());
+    assert_eq!(map.len(), 1);
+}
+
+#[test]
+fn test_entry_queries() {
+    let mut map = TagMap::new();
+    map.insert("dog", vec!["mammal", "canine"]);
+    map.insert("rock", vec![]);
+    assert!(map.contains_key("dog"));
+    assert!(!map.contains_key("cat"));
+    assert!(map.has_tag("dog", &"canine"));
+    assert!(!map.has_tag("dog", &"feline"));
+    assert!(!map.has_tag("cat", &"mammal"));
+    assert_eq!(map.tag_count("dog"), Some(2));
+    assert_eq!(map.tag_count("rock"), Some(0));
+    assert_eq!(map.tag_count("cat"), None);
+}
+
+#[test]
+fn test_from_iter_extend() {
+    let mut map: TagMap<_, _> = vec![(2, vec!["b"]), (1, vec!["a"])].into_iter().collect();
+    assert_eq!(map.keys().collect::<Vec<_>>(), [&1, &2]);
+    map.extend(vec![(3, vec!["c"]), (1, vec!["z"])]);
+    assert_eq!(map.len(), 3);
+    assert_eq!(map.get_tags(&1), Some(&["z"][..]));
+    assert_eq!(map.get_tags(&3), Some(&["c"][..]));
+}
+
+#[test]
+fn test_matching_size_hint() {
+    use MatchRule::*;
+    let map: TagMap<_, _> = (0..5).map(|i| (i, vec![i % 2])).collect();
+    let rule = Tags(vec![0]);
+    let mut matching = map.matching(&rule);
+    assert_eq!(matching.size_hint(), (0, Some(5)));
+    assert_eq!(matching.next(), Some(&0));
+    assert_eq!(matching.size_hint(), (0, Some(4)));
+    assert_eq!(map.matching_entries(&rule).size_hint(), (0, Some(5)));
+    assert_eq!(map.matching(&rule).collect::<Vec<_>>(), [&0, &2, &4]);
+}
+
+#[test]
+fn test_matching_rev() {
+    use MatchRule::*;
+    let map: TagMap<_, _> = (0..10).map(|i| (i, vec!["x"; (i % 3 == 0) as usize])).collect();
+    for rule in &[Tags(vec!["x"]), NotTags(vec!["x"]), Anything, Nothing] {
+        let mut forward: Vec<_> = map.matching(rule).collect();
+        forward.reverse();
+        assert_eq!(map.matching_rev(rule).collect::<Vec<_>>(), forward, "{:?}", rule);
+    }
+    assert_eq!(map.matching_rev(&Tags(vec!["x"])).take(2).collect::<Vec<_>>(), [&9, &6]);
+}
+
+#[test]
+fn test_not_rule() {
+    use MatchRule::*;
+    let rule = Not(Box::new(AnyTag(vec!["a", "b"])));
+    let tag_lists: [&[&str]; 4] = [&[], &["a"], &["c"], &["b", "c"]];
+    for tags in &tag_lists {
+        assert_eq!(rule.matches(tags), !AnyTag(vec!["a", "b"]).matches(tags));
+        assert_eq!(rule.matches(tags), NotRules(vec![AnyTag(vec!["a", "b"])]).matches(tags));
+        assert_eq!(Not(Box::new(rule.clone())).matches(tags),
+                   AnyTag(vec!["a", "b"]).matches(tags));
+    }
+    assert!(rule.matches(&["c"]));
+    assert!(!rule.matches(&["c", "b"]));
+}
+
+#[test]
+fn test_predicate() {
+    use MatchRule::*;
+    let mut map = TagMap::new();
+    map.insert(1, vec!["lang:en", "book"]);
+    map.insert(2, vec!["lang:fr"]);
+    map.insert(3, vec!["book", "film"]);
+    let lang = MatchRule::predicate(|tags: &[&str]| {
+        tags.iter().any(|tag| tag.starts_with("lang:"))
+    });
+    assert_eq!(map.matching(&lang).collect::<Vec<_>>(), [&1, &2]);
+    let several = MatchRule::predicate(|tags: &[&str]| tags.len() > 1);
+    let rule = Rules(vec![Tags(vec!["book"]), Not(Box::new(lang)), several.clone()]);
+    assert_eq!(map.matching(&rule).collect::<Vec<_>>(), [&3]);
+    assert_eq!(several, several.clone());
+    assert!(several != MatchRule::predicate(|tags: &[&str]| tags.len() > 1));
+}
+
+#[test]
+fn test_rule_combinators() {
+    use MatchRule::*;
+    let (a, b, c) = (Tags(vec!["a"]), AnyTag(vec!["b"]), NotTags(vec!["c"]));
+    assert_eq!(MatchRule::all_of(vec!["a"]), a);
+    assert_eq!(MatchRule::any_of(vec!["b"]), b);
+    assert_eq!(MatchRule::none_of(vec!["c"]), c);
+    assert_eq!(a.clone().and(b.clone()).and(c.clone()),
+               Rules(vec![a.clone(), b.clone(), c.clone()]));
+    assert_eq!(a.clone().or(b.clone()).or(c.clone()),
+               AnyRule(vec![a.clone(), b.clone(), c.clone()]));
+    assert_eq!(!a.clone(), Not(Box::new(a.clone())));
+    assert_eq!(a.clone() & b.clone() | !c.clone(),
+               AnyRule(vec![Rules(vec![a.clone(), b.clone()]), Not(Box::new(c))]));
+    assert!((a & b).matches(&["a", "b"]));
 }
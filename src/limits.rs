@@ -0,0 +1,177 @@
+//! Size limits for rules from untrusted input.
+
+use std::error::Error;
+use std::fmt;
+use {MatchRule, count_present, has_all, has_any, has_none};
+
+/// Limits on the size of a `MatchRule`.
+///
+/// Used to reject rules from untrusted input, like parsed queries, that would
+/// be too expensive to evaluate, or deep enough to overflow the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum nesting depth. A rule without sub-rules has a depth of 1.
+    pub max_depth: usize,
+    /// The maximum number of rules, counting sub-rules.
+    pub max_nodes: usize,
+    /// The maximum number of tags listed in the rule and its sub-rules.
+    pub max_tags: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: 32,
+            max_nodes: 1024,
+            max_tags: 4096,
+        }
+    }
+}
+
+/// An error from a rule exceeding its `Limits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitError {
+    /// The rule is nested too deeply.
+    TooDeep,
+    /// The rule has too many sub-rules.
+    TooManyNodes,
+    /// The rule lists too many tags.
+    TooManyTags,
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            LimitError::TooDeep => "rule is nested too deeply",
+            LimitError::TooManyNodes => "rule has too many sub-rules",
+            LimitError::TooManyTags => "rule lists too many tags",
+        })
+    }
+}
+
+impl Error for LimitError {}
+
+impl<TAG> MatchRule<TAG> {
+    /// Checks that the rule is within the given limits.
+    ///
+    /// This doesn't recurse, so it's safe to call on arbitrarily deep rules.
+    pub fn validate(&self, limits: &Limits) -> Result<(), LimitError> {
+        use MatchRule::*;
+        let (mut nodes, mut tag_count) = (0, 0);
+        let mut stack = vec![(self, 1)];
+        while let Some((rule, depth)) = stack.pop() {
+            if depth > limits.max_depth {
+                return Err(LimitError::TooDeep);
+            }
+            nodes += 1;
+            if nodes > limits.max_nodes {
+                return Err(LimitError::TooManyNodes);
+            }
+            match *rule {
+                Tags(ref tags) | NotTags(ref tags) | AnyTag(ref tags) | AtLeast(_, ref tags) |
+                Exactly(_, ref tags) | AtMost(_, ref tags) => tag_count += tags.len(),
+                Rules(ref rules) | NotRules(ref rules) | AnyRule(ref rules) | Xor(ref rules) => {
+                    stack.extend(rules.iter().map(|rule| (rule, depth + 1)))
+                }
+                Not(ref rule) => stack.push((rule, depth + 1)),
+                Anything | Nothing | Predicate(_) | Pattern(_) => {}
+            }
+            if tag_count > limits.max_tags {
+                return Err(LimitError::TooManyTags);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<TAG: Eq> MatchRule<TAG> {
+    /// Returns whether the given tags match, failing if the rule is nested
+    /// deeper than `limits.max_depth`.
+    ///
+    /// Unlike `validate`, only the parts of the rule that are evaluated are checked.
+    pub fn try_matches(&self, tags: &[TAG], limits: &Limits) -> Result<bool, LimitError> {
+        matches_checked(tags, self, limits.max_depth)
+    }
+}
+
+fn matches_checked<TAG: Eq>(tags: &[TAG],
+                            rule: &MatchRule<TAG>,
+                            depth_left: usize)
+                            -> Result<bool, LimitError> {
+    use MatchRule::*;
+    if depth_left == 0 {
+        return Err(LimitError::TooDeep);
+    }
+    let any = |rules: &[MatchRule<TAG>]| -> Result<bool, LimitError> {
+        for rule in rules {
+            if matches_checked(tags, rule, depth_left - 1)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    };
+    Ok(match *rule {
+        Tags(ref m_tags) => has_all(tags, m_tags),
+        NotTags(ref m_tags) => has_none(tags, m_tags),
+        AnyTag(ref m_tags) => has_any(tags, m_tags),
+        Rules(ref rules) => {
+            for rule in rules {
+                if !matches_checked(tags, rule, depth_left - 1)? {
+                    return Ok(false);
+                }
+            }
+            true
+        }
+        NotRules(ref rules) => !any(rules)?,
+        AnyRule(ref rules) => any(rules)?,
+        Xor(ref rules) => {
+            let mut count = 0;
+            for rule in rules {
+                if matches_checked(tags, rule, depth_left - 1)? {
+                    count += 1;
+                    if count > 1 {
+                        return Ok(false);
+                    }
+                }
+            }
+            count == 1
+        }
+        AtLeast(n, ref m_tags) => count_present(tags, m_tags, n) >= n,
+        Exactly(n, ref m_tags) => count_present(tags, m_tags, n.saturating_add(1)) == n,
+        AtMost(n, ref m_tags) => count_present(tags, m_tags, n.saturating_add(1)) <= n,
+        Anything => true,
+        Nothing => false,
+        Not(ref rule) => !matches_checked(tags, rule, depth_left - 1)?,
+        Predicate(ref pred) => (pred.0)(tags),
+        Pattern(ref pattern) => tags.iter().any(|tag| pattern.matches_tag(tag)),
+    })
+}
+
+#[test]
+fn test_limits() {
+    use MatchRule::*;
+    let limits = Limits {
+        max_depth: 3,
+        max_nodes: 5,
+        max_tags: 4,
+    };
+    let ok = Rules(vec![Tags(vec!["a", "b"]), Not(Box::new(AnyTag(vec!["c", "d"])))]);
+    assert_eq!(ok.validate(&limits), Ok(()));
+    assert_eq!(ok.try_matches(&["a", "b"], &limits), Ok(true));
+    let deep = Not(Box::new(Not(Box::new(Not(Box::new(Tags(vec!["a"])))))));
+    assert_eq!(deep.validate(&limits), Err(LimitError::TooDeep));
+    assert_eq!(deep.try_matches(&["a"], &limits), Err(LimitError::TooDeep));
+    let wide = AnyRule(vec![Anything, Anything, Anything, Anything, Anything]);
+    assert_eq!(wide.validate(&limits), Err(LimitError::TooManyNodes));
+    assert_eq!(wide.try_matches(&["a"], &limits), Ok(true));
+    let many = AnyTag(vec!["a", "b", "c", "d", "e"]);
+    assert_eq!(many.validate(&limits), Err(LimitError::TooManyTags));
+    let mut huge = Tags(vec!["a"]);
+    for _ in 0..100_000 {
+        huge = Not(Box::new(huge));
+    }
+    assert_eq!(huge.validate(&Limits::default()), Err(LimitError::TooDeep));
+    assert_eq!(huge.try_matches(&["a"], &Limits::default()), Err(LimitError::TooDeep));
+    // Dropping the rule recurses as well, and would overflow the test thread's stack
+    ::std::mem::forget(huge);
+}
@@ -0,0 +1,307 @@
+//! A tag map persisted with a write-ahead log.
+
+use binary::{BinaryError, BinaryValue, read_len, write_len};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use TagMap;
+
+const INSERT: u8 = 0;
+const REMOVE: u8 = 1;
+const ADD_TAG: u8 = 2;
+const REMOVE_TAG: u8 = 3;
+const CLEAR: u8 = 4;
+
+/// A `TagMap` persisted to disk, appending each change to a log.
+///
+/// The map is stored as a binary snapshot, written by `TagMap::save_binary`, and a
+/// log of the changes made since. Opening the map loads the snapshot and replays the
+/// log. Compacting writes a new snapshot and empties the log, which happens
+/// automatically once the log has enough records if a threshold is set. An automatic
+/// compaction that fails doesn't fail the change that triggered it, since the change
+/// is already logged. Its error is kept for `take_compact_error`, and compacting is
+/// retried after the next change.
+///
+/// Each change is written to the log before it's applied, and by default synced to
+/// disk, so changes that returned successfully survive a crash. A record left
+/// incomplete by a crash is discarded when the log is replayed. The map can be read
+/// through `Deref`, but must be modified through the methods of this type.
+#[derive(Debug)]
+pub struct LoggedTagMap<T: Ord, TAG: Eq> {
+    map: TagMap<T, TAG>,
+    path: PathBuf,
+    log: File,
+    records: usize,
+    compact_threshold: Option<usize>,
+    compact_error: Option<io::Error>,
+    sync: bool,
+}
+
+impl<T, TAG> LoggedTagMap<T, TAG>
+    where T: Ord + BinaryValue,
+          TAG: Ord + Clone + BinaryValue
+{
+    /// Opens the map with its snapshot at `path`, and its log next to it, with `.log`
+    /// appended to the file name.
+    ///
+    /// Missing files are treated as empty, and the log is created.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, BinaryError> {
+        let path = path.as_ref().to_path_buf();
+        let map = match TagMap::load_from_path(&path) {
+            Err(BinaryError::Io(ref err)) if err.kind() == io::ErrorKind::NotFound => {
+                TagMap::new()
+            }
+            result => result?,
+        };
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(log_path(&path))?;
+        let mut logged = LoggedTagMap {
+            map,
+            path,
+            log: log.try_clone()?,
+            records: 0,
+            compact_threshold: None,
+            compact_error: None,
+            sync: true,
+        };
+        let mut data = Vec::new();
+        log.read_to_end(&mut data)?;
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let start = rest;
+            match logged.replay(&mut rest) {
+                Ok(()) => logged.records += 1,
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    log.set_len((data.len() - start.len()) as u64)?;
+                    break;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(logged)
+    }
+    /// Returns the underlying map.
+    pub fn into_inner(self) -> TagMap<T, TAG> {
+        self.map
+    }
+    /// Returns the number of records in the log.
+    pub fn log_records(&self) -> usize {
+        self.records
+    }
+    /// Sets the number of log records after which the map is compacted automatically.
+    ///
+    /// With `None`, the default, the map is only compacted by calling `compact`.
+    pub fn set_compact_threshold(&mut self, records: Option<usize>) {
+        self.compact_threshold = records;
+    }
+    /// Returns the error of the last automatic compaction that failed, if any, and
+    /// clears it.
+    pub fn take_compact_error(&mut self) -> Option<io::Error> {
+        self.compact_error.take()
+    }
+    /// Sets whether each record is synced to disk as it's written, which is the default.
+    ///
+    /// Without syncing, changes are faster, but recent ones can be lost in a crash.
+    pub fn set_sync(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+    /// Inserts an entry with the given tags.
+    ///
+    /// If the key was already present, its old tags are replaced and returned.
+    pub fn insert(&mut self, key: T, tags: Vec<TAG>) -> io::Result<Option<Vec<TAG>>> {
+        let mut record = vec![INSERT];
+        key.write_binary(&mut record)?;
+        write_len(&mut record, tags.len())?;
+        for tag in &tags {
+            tag.write_binary(&mut record)?;
+        }
+        self.append(&record)?;
+        let old = self.map.insert(key, tags);
+        self.after_append();
+        Ok(old)
+    }
+    /// Removes an entry, returning its tags if it was present.
+    pub fn remove(&mut self, key: &T) -> io::Result<Option<Vec<TAG>>> {
+        if !self.map.contains_key(key) {
+            return Ok(None);
+        }
+        let mut record = vec![REMOVE];
+        key.write_binary(&mut record)?;
+        self.append(&record)?;
+        let tags = self.map.remove(key);
+        self.after_append();
+        Ok(tags)
+    }
+    /// Adds a tag to an entry, inserting the entry if it's not present.
+    ///
+    /// Returns false if the entry already had the tag.
+    pub fn add_tag(&mut self, key: T, tag: TAG) -> io::Result<bool> {
+        if self.map.has_tag(&key, &tag) {
+            return Ok(false);
+        }
+        let mut record = vec![ADD_TAG];
+        key.write_binary(&mut record)?;
+        tag.write_binary(&mut record)?;
+        self.append(&record)?;
+        self.map.add_tag(key, tag);
+        self.after_append();
+        Ok(true)
+    }
+    /// Removes a tag from an entry.
+    ///
+    /// Returns false if the entry is not present or didn't have the tag.
+    pub fn remove_tag(&mut self, key: &T, tag: &TAG) -> io::Result<bool> {
+        if !self.map.has_tag(key, tag) {
+            return Ok(false);
+        }
+        let mut record = vec![REMOVE_TAG];
+        key.write_binary(&mut record)?;
+        tag.write_binary(&mut record)?;
+        self.append(&record)?;
+        self.map.remove_tag(key, tag);
+        self.after_append();
+        Ok(true)
+    }
+    /// Removes all entries from the map.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.append(&[CLEAR])?;
+        self.map.clear();
+        self.after_append();
+        Ok(())
+    }
+    /// Writes a snapshot of the map and empties the log.
+    ///
+    /// The snapshot is saved with `TagMap::save_to_path`, so a crash leaves either
+    /// snapshot in place. Replaying the log on top of the new
+    /// snapshot, if the crash happens before the log is emptied, gives the same map.
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.map.save_to_path(&self.path)?;
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        self.records = 0;
+        Ok(())
+    }
+    /// Applies the record at the start of `data`, advancing past it.
+    fn replay(&mut self, data: &mut &[u8]) -> io::Result<()> {
+        let op = u8::read_binary(data)?;
+        match op {
+            INSERT => {
+                let key = T::read_binary(data)?;
+                let len = read_len(data)?;
+                let mut tags = Vec::with_capacity(len.min(data.len()));
+                for _ in 0..len {
+                    tags.push(TAG::read_binary(data)?);
+                }
+                self.map.insert(key, tags);
+            }
+            REMOVE => {
+                self.map.remove(&T::read_binary(data)?);
+            }
+            ADD_TAG => {
+                let key = T::read_binary(data)?;
+                self.map.add_tag(key, TAG::read_binary(data)?);
+            }
+            REMOVE_TAG => {
+                let key = T::read_binary(data)?;
+                self.map.remove_tag(&key, &TAG::read_binary(data)?);
+            }
+            CLEAR => self.map.clear(),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid log record")),
+        }
+        Ok(())
+    }
+    /// Writes a record to the log.
+    ///
+    /// If writing fails, the log is truncated back to its previous length, so a partial
+    /// record isn't left for later records to follow.
+    fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        let len = self.log.metadata()?.len();
+        let mut result = self.log.write_all(record);
+        if result.is_ok() && self.sync {
+            result = self.log.sync_data();
+        }
+        if let Err(err) = result {
+            let _ = self.log.set_len(len);
+            return Err(err);
+        }
+        self.records += 1;
+        Ok(())
+    }
+    /// Compacts the map if the log has reached the threshold, keeping the error if
+    /// compacting fails.
+    fn after_append(&mut self) {
+        match self.compact_threshold {
+            Some(threshold) if self.records >= threshold => {
+                if let Err(err) = self.compact() {
+                    self.compact_error = Some(err);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns the path of the log of the snapshot at `path`.
+fn log_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".log");
+    PathBuf::from(name)
+}
+
+impl<T: Ord, TAG: Eq> Deref for LoggedTagMap<T, TAG> {
+    type Target = TagMap<T, TAG>;
+    fn deref(&self) -> &TagMap<T, TAG> {
+        &self.map
+    }
+}
+
+#[test]
+fn test_logged() {
+    use std::fs;
+    let dir = ::std::env::temp_dir().join(format!("tagmap-test-logged-{}", ::std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("map");
+    let open = || LoggedTagMap::<String, String>::open(&path).unwrap();
+    let mut map = open();
+    map.insert("a".to_string(), vec!["x".to_string(), "y".to_string()]).unwrap();
+    map.insert("b".to_string(), vec![]).unwrap();
+    assert!(map.add_tag("b".to_string(), "z".to_string()).unwrap());
+    assert!(!map.add_tag("b".to_string(), "z".to_string()).unwrap());
+    assert!(map.remove_tag(&"a".to_string(), &"x".to_string()).unwrap());
+    assert_eq!(map.log_records(), 4);
+    let expected = map.clone();
+    drop(map);
+    let mut map = open();
+    assert_eq!(*map, expected);
+    assert_eq!(map.log_records(), 4);
+    map.compact().unwrap();
+    assert_eq!(fs::metadata(log_path(&path)).unwrap().len(), 0);
+    map.set_compact_threshold(Some(2));
+    map.remove(&"a".to_string()).unwrap();
+    assert_eq!(map.log_records(), 1);
+    map.add_tag("c".to_string(), "w".to_string()).unwrap();
+    assert_eq!(map.log_records(), 0);
+    map.clear().unwrap();
+    drop(map);
+    // A record cut short by a crash is discarded
+    let mut log = OpenOptions::new().append(true).open(log_path(&path)).unwrap();
+    log.write_all(&[INSERT, 5, b'a']).unwrap();
+    let map = open();
+    assert!(map.is_empty());
+    assert_eq!(fs::metadata(log_path(&path)).unwrap().len(), 1);
+    // A failed automatic compaction doesn't fail the logged change
+    let blocked = dir.join("blocked");
+    let mut map = LoggedTagMap::<String, String>::open(&blocked).unwrap();
+    map.set_compact_threshold(Some(1));
+    fs::create_dir(&blocked).unwrap();
+    assert!(map.add_tag("a".to_string(), "x".to_string()).unwrap());
+    assert!(map.take_compact_error().is_some());
+    assert!(map.take_compact_error().is_none());
+    assert_eq!(map.log_records(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
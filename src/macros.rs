@@ -0,0 +1,36 @@
+/// Builds a `MatchRule` from a boolean expression over tags.
+///
+/// Tags are combined with `&`, `|` and `!` using the usual precedence, and can
+/// be grouped with parentheses. Each tag must be a single token, like a literal
+/// or a variable.
+///
+/// ```
+/// #[macro_use]
+/// extern crate tagmap;
+///
+/// use tagmap::MatchRule::*;
+///
+/// # fn main() {
+/// let rule = rule!("mammal" & !"hostile" | "fish");
+/// assert_eq!(rule,
+///            AnyRule(vec![Rules(vec![Tags(vec!["mammal"]),
+///                                    Not(Box::new(Tags(vec!["hostile"])))]),
+///                         Tags(vec!["fish"])]));
+/// # }
+/// ```
+#[macro_export]
+macro_rules! rule {
+    (@munch [$($out:tt)*]) => { $($out)* };
+    (@munch [$($out:tt)*] ($($inner:tt)+) $($rest:tt)*) => {
+        rule!(@munch [$($out)* (rule!($($inner)+))] $($rest)*)
+    };
+    (@munch [$($out:tt)*] & $($rest:tt)*) => { rule!(@munch [$($out)* &] $($rest)*) };
+    (@munch [$($out:tt)*] && $($rest:tt)*) => { rule!(@munch [$($out)* &] $($rest)*) };
+    (@munch [$($out:tt)*] | $($rest:tt)*) => { rule!(@munch [$($out)* |] $($rest)*) };
+    (@munch [$($out:tt)*] || $($rest:tt)*) => { rule!(@munch [$($out)* |] $($rest)*) };
+    (@munch [$($out:tt)*] ! $($rest:tt)*) => { rule!(@munch [$($out)* !] $($rest)*) };
+    (@munch [$($out:tt)*] $tag:tt $($rest:tt)*) => {
+        rule!(@munch [$($out)* $crate::MatchRule::Tags(vec![$tag])] $($rest)*)
+    };
+    ($($t:tt)+) => { rule!(@munch [] $($t)+) };
+}
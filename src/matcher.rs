@@ -0,0 +1,134 @@
+//! Rule evaluation, and borrowed rules.
+
+use {MatchRule, Subrule, all_match, any_match, count_present, has_all, has_any, has_none,
+     one_matches, tags_match_rule};
+
+/// Something that can decide whether a list of tags matches.
+///
+/// All the matching methods of `TagMap` accept any `Matcher`.
+pub trait Matcher<TAG> {
+    /// Returns whether the given tags match.
+    fn matches(&self, tags: &[TAG]) -> bool;
+}
+
+impl<TAG: Eq> Matcher<TAG> for MatchRule<TAG> {
+    fn matches(&self, tags: &[TAG]) -> bool {
+        tags_match_rule(tags, self)
+    }
+}
+
+/// A borrowed counterpart of `MatchRule`.
+///
+/// Its variants refer to slices instead of owning vectors, so rules can be
+/// built from static data, or on the stack, without allocating.
+#[derive(Debug, PartialEq)]
+pub enum MatchRuleRef<'a, TAG: 'a> {
+    /// Match all given tags.
+    Tags(&'a [TAG]),
+    /// Don't match any given tag.
+    NotTags(&'a [TAG]),
+    /// Match any given tag.
+    AnyTag(&'a [TAG]),
+    /// Match all given rules.
+    Rules(&'a [MatchRuleRef<'a, TAG>]),
+    /// Don't match any given rule.
+    NotRules(&'a [MatchRuleRef<'a, TAG>]),
+    /// Match any given rule.
+    AnyRule(&'a [MatchRuleRef<'a, TAG>]),
+    /// Match exactly one of the given rules.
+    Xor(&'a [MatchRuleRef<'a, TAG>]),
+    /// Match at least the given number of the given tags.
+    AtLeast(usize, &'a [TAG]),
+    /// Match exactly the given number of the given tags.
+    Exactly(usize, &'a [TAG]),
+    /// Match at most the given number of the given tags.
+    AtMost(usize, &'a [TAG]),
+    /// Match every entry.
+    Anything,
+    /// Match no entry.
+    Nothing,
+    /// Don't match the given rule.
+    Not(&'a MatchRuleRef<'a, TAG>),
+    /// Match the given owned rule.
+    Rule(&'a MatchRule<TAG>),
+}
+
+impl<'a, TAG> Clone for MatchRuleRef<'a, TAG> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, TAG> Copy for MatchRuleRef<'a, TAG> {}
+
+impl<'a, TAG: Eq> Subrule<TAG> for MatchRuleRef<'a, TAG> {
+    fn is_shallow(&self) -> bool {
+        use self::MatchRuleRef::*;
+        match *self {
+            Tags(_) | NotTags(_) | AnyTag(_) | AtLeast(..) | Exactly(..) | AtMost(..) |
+            Anything | Nothing => true,
+            Rule(rule) => rule.is_shallow(),
+            _ => false,
+        }
+    }
+}
+
+impl<'a, TAG: Eq> Matcher<TAG> for MatchRuleRef<'a, TAG> {
+    fn matches(&self, tags: &[TAG]) -> bool {
+        use self::MatchRuleRef::*;
+        match *self {
+            Tags(m_tags) => has_all(tags, m_tags),
+            NotTags(m_tags) => has_none(tags, m_tags),
+            AnyTag(m_tags) => has_any(tags, m_tags),
+            Rules(rules) => all_match(tags, rules),
+            NotRules(rules) => !any_match(tags, rules),
+            AnyRule(rules) => any_match(tags, rules),
+            Xor(rules) => one_matches(tags, rules),
+            AtLeast(n, m_tags) => count_present(tags, m_tags, n) >= n,
+            Exactly(n, m_tags) => count_present(tags, m_tags, n.saturating_add(1)) == n,
+            AtMost(n, m_tags) => count_present(tags, m_tags, n.saturating_add(1)) <= n,
+            Anything => true,
+            Nothing => false,
+            Not(rule) => !rule.matches(tags),
+            Rule(rule) => rule.matches(tags),
+        }
+    }
+}
+
+#[test]
+fn test_match_rule_ref() {
+    use self::MatchRuleRef as R;
+    use MatchRule as M;
+    use TagMap;
+    let owned = M::AnyTag(vec!["c"]);
+    let pairs = [(R::Tags(&["a", "b"]), M::Tags(vec!["a", "b"])),
+                 (R::NotTags(&["c"]), M::NotTags(vec!["c"])),
+                 (R::AnyTag(&["b", "c"]), M::AnyTag(vec!["b", "c"])),
+                 (R::Rules(&[R::Tags(&["a"]), R::NotTags(&["b"])]),
+                  M::Rules(vec![M::Tags(vec!["a"]), M::NotTags(vec!["b"])])),
+                 (R::NotRules(&[R::Tags(&["a"]), R::Tags(&["c"])]),
+                  M::NotRules(vec![M::Tags(vec!["a"]), M::Tags(vec!["c"])])),
+                 (R::AnyRule(&[R::Tags(&["b"]), R::Nothing]),
+                  M::AnyRule(vec![M::Tags(vec!["b"]), M::Nothing])),
+                 (R::Xor(&[R::Tags(&["a"]), R::Tags(&["b"])]),
+                  M::Xor(vec![M::Tags(vec!["a"]), M::Tags(vec!["b"])])),
+                 (R::AtLeast(2, &["a", "b", "c"]), M::AtLeast(2, vec!["a", "b", "c"])),
+                 (R::Exactly(1, &["a", "b"]), M::Exactly(1, vec!["a", "b"])),
+                 (R::AtMost(1, &["a", "b"]), M::AtMost(1, vec!["a", "b"])),
+                 (R::Anything, M::Anything),
+                 (R::Not(&R::Tags(&["a"])), M::Not(Box::new(M::Tags(vec!["a"])))),
+                 (R::Rule(&owned), owned.clone())];
+    let tag_lists: [&[&str]; 6] = [&[], &["a"], &["b"], &["c"], &["a", "b"], &["a", "b", "c"]];
+    for (borrowed, rule) in &pairs {
+        for tags in &tag_lists {
+            assert_eq!(borrowed.matches(tags), rule.matches(tags), "{:?} {:?}", rule, tags);
+        }
+    }
+    let mut map = TagMap::new();
+    map.insert(1, vec!["a", "b"]);
+    map.insert(2, vec!["a"]);
+    map.insert(3, vec!["c"]);
+    let rule = R::Rules(&[R::Tags(&["a"]), R::NotTags(&["b"])]);
+    assert_eq!(map.matching(&rule).collect::<Vec<_>>(), [&2]);
+    assert_eq!(map.count_matching(&R::AnyTag(&["a", "c"])), 3);
+}
@@ -0,0 +1,77 @@
+//! Estimates of the memory used by maps.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+use std::mem::size_of;
+use {TagList, TagMap};
+
+/// An estimate of the memory used by a map, in bytes.
+///
+/// Only memory owned by the map is counted. Heap memory owned by the keys and tags
+/// themselves, like the contents of `String`s, isn't, and the bookkeeping of the
+/// underlying collections is approximated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryReport {
+    /// Memory used by the keys.
+    pub keys: usize,
+    /// Memory used by the tag lists of the entries.
+    pub tags: usize,
+    /// Memory used by the vocabulary.
+    pub vocabulary: usize,
+    /// Memory used by indexes, and registries like the interner of `InternedTagMap`.
+    pub indexes: usize,
+}
+
+impl MemoryReport {
+    /// Returns the total number of bytes.
+    pub fn total(&self) -> usize {
+        self.keys + self.tags + self.vocabulary + self.indexes
+    }
+}
+
+impl<T: Ord, TAG: Eq, L: TagList<TAG>> TagMap<T, TAG, BTreeMap<T, L>> {
+    /// Estimates the memory used by the map.
+    pub fn memory_usage(&self) -> MemoryReport {
+        let tag_heap: usize = self.entries.values().map(TagList::heap_size).sum();
+        MemoryReport {
+            keys: self.len() * size_of::<T>(),
+            tags: self.len() * size_of::<L>() + tag_heap,
+            vocabulary: self.vocabulary.memory_usage(),
+            indexes: 0,
+        }
+    }
+}
+
+impl<T: Hash + Eq, TAG: Eq, L: TagList<TAG>, S: BuildHasher> TagMap<T, TAG, HashMap<T, L, S>> {
+    /// Estimates the memory used by the map, including unused capacity.
+    pub fn memory_usage(&self) -> MemoryReport {
+        let tag_heap: usize = self.entries.values().map(TagList::heap_size).sum();
+        let capacity = self.entries.capacity();
+        MemoryReport {
+            keys: capacity * size_of::<T>(),
+            tags: capacity * size_of::<L>() + tag_heap,
+            vocabulary: self.vocabulary.memory_usage(),
+            indexes: 0,
+        }
+    }
+}
+
+#[test]
+fn test_memory_usage() {
+    use {HashTagMap, IndexedTagMap, InternedTagMap};
+    let mut map = TagMap::new();
+    assert_eq!(map.memory_usage().total(), 0);
+    map.insert(1u64, vec![1u32; 100]);
+    let report = map.memory_usage();
+    assert_eq!(report.keys, 8);
+    assert!(report.tags >= 400);
+    map.add_alias_group(vec![1, 2]);
+    assert!(map.memory_usage().vocabulary >= 8);
+    let hashed: HashTagMap<_, _> = map.clone().into_iter().map(|(k, t)| (k, t.to_vec())).collect();
+    assert!(hashed.memory_usage().tags >= 400);
+    let indexed = IndexedTagMap::from(map.clone());
+    assert!(indexed.memory_usage().indexes > 0);
+    let mut interned = InternedTagMap::new();
+    interned.insert("a", vec!["long tag", "long tag"]);
+    assert!(interned.memory_usage().indexes >= "long tag".len());
+}
@@ -0,0 +1,106 @@
+//! Namespaced tags of the form `namespace:name`.
+
+use pattern::write_prefix;
+use std::collections::BTreeSet;
+use std::fmt;
+use {Storage, TagMap, TagPattern};
+
+/// A tag that may belong to a namespace.
+///
+/// String tags are split at the first `:`, so `"license:mit"` is the tag `"mit"`
+/// in the namespace `"license"`, and `"mammal"` has no namespace.
+pub trait Namespaced {
+    /// Returns the namespace of the tag, if any.
+    fn namespace(&self) -> Option<&str>;
+    /// Returns the name of the tag within its namespace.
+    fn name(&self) -> &str;
+}
+
+impl Namespaced for str {
+    fn namespace(&self) -> Option<&str> {
+        self.split_once(':').map(|(namespace, _)| namespace)
+    }
+    fn name(&self) -> &str {
+        self.split_once(':').map_or(self, |(_, name)| name)
+    }
+}
+
+impl Namespaced for String {
+    fn namespace(&self) -> Option<&str> {
+        self[..].namespace()
+    }
+    fn name(&self) -> &str {
+        self[..].name()
+    }
+}
+
+impl Namespaced for &str {
+    fn namespace(&self) -> Option<&str> {
+        (*self).namespace()
+    }
+    fn name(&self) -> &str {
+        (*self).name()
+    }
+}
+
+/// Matches tags in the given namespace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InNamespace {
+    namespace: String,
+}
+
+impl InNamespace {
+    /// Creates a new pattern.
+    pub fn new(namespace: &str) -> Self {
+        InNamespace { namespace: namespace.to_owned() }
+    }
+    /// Returns the namespace.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+}
+
+impl<TAG: Namespaced> TagPattern<TAG> for InNamespace {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        tag.namespace() == Some(&self.namespace[..])
+    }
+    fn fmt_query(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_prefix(&format!("{}:", self.namespace), f)
+    }
+}
+
+impl<T, TAG: Eq + Namespaced, B: Storage<T, TAG>> TagMap<T, TAG, B> {
+    /// Returns the namespaces used by the tags in the map, in order.
+    pub fn namespaces(&self) -> Vec<&str> {
+        let namespaces: BTreeSet<_> = self.values()
+            .flat_map(|tags| tags.iter().filter_map(Namespaced::namespace))
+            .collect();
+        namespaces.into_iter().collect()
+    }
+    /// Returns the distinct tags in the given namespace, in order of first appearance.
+    pub fn tags_in_namespace(&self, namespace: &str) -> Vec<&TAG> {
+        let mut out = Vec::new();
+        for tag in self.values().flat_map(|tags| tags.iter()) {
+            if tag.namespace() == Some(namespace) && !out.contains(&tag) {
+                out.push(tag);
+            }
+        }
+        out
+    }
+}
+
+#[test]
+fn test_namespaces() {
+    use MatchRule;
+    let mut map = TagMap::new();
+    map.insert("tagmap", vec!["license:mit", "lang:rust"]);
+    map.insert("linux", vec!["license:gpl", "lang:c", "kernel"]);
+    map.insert("sqlite", vec!["license:public-domain", "lang:c"]);
+    assert_eq!(map.namespaces(), ["lang", "license"]);
+    assert_eq!(map.tags_in_namespace("lang"), [&"lang:c", &"lang:rust"]);
+    assert_eq!("license:mit".name(), "mit");
+    assert_eq!("kernel".name(), "kernel");
+    let rule = MatchRule::in_namespace("license") & MatchRule::NotTags(vec!["lang:c"]);
+    assert_eq!(map.matching(&rule).cloned().collect::<Vec<_>>(), ["tagmap"]);
+    assert_eq!(rule.to_string(), "license:* & !lang:c");
+}
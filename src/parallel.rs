@@ -0,0 +1,29 @@
+//! Parallel matching with rayon.
+
+use rayon::prelude::*;
+use {Matcher, TagMap};
+
+impl<T: Ord + Sync, TAG: Eq + Sync> TagMap<T, TAG> {
+    /// Returns a parallel iterator over the keys of the entries matching the given rule.
+    ///
+    /// Use `collect` to gather the keys in order.
+    pub fn par_matching<'a, R>(&'a self, rule: &'a R) -> impl ParallelIterator<Item = &'a T> + 'a
+        where R: ?Sized + Matcher<TAG> + Sync
+    {
+        self.entries.par_iter().filter(move |(_, tags)| rule.matches(tags)).map(|(key, _)| key)
+    }
+    /// Returns the number of entries matching the given rule, evaluating it in parallel.
+    pub fn par_count_matching<R: ?Sized + Matcher<TAG> + Sync>(&self, rule: &R) -> usize {
+        self.entries.par_iter().filter(|(_, tags)| rule.matches(tags)).count()
+    }
+}
+
+#[test]
+fn test_par_matching() {
+    use MatchRule;
+    let map: TagMap<_, _> = (0..1000).map(|i| (i, vec![i % 3, i % 5])).collect();
+    let rule = MatchRule::Tags(vec![0, 0]) & MatchRule::NotTags(vec![4]);
+    let expected: Vec<_> = map.matching(&rule).collect();
+    assert_eq!(map.par_matching(&rule).collect::<Vec<_>>(), expected);
+    assert_eq!(map.par_count_matching(&rule), expected.len());
+}
@@ -0,0 +1,699 @@
+//! Parsing rules from query strings.
+//!
+//! The syntax is a boolean expression over tags:
+//!
+//! - `a & b` matches both, `a | b` either, `a ^ b` exactly one, and `!a` negates.
+//!   `!` binds tightest, then `&`, `^` and `|`. Parentheses group.
+//! - Tags are bare words, or double-quoted strings with `\"` and `\\` escapes.
+//!   Quoting is needed for tags containing whitespace or any of `&|^!(),"~*?`.
+//! - `*` matches anything, and `!*` nothing.
+//! - `atleast(n, a, b, ...)`, `exactly(...)` and `atmost(...)` count tags.
+//! - `pre*` matches tags with the prefix `pre`, as does `"pre"*` for quoted
+//!   prefixes. With the `glob` feature, bare words containing other `*` or `?`
+//!   wildcards are glob patterns, as is `glob("...")` for quoted patterns.
+//! - With the `regex` feature, `/re/` matches tags matching a regex.
+//! - With the `fuzzy` feature, `tag~n` matches tags within edit distance `n`.
+//!
+//! `MatchRule` implements `Display` in the same syntax, so displayed rules parse
+//! back into equivalent rules. The exceptions are `Predicate` rules and custom
+//! patterns that don't implement `TagPattern::fmt_query`.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use MatchRule;
+
+const MAX_DEPTH: usize = 128;
+
+/// An error from parsing a `MatchRule`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseRuleError {
+    position: usize,
+    message: String,
+}
+
+impl ParseRuleError {
+    fn new<S: Into<String>>(position: usize, message: S) -> Self {
+        ParseRuleError {
+            position,
+            message: message.into(),
+        }
+    }
+    /// Returns the byte offset in the input where the error occurred.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+    /// Returns a description of the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ParseRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl Error for ParseRuleError {}
+
+impl MatchRule<String> {
+    /// Parses a rule from a query string.
+    ///
+    /// See the `parse` module documentation for the syntax.
+    pub fn parse(query: &str) -> Result<Self, ParseRuleError> {
+        let tokens = tokenize(query)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: query.len(),
+            depth: 0,
+        };
+        let node = parser.parse_or()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(ParseRuleError::new(parser.position(), "unexpected token"));
+        }
+        Ok(node.into_rule())
+    }
+}
+
+impl FromStr for MatchRule<String> {
+    type Err = ParseRuleError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MatchRule::parse(s)
+    }
+}
+
+impl<TAG: fmt::Display> fmt::Display for MatchRule<TAG> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_rule(self, f, OR)
+    }
+}
+
+// Operator precedences, from loosest to tightest
+const OR: u8 = 0;
+const XOR: u8 = 1;
+const AND: u8 = 2;
+const UNARY: u8 = 3;
+
+fn precedence<TAG>(rule: &MatchRule<TAG>) -> u8 {
+    use MatchRule::*;
+    match *rule {
+        Tags(ref tags) if tags.len() > 1 => AND,
+        AnyTag(ref tags) if tags.len() > 1 => OR,
+        Rules(ref rules) if rules.len() > 1 => AND,
+        AnyRule(ref rules) if rules.len() > 1 => OR,
+        Xor(ref rules) if rules.len() > 1 => XOR,
+        Rules(ref rules) | AnyRule(ref rules) | Xor(ref rules) if rules.len() == 1 => {
+            precedence(&rules[0])
+        }
+        _ => UNARY,
+    }
+}
+
+fn write_rule<TAG: fmt::Display>(rule: &MatchRule<TAG>,
+                                 f: &mut fmt::Formatter,
+                                 min: u8)
+                                 -> fmt::Result {
+    use MatchRule::*;
+    if precedence(rule) < min {
+        f.write_str("(")?;
+        write_rule(rule, f, OR)?;
+        return f.write_str(")");
+    }
+    match *rule {
+        Tags(ref tags) if tags.is_empty() => f.write_str("*"),
+        Rules(ref rules) if rules.is_empty() => f.write_str("*"),
+        NotTags(ref tags) if tags.is_empty() => f.write_str("*"),
+        NotRules(ref rules) if rules.is_empty() => f.write_str("*"),
+        AnyTag(ref tags) if tags.is_empty() => f.write_str("!*"),
+        AnyRule(ref rules) | Xor(ref rules) if rules.is_empty() => f.write_str("!*"),
+        Tags(ref tags) => write_tags(tags, " & ", f),
+        AnyTag(ref tags) => write_tags(tags, " | ", f),
+        NotTags(ref tags) if tags.len() == 1 => {
+            f.write_str("!")?;
+            write_tag(&tags[0], f)
+        }
+        NotTags(ref tags) => {
+            f.write_str("!(")?;
+            write_tags(tags, " | ", f)?;
+            f.write_str(")")
+        }
+        Rules(ref rules) => write_rules(rules, " & ", AND, f),
+        AnyRule(ref rules) => write_rules(rules, " | ", XOR, f),
+        Xor(ref rules) => write_rules(rules, " ^ ", AND, f),
+        NotRules(ref rules) if rules.len() == 1 => {
+            f.write_str("!")?;
+            write_rule(&rules[0], f, UNARY)
+        }
+        NotRules(ref rules) => {
+            f.write_str("!(")?;
+            write_rules(rules, " | ", XOR, f)?;
+            f.write_str(")")
+        }
+        AtLeast(n, ref tags) => write_count("atleast", n, tags, f),
+        Exactly(n, ref tags) => write_count("exactly", n, tags, f),
+        AtMost(n, ref tags) => write_count("atmost", n, tags, f),
+        Anything => f.write_str("*"),
+        Nothing => f.write_str("!*"),
+        Not(ref rule) => {
+            f.write_str("!")?;
+            write_rule(rule, f, UNARY)
+        }
+        Predicate(_) => f.write_str("<predicate>"),
+        Pattern(ref pattern) => write!(f, "{}", pattern),
+    }
+}
+
+fn write_tags<TAG: fmt::Display>(tags: &[TAG], sep: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    for (i, tag) in tags.iter().enumerate() {
+        if i != 0 {
+            f.write_str(sep)?;
+        }
+        write_tag(tag, f)?;
+    }
+    Ok(())
+}
+
+fn write_rules<TAG: fmt::Display>(rules: &[MatchRule<TAG>],
+                                  sep: &str,
+                                  min: u8,
+                                  f: &mut fmt::Formatter)
+                                  -> fmt::Result {
+    for (i, rule) in rules.iter().enumerate() {
+        if i != 0 {
+            f.write_str(sep)?;
+        }
+        write_rule(rule, f, min)?;
+    }
+    Ok(())
+}
+
+fn write_count<TAG: fmt::Display>(name: &str,
+                                  n: usize,
+                                  tags: &[TAG],
+                                  f: &mut fmt::Formatter)
+                                  -> fmt::Result {
+    write!(f, "{}({}", name, n)?;
+    for tag in tags {
+        f.write_str(", ")?;
+        write_tag(tag, f)?;
+    }
+    f.write_str(")")
+}
+
+/// Writes a tag in query syntax, quoting it if needed.
+pub(crate) fn write_tag<TAG: fmt::Display + ?Sized, W: fmt::Write>(tag: &TAG,
+                                                                   w: &mut W)
+                                                                   -> fmt::Result {
+    let tag = tag.to_string();
+    let bare = !tag.is_empty() && !tag.starts_with('/') &&
+               tag.chars().all(|c| is_word_char(c) && c != '*' && c != '?');
+    if bare {
+        return w.write_str(&tag);
+    }
+    w.write_char('"')?;
+    for c in tag.chars() {
+        if c == '"' || c == '\\' {
+            w.write_char('\\')?;
+        }
+        w.write_char(c)?;
+    }
+    w.write_char('"')
+}
+
+/// Writes a glob pattern in query syntax.
+///
+/// Patterns that would read back as a different rule as a bare word are written as
+/// `glob("...")`.
+#[cfg(feature = "glob")]
+pub(crate) fn write_glob<W: fmt::Write>(pattern: &str, w: &mut W) -> fmt::Result {
+    let stem = pattern.strip_suffix('*').unwrap_or("");
+    let prefix = !stem.is_empty() && !stem.contains(['*', '?']);
+    let bare = pattern.contains(['*', '?']) && pattern != "*" && !prefix &&
+               !pattern.starts_with('/') && pattern.chars().all(is_word_char);
+    if bare {
+        return w.write_str(pattern);
+    }
+    w.write_str("glob(")?;
+    write_tag(pattern, w)?;
+    w.write_char(')')
+}
+
+/// Returns whether a character can appear in a bare word.
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !"&|^!(),\"~".contains(c)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    And,
+    Or,
+    Xor,
+    Not,
+    Open,
+    Close,
+    Comma,
+    Tilde,
+    Word(String),
+    Quoted(String),
+    QuotedPrefix(String),
+    Regex(String),
+}
+
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, ParseRuleError> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((pos, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '&' | '|' => {
+                // Accept `&&` and `||` too
+                if chars.peek().map(|&(_, next)| next) == Some(c) {
+                    chars.next();
+                }
+                if c == '&' { Token::And } else { Token::Or }
+            }
+            '^' => Token::Xor,
+            '!' => Token::Not,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            '~' => Token::Tilde,
+            '"' | '/' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) if c == '"' => {
+                            match chars.next() {
+                                Some((_, esc)) if esc == '"' || esc == '\\' => text.push(esc),
+                                Some((pos, _)) => {
+                                    return Err(ParseRuleError::new(pos, "invalid escape"))
+                                }
+                                None => break,
+                            }
+                        }
+                        Some((_, '\\')) => {
+                            // Regexes keep their escapes, except for the delimiter
+                            match chars.next() {
+                                Some((_, '/')) => text.push('/'),
+                                Some((_, esc)) => {
+                                    text.push('\\');
+                                    text.push(esc);
+                                }
+                                None => break,
+                            }
+                        }
+                        Some((_, end)) if end == c => {
+                            let token = if c == '/' {
+                                Token::Regex(text)
+                            } else if chars.peek().map(|&(_, next)| next) == Some('*') {
+                                chars.next();
+                                Token::QuotedPrefix(text)
+                            } else {
+                                Token::Quoted(text)
+                            };
+                            tokens.push((pos, token));
+                            break;
+                        }
+                        Some((_, other)) => text.push(other),
+                        None => break,
+                    }
+                }
+                if tokens.last().map(|&(p, _)| p) != Some(pos) {
+                    return Err(ParseRuleError::new(pos, "unterminated string"));
+                }
+                continue;
+            }
+            _ => {
+                let mut word = c.to_string();
+                while let Some(&(_, c)) = chars.peek() {
+                    if !is_word_char(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push((pos, token));
+    }
+    Ok(tokens)
+}
+
+/// A parsed operand. Plain tags are kept apart so chains of them can be merged.
+enum Node {
+    Tag(String),
+    Rule(MatchRule<String>),
+}
+
+impl Node {
+    fn into_rule(self) -> MatchRule<String> {
+        match self {
+            Node::Tag(tag) => MatchRule::Tags(vec![tag]),
+            Node::Rule(rule) => rule,
+        }
+    }
+}
+
+/// Combines the operands of a `&` or `|` chain, merging runs of plain tags.
+fn chain(nodes: Vec<Node>,
+         tags: fn(Vec<String>) -> MatchRule<String>,
+         rules: fn(Vec<MatchRule<String>>) -> MatchRule<String>)
+         -> Node {
+    if nodes.len() == 1 {
+        return nodes.into_iter().next().unwrap();
+    }
+    let mut out = Vec::new();
+    let mut run = Vec::new();
+    for node in nodes {
+        match node {
+            Node::Tag(tag) => run.push(tag),
+            Node::Rule(rule) => {
+                flush_run(&mut run, &mut out, tags);
+                out.push(rule);
+            }
+        }
+    }
+    if out.is_empty() {
+        return Node::Rule(tags(run));
+    }
+    flush_run(&mut run, &mut out, tags);
+    Node::Rule(rules(out))
+}
+
+fn flush_run(run: &mut Vec<String>,
+             out: &mut Vec<MatchRule<String>>,
+             tags: fn(Vec<String>) -> MatchRule<String>) {
+    match run.len() {
+        0 => {}
+        1 => out.push(MatchRule::Tags(run.split_off(0))),
+        _ => out.push(tags(run.split_off(0))),
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |&(pos, _)| pos)
+    }
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, token: &Token, what: &str) -> Result<(), ParseRuleError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(ParseRuleError::new(self.position(), format!("expected {}", what)))
+        }
+    }
+    fn parse_or(&mut self) -> Result<Node, ParseRuleError> {
+        let mut nodes = vec![self.parse_xor()?];
+        while self.eat(&Token::Or) {
+            nodes.push(self.parse_xor()?);
+        }
+        Ok(chain(nodes, MatchRule::AnyTag, MatchRule::AnyRule))
+    }
+    fn parse_xor(&mut self) -> Result<Node, ParseRuleError> {
+        let first = self.parse_and()?;
+        if self.peek() != Some(&Token::Xor) {
+            return Ok(first);
+        }
+        let mut rules = vec![first.into_rule()];
+        while self.eat(&Token::Xor) {
+            rules.push(self.parse_and()?.into_rule());
+        }
+        Ok(Node::Rule(MatchRule::Xor(rules)))
+    }
+    fn parse_and(&mut self) -> Result<Node, ParseRuleError> {
+        let mut nodes = vec![self.parse_unary()?];
+        while self.eat(&Token::And) {
+            nodes.push(self.parse_unary()?);
+        }
+        Ok(chain(nodes, MatchRule::Tags, MatchRule::Rules))
+    }
+    fn parse_unary(&mut self) -> Result<Node, ParseRuleError> {
+        if !self.eat(&Token::Not) {
+            return self.parse_primary();
+        }
+        self.enter()?;
+        let operand = self.parse_unary()?;
+        self.depth -= 1;
+        Ok(Node::Rule(match operand {
+            Node::Tag(tag) => MatchRule::NotTags(vec![tag]),
+            Node::Rule(MatchRule::AnyTag(tags)) => MatchRule::NotTags(tags),
+            Node::Rule(MatchRule::AnyRule(rules)) => MatchRule::NotRules(rules),
+            Node::Rule(MatchRule::Anything) => MatchRule::Nothing,
+            Node::Rule(rule) => MatchRule::Not(Box::new(rule)),
+        }))
+    }
+    fn enter(&mut self) -> Result<(), ParseRuleError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            Err(ParseRuleError::new(self.position(), "nesting too deep"))
+        } else {
+            Ok(())
+        }
+    }
+    fn parse_primary(&mut self) -> Result<Node, ParseRuleError> {
+        let position = self.position();
+        let token = match self.tokens.get(self.pos) {
+            Some((_, token)) => token.clone(),
+            None => return Err(ParseRuleError::new(position, "expected a tag")),
+        };
+        self.pos += 1;
+        match token {
+            Token::Open => {
+                self.enter()?;
+                let node = self.parse_or()?;
+                self.expect(&Token::Close, "`)`")?;
+                self.depth -= 1;
+                Ok(node)
+            }
+            Token::Word(ref word) if word == "*" => Ok(Node::Rule(MatchRule::Anything)),
+            Token::Word(ref word) if word == "glob" && self.peek() == Some(&Token::Open) => {
+                self.pos += 1;
+                let position = self.position();
+                let pattern = match self.tokens.get(self.pos) {
+                    Some(&(_, Token::Quoted(ref pattern))) |
+                    Some(&(_, Token::Word(ref pattern))) => pattern.clone(),
+                    _ => return Err(ParseRuleError::new(position, "expected a pattern")),
+                };
+                self.pos += 1;
+                self.expect(&Token::Close, "`)`")?;
+                glob(position, &pattern).map(Node::Rule)
+            }
+            Token::Word(ref word) if self.peek() == Some(&Token::Open) &&
+                                     (word == "atleast" || word == "exactly" ||
+                                      word == "atmost") => {
+                self.pos += 1;
+                let n = self.parse_count()?;
+                let mut tags = Vec::new();
+                while self.eat(&Token::Comma) {
+                    tags.push(self.parse_plain_tag()?);
+                }
+                self.expect(&Token::Close, "`,` or `)`")?;
+                Ok(Node::Rule(match &word[..] {
+                    "atleast" => MatchRule::AtLeast(n, tags),
+                    "exactly" => MatchRule::Exactly(n, tags),
+                    _ => MatchRule::AtMost(n, tags),
+                }))
+            }
+            Token::Word(word) => {
+                if word.contains(['*', '?']) {
+                    return wildcard(position, &word).map(Node::Rule);
+                }
+                self.parse_fuzzy(word)
+            }
+            Token::Quoted(tag) => self.parse_fuzzy(tag),
+            Token::QuotedPrefix(prefix) => Ok(Node::Rule(MatchRule::tag_prefix(&prefix))),
+            Token::Regex(re) => regex(position, &re).map(Node::Rule),
+            _ => Err(ParseRuleError::new(position, "expected a tag")),
+        }
+    }
+    fn parse_plain_tag(&mut self) -> Result<String, ParseRuleError> {
+        match self.tokens.get(self.pos) {
+            Some(&(_, Token::Quoted(_))) => {}
+            Some(&(_, Token::Word(ref tag))) if !tag.contains(['*', '?']) => {}
+            _ => return Err(ParseRuleError::new(self.position(), "expected a tag")),
+        }
+        match self.tokens[self.pos].1.clone() {
+            Token::Quoted(tag) | Token::Word(tag) => {
+                self.pos += 1;
+                Ok(tag)
+            }
+            _ => unreachable!(),
+        }
+    }
+    fn parse_count(&mut self) -> Result<usize, ParseRuleError> {
+        let position = self.position();
+        match self.tokens.get(self.pos) {
+            Some(&(_, Token::Word(ref word))) => {
+                match word.parse() {
+                    Ok(n) => {
+                        self.pos += 1;
+                        Ok(n)
+                    }
+                    Err(_) => Err(ParseRuleError::new(position, "expected a number")),
+                }
+            }
+            _ => Err(ParseRuleError::new(position, "expected a number")),
+        }
+    }
+    fn parse_fuzzy(&mut self, tag: String) -> Result<Node, ParseRuleError> {
+        let position = self.position();
+        if !self.eat(&Token::Tilde) {
+            return Ok(Node::Tag(tag));
+        }
+        let distance = self.parse_count()?;
+        fuzzy(position, &tag, distance).map(Node::Rule)
+    }
+}
+
+fn wildcard(position: usize, word: &str) -> Result<MatchRule<String>, ParseRuleError> {
+    if let Some(stem) = word.strip_suffix('*') {
+        if !stem.is_empty() && !stem.contains(['*', '?']) {
+            return Ok(MatchRule::tag_prefix(stem));
+        }
+    }
+    glob(position, word)
+}
+
+#[cfg(feature = "glob")]
+fn glob(_position: usize, word: &str) -> Result<MatchRule<String>, ParseRuleError> {
+    Ok(MatchRule::glob(word))
+}
+
+#[cfg(not(feature = "glob"))]
+fn glob(position: usize, _word: &str) -> Result<MatchRule<String>, ParseRuleError> {
+    Err(ParseRuleError::new(position, "glob patterns require the `glob` feature"))
+}
+
+#[cfg(feature = "regex")]
+fn regex(position: usize, re: &str) -> Result<MatchRule<String>, ParseRuleError> {
+    ::regex::Regex::new(re)
+        .map(MatchRule::regex)
+        .map_err(|e| ParseRuleError::new(position, format!("invalid regex: {}", e)))
+}
+
+#[cfg(not(feature = "regex"))]
+fn regex(position: usize, _re: &str) -> Result<MatchRule<String>, ParseRuleError> {
+    Err(ParseRuleError::new(position, "regex patterns require the `regex` feature"))
+}
+
+#[cfg(feature = "fuzzy")]
+fn fuzzy(_position: usize,
+         tag: &str,
+         distance: usize)
+         -> Result<MatchRule<String>, ParseRuleError> {
+    Ok(MatchRule::fuzzy(tag, distance))
+}
+
+#[cfg(not(feature = "fuzzy"))]
+fn fuzzy(position: usize,
+         _tag: &str,
+         _distance: usize)
+         -> Result<MatchRule<String>, ParseRuleError> {
+    Err(ParseRuleError::new(position, "fuzzy matching requires the `fuzzy` feature"))
+}
+
+#[test]
+fn test_parse() {
+    use Matcher;
+    use MatchRule::*;
+    let s = |tags: &[&str]| tags.iter().map(|&t| t.to_owned()).collect::<Vec<_>>();
+    let parse = |query: &str| MatchRule::parse(query).unwrap();
+    assert_eq!(parse("mammal & carnivore & !hostile | fish"),
+               AnyRule(vec![Rules(vec![Tags(s(&["mammal", "carnivore"])),
+                                       NotTags(s(&["hostile"]))]),
+                            Tags(s(&["fish"]))]));
+    assert_eq!(parse("a | b | (c && d)"),
+               AnyRule(vec![AnyTag(s(&["a", "b"])), Tags(s(&["c", "d"]))]));
+    assert_eq!(parse("!(a | b) ^ \"x \\\"y\\\"\""),
+               Xor(vec![NotTags(s(&["a", "b"])), Tags(s(&["x \"y\""]))]));
+    assert_eq!(parse("atleast(2, a, b, c) & !*"),
+               Rules(vec![AtLeast(2, s(&["a", "b", "c"])), Nothing]));
+    assert_eq!(parse("!(a & b)"), Not(Box::new(Tags(s(&["a", "b"])))));
+    assert!(parse("lang:*").matches(&s(&["lang:rust"])));
+    assert!(parse("é*").matches(&s(&["éa"])));
+    for &query in &["a?é", "?é", "ab*é"] {
+        assert_eq!(MatchRule::parse(query).is_ok(), cfg!(feature = "glob"), "{}", query);
+    }
+    for &(query, position) in &[("", 0), ("a &", 3), ("(a | b", 6), ("a b", 2),
+                                ("\"a", 0), ("atleast(x)", 8)] {
+        assert_eq!(MatchRule::parse(query).unwrap_err().position(), position, "{}", query);
+    }
+    let deep = format!("{}a{}", "(".repeat(1000), ")".repeat(1000));
+    assert!(MatchRule::parse(&deep).is_err());
+}
+
+#[test]
+fn test_display_round_trip() {
+    use MatchRule::*;
+    let queries = ["mammal & carnivore & !hostile | fish",
+                   "a | b ^ (c | d) & e",
+                   "(a ^ b) ^ c",
+                   "!(a | b) & !(c & d) & !!e",
+                   "atleast(2, a, \"b c\", \"\\\"q\\\"\") | exactly(0) | atmost(1, x)",
+                   "* & !* | lang:* | \"a b\"* | \"\"*",
+                   "\"/etc\" & \"*\" & \"\""];
+    for &query in &queries {
+        let rule = MatchRule::parse(query).unwrap();
+        assert_eq!(rule.to_string(), query);
+        // Patterns only compare equal to their clones, so compare the structure
+        let reparsed = MatchRule::parse(&rule.to_string()).unwrap();
+        assert_eq!(format!("{:?}", reparsed), format!("{:?}", rule));
+    }
+    let rule: MatchRule<&str> = Rules(vec![AnyTag(vec!["a", "b"]),
+                                           Xor(vec![Tags(vec!["c"]), AnyRule(vec![])]),
+                                           NotRules(vec![Tags(vec!["d", "e"])]),
+                                           MatchRule::any_prefix(vec!["x", "y"])]);
+    assert_eq!(rule.to_string(), "(a | b) & (c ^ !*) & !(d & e) & (x* | y*)");
+    #[cfg(all(feature = "glob", feature = "regex", feature = "fuzzy"))]
+    {
+        let query = "l?ng:* | /^a\\/b\\d$/ | mamal~1 | \"two words\"~2";
+        assert_eq!(MatchRule::parse(query).unwrap().to_string(), query);
+        let re = ::regex::Regex::new("a/b").unwrap();
+        assert_eq!(MatchRule::<String>::regex(re).to_string(), "/a\\/b/");
+    }
+    assert_eq!(Rules::<&str>(vec![]).to_string(), "*");
+    assert_eq!(Not::<&str>(Box::new(Rules(vec![]))).to_string(), "!*");
+    assert_eq!(MatchRule::parse("*").unwrap(), Anything);
+    assert_eq!(MatchRule::parse("!*").unwrap(), Nothing);
+}
+
+#[cfg(feature = "glob")]
+#[test]
+fn test_glob_round_trip() {
+    use Matcher;
+    let tags = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+    for &(pattern, display) in &[("*", "glob(\"*\")"),
+                                 ("a b*", "glob(\"a b*\")"),
+                                 ("ab*", "glob(\"ab*\")"),
+                                 ("a?c", "a?c"),
+                                 ("(x)|*", "glob(\"(x)|*\")")] {
+        let rule = MatchRule::<String>::glob(pattern);
+        assert_eq!(rule.to_string(), display);
+        let reparsed = MatchRule::parse(&rule.to_string()).unwrap();
+        assert_eq!(format!("{:?}", reparsed), format!("{:?}", rule));
+    }
+    let rule = MatchRule::parse(&MatchRule::<String>::glob("*").to_string()).unwrap();
+    assert!(!rule.matches(&tags(&[])));
+    assert!(MatchRule::parse("glob(\"a b*\")").unwrap().matches(&tags(&["a bc"])));
+    assert!(MatchRule::parse("glob(a").is_err());
+}
@@ -0,0 +1,388 @@
+//! Patterns that are tested against individual tags, used by `MatchRule::Pattern`.
+
+#[cfg(feature = "glob")]
+use parse::write_glob;
+use parse::write_tag;
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+/// A test applied to individual tags.
+///
+/// A `MatchRule::Pattern` rule matches an entry if any of its tags matches the
+/// pattern. Implement this for custom kinds of tag matching.
+pub trait TagPattern<TAG>: fmt::Debug + Send + Sync {
+    /// Returns whether the tag matches the pattern.
+    fn matches_tag(&self, tag: &TAG) -> bool;
+    /// Writes the pattern in the query syntax of the `parse` module.
+    ///
+    /// The default writes the `Debug` representation, which doesn't parse back.
+    fn fmt_query(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{:?}>", self)
+    }
+}
+
+/// A shared `TagPattern`, used by `MatchRule::Pattern`.
+///
+/// Patterns compare equal only if they are clones of each other.
+pub struct SharedPattern<TAG>(Arc<dyn TagPattern<TAG>>);
+
+impl<TAG> SharedPattern<TAG> {
+    /// Wraps a pattern.
+    pub fn new<P: TagPattern<TAG> + 'static>(pattern: P) -> Self {
+        SharedPattern(Arc::new(pattern))
+    }
+    /// Returns whether the tag matches the pattern.
+    pub fn matches_tag(&self, tag: &TAG) -> bool {
+        self.0.matches_tag(tag)
+    }
+}
+
+impl<TAG> Clone for SharedPattern<TAG> {
+    fn clone(&self) -> Self {
+        SharedPattern(self.0.clone())
+    }
+}
+
+impl<TAG> PartialEq for SharedPattern<TAG> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<TAG> fmt::Debug for SharedPattern<TAG> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<TAG> fmt::Display for SharedPattern<TAG> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_query(f)
+    }
+}
+
+/// Matches string tags starting with a prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prefix {
+    prefix: String,
+}
+
+impl Prefix {
+    /// Creates a new prefix pattern.
+    pub fn new(prefix: &str) -> Self {
+        Prefix { prefix: prefix.to_owned() }
+    }
+    /// Returns the prefix.
+    pub fn as_str(&self) -> &str {
+        &self.prefix
+    }
+}
+
+impl<TAG: AsRef<str>> TagPattern<TAG> for Prefix {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        tag.as_ref().starts_with(&self.prefix[..])
+    }
+    fn fmt_query(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_prefix(&self.prefix, f)
+    }
+}
+
+/// Writes a prefix pattern in query syntax.
+pub(crate) fn write_prefix(prefix: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    write_tag(prefix, f)?;
+    f.write_str("*")
+}
+
+/// Matches string tags starting with any of the given prefixes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnyPrefix {
+    prefixes: Vec<String>,
+}
+
+impl AnyPrefix {
+    /// Creates a new pattern from the given prefixes.
+    pub fn new<I: IntoIterator>(prefixes: I) -> Self
+        where I::Item: Into<String>
+    {
+        AnyPrefix { prefixes: prefixes.into_iter().map(Into::into).collect() }
+    }
+    /// Returns the prefixes.
+    pub fn prefixes(&self) -> &[String] {
+        &self.prefixes
+    }
+}
+
+impl<TAG: AsRef<str>> TagPattern<TAG> for AnyPrefix {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        let tag = tag.as_ref();
+        self.prefixes.iter().any(|prefix| tag.starts_with(&prefix[..]))
+    }
+    fn fmt_query(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.prefixes.len() {
+            0 => f.write_str("!*"),
+            1 => write_prefix(&self.prefixes[0], f),
+            _ => {
+                f.write_str("(")?;
+                for (i, prefix) in self.prefixes.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(" | ")?;
+                    }
+                    write_prefix(prefix, f)?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+/// Matches tags greater than a given tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GreaterThan<TAG>(pub TAG);
+
+impl<TAG: Ord + fmt::Debug + Send + Sync> TagPattern<TAG> for GreaterThan<TAG> {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        *tag > self.0
+    }
+}
+
+/// Matches tags less than a given tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LessThan<TAG>(pub TAG);
+
+impl<TAG: Ord + fmt::Debug + Send + Sync> TagPattern<TAG> for LessThan<TAG> {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        *tag < self.0
+    }
+}
+
+/// Matches tags within a range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InRange<TAG> {
+    start: Bound<TAG>,
+    end: Bound<TAG>,
+}
+
+impl<TAG: Clone> InRange<TAG> {
+    /// Creates a new pattern from a range, like `10..20` or `..=5`.
+    pub fn new<R: RangeBounds<TAG>>(range: R) -> Self {
+        InRange {
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+        }
+    }
+}
+
+impl<TAG: Ord + fmt::Debug + Send + Sync> TagPattern<TAG> for InRange<TAG> {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        (self.start.as_ref(), self.end.as_ref()).contains(tag)
+    }
+}
+
+/// A glob pattern for string tags.
+///
+/// `*` matches any sequence of characters, and `?` matches any single character.
+#[cfg(feature = "glob")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    pattern: String,
+}
+
+#[cfg(feature = "glob")]
+impl Glob {
+    /// Creates a new glob pattern.
+    pub fn new(pattern: &str) -> Self {
+        Glob { pattern: pattern.to_owned() }
+    }
+    /// Returns the pattern string.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+    /// Returns whether the string matches the pattern.
+    pub fn is_match(&self, text: &str) -> bool {
+        glob_match(&self.pattern, text)
+    }
+}
+
+#[cfg(feature = "glob")]
+impl<TAG: AsRef<str>> TagPattern<TAG> for Glob {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        self.is_match(tag.as_ref())
+    }
+    fn fmt_query(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_glob(&self.pattern, f)
+    }
+}
+
+/// Matches string tags within a Levenshtein distance of a given tag.
+#[cfg(feature = "fuzzy")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fuzzy {
+    tag: String,
+    max_distance: usize,
+}
+
+#[cfg(feature = "fuzzy")]
+impl Fuzzy {
+    /// Creates a new fuzzy pattern.
+    pub fn new(tag: &str, max_distance: usize) -> Self {
+        Fuzzy {
+            tag: tag.to_owned(),
+            max_distance,
+        }
+    }
+    /// Returns the tag to compare against.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+    /// Returns the maximum allowed edit distance.
+    pub fn max_distance(&self) -> usize {
+        self.max_distance
+    }
+}
+
+#[cfg(feature = "fuzzy")]
+impl<TAG: AsRef<str>> TagPattern<TAG> for Fuzzy {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        let tag = tag.as_ref();
+        let (a, b) = (self.tag.chars().count(), tag.chars().count());
+        // The distance is at least the difference in length
+        if a.max(b) - a.min(b) > self.max_distance {
+            return false;
+        }
+        levenshtein(&self.tag, tag) <= self.max_distance
+    }
+    fn fmt_query(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_tag(&self.tag, f)?;
+        write!(f, "~{}", self.max_distance)
+    }
+}
+
+/// Returns the Levenshtein edit distance between two strings, in characters.
+#[cfg(feature = "fuzzy")]
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..b.len() + 1).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == cb { 0 } else { 1 };
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        ::std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+#[cfg(feature = "regex")]
+impl<TAG: AsRef<str>> TagPattern<TAG> for ::regex::Regex {
+    fn matches_tag(&self, tag: &TAG) -> bool {
+        self.is_match(tag.as_ref())
+    }
+    fn fmt_query(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("/")?;
+        let mut chars = self.as_str().chars();
+        while let Some(c) = chars.next() {
+            match c {
+                // `\/` and `/` mean the same in a regex, so both are written as `\/`
+                '\\' => {
+                    f.write_str("\\")?;
+                    if let Some(next) = chars.next() {
+                        write!(f, "{}", next)?;
+                    }
+                }
+                '/' => f.write_str("\\/")?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        f.write_str("/")
+    }
+}
+
+#[cfg(feature = "glob")]
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Pattern position after the last `*`, and the text position it's tried at
+    let mut star = None;
+    loop {
+        if let Some(pc) = pattern[p..].chars().next() {
+            let tc = text[t..].chars().next();
+            match (pc, tc) {
+                ('*', _) => {
+                    p += 1;
+                    star = Some((p, t));
+                    continue;
+                }
+                ('?', Some(tc)) => {
+                    p += 1;
+                    t += tc.len_utf8();
+                    continue;
+                }
+                (pc, Some(tc)) if pc == tc => {
+                    p += pc.len_utf8();
+                    t += tc.len_utf8();
+                    continue;
+                }
+                _ => {}
+            }
+        } else if t == text.len() {
+            return true;
+        }
+        // Mismatch, let the last `*` swallow one more character
+        match star {
+            Some((sp, st)) if st < text.len() => {
+                let st = st + text[st..].chars().next().map_or(1, char::len_utf8);
+                star = Some((sp, st));
+                p = sp;
+                t = st;
+            }
+            _ => return false,
+        }
+    }
+}
+
+#[test]
+fn test_ordered() {
+    let sizes = [1u32, 5, 10, 20];
+    let count = |pattern: &dyn TagPattern<u32>| {
+        sizes.iter().filter(|tag| pattern.matches_tag(tag)).count()
+    };
+    assert_eq!(count(&GreaterThan(5)), 2);
+    assert_eq!(count(&LessThan(5)), 1);
+    assert_eq!(count(&InRange::new(5..20)), 2);
+    assert_eq!(count(&InRange::new(5..=20)), 3);
+    assert_eq!(count(&InRange::new(..)), 4);
+    use {MatchRule, Matcher};
+    assert!(MatchRule::tag_greater_than(15).matches(&sizes));
+    assert!(!MatchRule::tag_in_range(11..20).matches(&sizes));
+}
+
+#[cfg(feature = "glob")]
+#[test]
+fn test_glob() {
+    let cases = [("lang:*", "lang:rust", true),
+                 ("lang:*", "lang:", true),
+                 ("lang:*", "year:2021", false),
+                 ("*:rust", "lang:rust", true),
+                 ("l?ng:*t", "läng:rust", true),
+                 ("a*b*c", "abxbyc", true),
+                 ("a*b*c", "abxbyd", false),
+                 ("", "", true),
+                 ("*", "", true),
+                 ("?", "", false)];
+    for &(pattern, text, expected) in &cases {
+        assert_eq!(Glob::new(pattern).is_match(text), expected, "{} {}", pattern, text);
+    }
+}
+
+#[cfg(feature = "fuzzy")]
+#[test]
+fn test_fuzzy() {
+    assert_eq!(levenshtein("mammal", "mamal"), 1);
+    assert_eq!(levenshtein("kitten", "sitting"), 3);
+    assert_eq!(levenshtein("", "abc"), 3);
+    let fuzzy = Fuzzy::new("mammal", 1);
+    assert!(TagPattern::<&str>::matches_tag(&fuzzy, &"mamal"));
+    assert!(!TagPattern::<&str>::matches_tag(&fuzzy, &"animal"));
+}
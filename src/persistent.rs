@@ -0,0 +1,315 @@
+//! An immutable tag map sharing structure between versions.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::FromIterator;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use {Matcher, TagMap};
+
+type Link<T, TAG> = Option<Arc<Node<T, TAG>>>;
+
+/// A node of a treap, a search tree by key that is a heap by priority.
+struct Node<T, TAG> {
+    entry: Arc<(T, Vec<TAG>)>,
+    priority: u64,
+    len: usize,
+    left: Link<T, TAG>,
+    right: Link<T, TAG>,
+}
+
+impl<T, TAG> Node<T, TAG> {
+    fn new(entry: Arc<(T, Vec<TAG>)>, priority: u64, left: Link<T, TAG>,
+           right: Link<T, TAG>) -> Arc<Self> {
+        let len = 1 + len(&left) + len(&right);
+        Arc::new(Node { entry, priority, len, left, right })
+    }
+    /// Returns a copy of the node with other children, sharing its entry.
+    fn with_children(&self, left: Link<T, TAG>, right: Link<T, TAG>) -> Arc<Self> {
+        Node::new(self.entry.clone(), self.priority, left, right)
+    }
+}
+
+fn len<T, TAG>(link: &Link<T, TAG>) -> usize {
+    link.as_ref().map_or(0, |node| node.len)
+}
+
+/// Returns a pseudo-random priority for a new node.
+fn next_priority() -> u64 {
+    // SplitMix64 over a shared counter
+    static STATE: AtomicU64 = AtomicU64::new(0);
+    let mut z = STATE.fetch_add(0x9e37_79b9_7f4a_7c15, AtomicOrdering::Relaxed)
+        .wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Splits a tree into the nodes with keys less than, equal to, and greater than `key`.
+fn split<T, TAG, Q>(link: &Link<T, TAG>, key: &Q) -> (Link<T, TAG>, Link<T, TAG>, Link<T, TAG>)
+    where T: Borrow<Q>,
+          Q: ?Sized + Ord
+{
+    let node = match *link {
+        Some(ref node) => node,
+        None => return (None, None, None),
+    };
+    match key.cmp(node.entry.0.borrow()) {
+        Ordering::Less => {
+            let (less, equal, greater) = split(&node.left, key);
+            (less, equal, Some(node.with_children(greater, node.right.clone())))
+        }
+        Ordering::Greater => {
+            let (less, equal, greater) = split(&node.right, key);
+            (Some(node.with_children(node.left.clone(), less)), equal, greater)
+        }
+        Ordering::Equal => (node.left.clone(), Some(node.clone()), node.right.clone()),
+    }
+}
+
+/// Joins two trees, the keys of `left` being less than those of `right`.
+fn merge<T, TAG>(left: Link<T, TAG>, right: Link<T, TAG>) -> Link<T, TAG> {
+    match (left, right) {
+        (None, link) | (link, None) => link,
+        (Some(left), Some(right)) => {
+            if left.priority >= right.priority {
+                let merged = merge(left.right.clone(), Some(right));
+                Some(left.with_children(left.left.clone(), merged))
+            } else {
+                let merged = merge(Some(left), right.left.clone());
+                Some(right.with_children(merged, right.right.clone()))
+            }
+        }
+    }
+}
+
+/// An immutable `TagMap`, where changes return a new map sharing structure with the old.
+///
+/// Cloning is cheap, and changing an entry only copies the path to it in a balanced
+/// tree, so versions of a map can be kept around cheaply, for example to query a
+/// consistent snapshot in one thread while another one makes changes. Tags are
+/// shared between versions too, and only copied when the entry is changed. Maps can
+/// be shared between threads if their keys and tags can.
+pub struct PersistentTagMap<T, TAG> {
+    root: Link<T, TAG>,
+}
+
+impl<T, TAG> Clone for PersistentTagMap<T, TAG> {
+    fn clone(&self) -> Self {
+        PersistentTagMap { root: self.root.clone() }
+    }
+}
+
+impl<T: fmt::Debug, TAG: fmt::Debug> fmt::Debug for PersistentTagMap<T, TAG> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<T, TAG> PersistentTagMap<T, TAG> {
+    /// Creates a new empty map.
+    pub fn new() -> Self {
+        PersistentTagMap { root: None }
+    }
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        len(&self.root)
+    }
+    /// Returns whether the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+    /// Returns whether two maps are the same version, sharing all their entries.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+    /// Returns an iterator over the entries, in order.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (&T, &[TAG])> {
+        let mut iter = Iter { stack: Vec::new(), remaining: self.len() };
+        iter.push_left(&self.root);
+        iter
+    }
+    /// Returns an iterator over the keys, in order.
+    pub fn keys(&self) -> impl Iterator<Item = &T> {
+        self.iter().map(|(key, _)| key)
+    }
+}
+
+impl<T: Ord, TAG: Eq> PersistentTagMap<T, TAG> {
+    /// Returns a map with an entry with the given tags inserted, replacing the old one.
+    pub fn insert(&self, key: T, tags: Vec<TAG>) -> Self {
+        self.with_entry(Arc::new((key, tags)))
+    }
+    /// Returns a map without the entry for the given key.
+    ///
+    /// Returns a map sharing everything with this one if the entry isn't present.
+    pub fn remove<Q: ?Sized + Ord>(&self, key: &Q) -> Self
+        where T: Borrow<Q>
+    {
+        if !self.contains_key(key) {
+            return self.clone();
+        }
+        let (less, _, greater) = split(&self.root, key);
+        PersistentTagMap { root: merge(less, greater) }
+    }
+    /// Returns the tags of an entry, if it is present.
+    pub fn get_tags<Q: ?Sized + Ord>(&self, key: &Q) -> Option<&[TAG]>
+        where T: Borrow<Q>
+    {
+        let mut link = &self.root;
+        while let Some(ref node) = *link {
+            link = match key.cmp(node.entry.0.borrow()) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => &node.right,
+                Ordering::Equal => return Some(&node.entry.1),
+            };
+        }
+        None
+    }
+    /// Returns whether the map contains an entry for the given key.
+    pub fn contains_key<Q: ?Sized + Ord>(&self, key: &Q) -> bool
+        where T: Borrow<Q>
+    {
+        self.get_tags(key).is_some()
+    }
+    /// Returns whether an entry has the given tag.
+    pub fn has_tag<Q: ?Sized + Ord>(&self, key: &Q, tag: &TAG) -> bool
+        where T: Borrow<Q>
+    {
+        self.get_tags(key).is_some_and(|tags| tags.contains(tag))
+    }
+    /// Returns the keys of the entries matching the given rule, in order.
+    pub fn matching<'a, R>(&'a self, rule: &'a R) -> impl Iterator<Item = &'a T> + 'a
+        where R: ?Sized + Matcher<TAG>
+    {
+        self.iter().filter(move |(_, tags)| rule.matches(tags)).map(|(key, _)| key)
+    }
+    /// Returns the number of entries matching the given rule.
+    pub fn count_matching<R: ?Sized + Matcher<TAG>>(&self, rule: &R) -> usize {
+        self.iter().filter(|(_, tags)| rule.matches(tags)).count()
+    }
+    /// Returns a map with the given entry, replacing the old one.
+    fn with_entry(&self, entry: Arc<(T, Vec<TAG>)>) -> Self {
+        let (less, equal, greater) = split(&self.root, &entry.0);
+        // An existing node keeps its priority, so the shape of the tree stays the same
+        let priority = equal.map_or_else(next_priority, |node| node.priority);
+        let node = Node::new(entry, priority, None, None);
+        PersistentTagMap { root: merge(merge(less, Some(node)), greater) }
+    }
+}
+
+impl<T: Ord + Clone, TAG: Eq + Clone> PersistentTagMap<T, TAG> {
+    /// Returns a map with a tag added to an entry, inserting the entry if it's not
+    /// present.
+    ///
+    /// Returns a map sharing everything with this one if the entry already has the tag.
+    pub fn add_tag(&self, key: T, tag: TAG) -> Self {
+        let mut tags = match self.get_tags(&key) {
+            Some(tags) if tags.contains(&tag) => return self.clone(),
+            Some(tags) => tags.to_vec(),
+            None => Vec::new(),
+        };
+        tags.push(tag);
+        self.insert(key, tags)
+    }
+    /// Returns a map with a tag removed from an entry.
+    ///
+    /// Returns a map sharing everything with this one if the entry is not present or
+    /// doesn't have the tag.
+    pub fn remove_tag<Q: ?Sized + Ord>(&self, key: &Q, tag: &TAG) -> Self
+        where T: Borrow<Q>
+    {
+        let (less, equal, greater) = split(&self.root, key);
+        let node = match equal {
+            Some(ref node) if node.entry.1.contains(tag) => node,
+            _ => return self.clone(),
+        };
+        let tags = node.entry.1.iter().filter(|t| *t != tag).cloned().collect();
+        let entry = Arc::new((node.entry.0.clone(), tags));
+        let node = Node::new(entry, node.priority, None, None);
+        PersistentTagMap { root: merge(merge(less, Some(node)), greater) }
+    }
+}
+
+impl<T, TAG> Default for PersistentTagMap<T, TAG> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord, TAG: Eq> FromIterator<(T, Vec<TAG>)> for PersistentTagMap<T, TAG> {
+    fn from_iter<I: IntoIterator<Item = (T, Vec<TAG>)>>(iter: I) -> Self {
+        iter.into_iter().fold(Self::new(), |map, (key, tags)| map.insert(key, tags))
+    }
+}
+
+impl<T: Ord, TAG: Eq> From<TagMap<T, TAG>> for PersistentTagMap<T, TAG> {
+    fn from(map: TagMap<T, TAG>) -> Self {
+        map.entries.into_iter().fold(Self::new(), |map, entry| map.with_entry(Arc::new(entry)))
+    }
+}
+
+impl<T: Ord + Clone, TAG: Eq + Clone> From<&PersistentTagMap<T, TAG>> for TagMap<T, TAG> {
+    fn from(map: &PersistentTagMap<T, TAG>) -> Self {
+        map.iter().map(|(key, tags)| (key.clone(), tags.to_vec())).collect()
+    }
+}
+
+/// An iterator over the entries of a `PersistentTagMap`, in order.
+struct Iter<'a, T: 'a, TAG: 'a> {
+    stack: Vec<&'a Node<T, TAG>>,
+    remaining: usize,
+}
+
+impl<'a, T, TAG> Iter<'a, T, TAG> {
+    fn push_left(&mut self, mut link: &'a Link<T, TAG>) {
+        while let Some(ref node) = *link {
+            self.stack.push(node);
+            link = &node.left;
+        }
+    }
+}
+
+impl<'a, T, TAG> Iterator for Iter<'a, T, TAG> {
+    type Item = (&'a T, &'a [TAG]);
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left(&node.right);
+        self.remaining -= 1;
+        Some((&node.entry.0, &node.entry.1))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T, TAG> ExactSizeIterator for Iter<'a, T, TAG> {}
+
+#[test]
+fn test_persistent() {
+    use MatchRule::*;
+    let empty = PersistentTagMap::new();
+    let v1 = empty.insert("b", vec!["red"]).insert("a", vec!["blue"]);
+    let v2 = v1.insert("c", vec!["red", "big"]).remove("a");
+    let v3 = v2.add_tag("b", "big").remove_tag("c", &"red");
+    assert!(empty.is_empty());
+    assert_eq!(v1.keys().collect::<Vec<_>>(), [&"a", &"b"]);
+    assert_eq!(v2.keys().collect::<Vec<_>>(), [&"b", &"c"]);
+    assert_eq!(v1.matching(&Tags(vec!["red"])).collect::<Vec<_>>(), [&"b"]);
+    assert_eq!(v2.matching(&Tags(vec!["red"])).collect::<Vec<_>>(), [&"b", &"c"]);
+    assert_eq!(v3.count_matching(&Tags(vec!["big"])), 2);
+    assert_eq!(v3.get_tags("c"), Some(&["big"][..]));
+    assert!(v3.remove("z").ptr_eq(&v3));
+    assert!(v3.add_tag("b", "big").ptr_eq(&v3));
+    let many: PersistentTagMap<u32, u32> = (0..1000).rev().map(|i| (i, vec![i % 3])).collect();
+    assert_eq!(many.len(), 1000);
+    assert!(many.keys().cloned().eq(0..1000));
+    let fewer = (0..1000).step_by(2).fold(many.clone(), |map, i| map.remove(&i));
+    assert_eq!(fewer.len(), 500);
+    assert_eq!(many.len(), 1000);
+    assert_eq!(TagMap::from(&fewer).count_matching(&Tags(vec![0])), 167);
+}
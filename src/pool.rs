@@ -0,0 +1,103 @@
+//! Tags shared between entries.
+
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use TagMap;
+
+/// A pool of tags, handing out one shared `Arc` for each distinct tag.
+///
+/// Maps with `Arc<TAG>` tags taken from a pool store each distinct tag once,
+/// however many entries have it. Since `Arc`s compare by value, rules can be
+/// built from pooled tags, or from fresh `Arc`s.
+#[derive(Debug, Clone)]
+pub struct TagPool<TAG: ?Sized + Hash + Eq> {
+    tags: HashSet<Arc<TAG>>,
+}
+
+impl<TAG: ?Sized + Hash + Eq> Default for TagPool<TAG> {
+    fn default() -> Self {
+        TagPool { tags: HashSet::new() }
+    }
+}
+
+impl<TAG: ?Sized + Hash + Eq> TagPool<TAG> {
+    /// Creates a new empty pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Returns the number of distinct tags in the pool.
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+    /// Returns whether the pool is empty.
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+    /// Returns the shared tag equal to `tag`, adding it to the pool if it's new.
+    ///
+    /// Accepts owned tags, or borrowed ones like `&str` for a `TagPool<str>`.
+    pub fn get<Q: Borrow<TAG> + Into<Arc<TAG>>>(&mut self, tag: Q) -> Arc<TAG> {
+        if let Some(shared) = self.tags.get(tag.borrow()) {
+            return shared.clone();
+        }
+        let shared = tag.into();
+        self.tags.insert(shared.clone());
+        shared
+    }
+    /// Removes the tags that are only referenced by the pool, returning how many were removed.
+    pub fn purge_unused(&mut self) -> usize {
+        let len = self.tags.len();
+        self.tags.retain(|tag| Arc::strong_count(tag) > 1);
+        len - self.tags.len()
+    }
+}
+
+impl<T: Ord, TAG: Hash + Eq> TagMap<T, TAG> {
+    /// Converts the map to one whose tags are shared through the given pool.
+    ///
+    /// The tags of the vocabulary are shared through the pool too.
+    pub fn into_shared(self, pool: &mut TagPool<TAG>) -> TagMap<T, Arc<TAG>> {
+        TagMap {
+            entries: self.entries
+                .into_iter()
+                .map(|(key, tags)| (key, tags.into_iter().map(|tag| pool.get(tag)).collect()))
+                .collect(),
+            vocabulary: self.vocabulary.map(|tag| pool.get(tag)),
+            key: PhantomData,
+        }
+    }
+}
+
+#[test]
+fn test_pool() {
+    use MatchRule;
+    let mut pool: TagPool<str> = TagPool::new();
+    let mut map = TagMap::new();
+    for i in 0..100 {
+        let color = if i % 2 == 0 { "red" } else { "blue" };
+        map.insert(i, vec![pool.get(color), pool.get("shape")]);
+    }
+    assert_eq!(pool.len(), 3);
+    let red = map.get_tags(&0).unwrap()[0].clone();
+    assert!(Arc::ptr_eq(&red, &map.get_tags(&98).unwrap()[0]));
+    let rule = MatchRule::Tags(vec![Arc::from("red")]);
+    assert_eq!(map.count_matching(&rule), 50);
+    map.retain_matching(&MatchRule::NotTags(vec![Arc::from("blue")]));
+    assert_eq!(pool.purge_unused(), 1);
+    let mut owned = TagPool::new();
+    let a = || vec![String::from("a")];
+    let shared = TagMap::from_sorted_iter(vec![(1, a()), (2, a())]).into_shared(&mut owned);
+    assert!(Arc::ptr_eq(&shared.get_tags(&1).unwrap()[0], &shared.get_tags(&2).unwrap()[0]));
+    let mut map = TagMap::new();
+    map.insert(1, vec![String::from("car")]);
+    map.add_alias_group(vec![String::from("car"), String::from("auto")]);
+    map.add_implication(String::from("car"), String::from("vehicle"));
+    let shared = map.into_shared(&mut owned);
+    let auto = MatchRule::Tags(vec![Arc::new(String::from("auto"))]);
+    assert_eq!(shared.matching(&shared.resolve(&auto)).collect::<Vec<_>>(), [&1]);
+    let vehicle = MatchRule::Tags(vec![Arc::new(String::from("vehicle"))]);
+    assert_eq!(shared.count_matching(&shared.resolve(&vehicle)), 1);
+}
@@ -0,0 +1,102 @@
+//! Tags that record where they came from.
+
+use std::fmt;
+use TagPattern;
+
+/// A tag with an optional source, like the user or importer that applied it.
+///
+/// Tags compare equal if the wrapped tags are equal, regardless of their
+/// sources, so ordinary rules match tags from any source. Use `FromSource`
+/// to match tags from a specific source.
+#[derive(Debug, Clone)]
+pub struct Sourced<TAG, S> {
+    /// The tag.
+    pub tag: TAG,
+    /// Where the tag came from, if known.
+    pub source: Option<S>,
+}
+
+impl<TAG, S> Sourced<TAG, S> {
+    /// Creates a tag with an unknown source.
+    pub fn new(tag: TAG) -> Self {
+        Sourced { tag, source: None }
+    }
+    /// Creates a tag with the given source.
+    pub fn with_source(tag: TAG, source: S) -> Self {
+        Sourced {
+            tag,
+            source: Some(source),
+        }
+    }
+}
+
+impl<TAG, S> From<TAG> for Sourced<TAG, S> {
+    fn from(tag: TAG) -> Self {
+        Sourced::new(tag)
+    }
+}
+
+impl<TAG: PartialEq, S> PartialEq for Sourced<TAG, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl<TAG: Eq, S> Eq for Sourced<TAG, S> {}
+
+impl<TAG: fmt::Display, S> fmt::Display for Sourced<TAG, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.tag.fmt(f)
+    }
+}
+
+/// Matches a tag applied by the given source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FromSource<TAG, S> {
+    tag: TAG,
+    source: S,
+}
+
+impl<TAG, S> FromSource<TAG, S> {
+    /// Creates a new pattern.
+    pub fn new(tag: TAG, source: S) -> Self {
+        FromSource { tag, source }
+    }
+    /// Returns the tag.
+    pub fn tag(&self) -> &TAG {
+        &self.tag
+    }
+    /// Returns the source.
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+}
+
+impl<TAG, S> TagPattern<Sourced<TAG, S>> for FromSource<TAG, S>
+    where TAG: Eq + fmt::Debug + Send + Sync,
+          S: Eq + fmt::Debug + Send + Sync
+{
+    fn matches_tag(&self, tag: &Sourced<TAG, S>) -> bool {
+        tag.tag == self.tag && tag.source.as_ref() == Some(&self.source)
+    }
+}
+
+#[test]
+fn test_provenance() {
+    use {MatchRule, TagMap};
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Source {
+        Human,
+        Classifier,
+    }
+    let mut map = TagMap::new();
+    map.add_tag("a.png", Sourced::with_source("nsfw", Source::Human));
+    map.add_tag("b.png", Sourced::with_source("nsfw", Source::Classifier));
+    map.add_tag("c.png", Sourced::new("nsfw"));
+    assert!(!map.add_tag("a.png", Sourced::with_source("nsfw", Source::Classifier)));
+    let any_source = MatchRule::all_of(vec!["nsfw".into()]);
+    assert_eq!(map.count_matching(&any_source), 3);
+    let human = MatchRule::tag_from_source("nsfw", Source::Human);
+    assert_eq!(map.matching(&human).cloned().collect::<Vec<_>>(), ["a.png"]);
+    assert_eq!(any_source.to_string(), "nsfw");
+}
@@ -0,0 +1,97 @@
+//! Ranking matching entries by relevance.
+
+use std::cmp::Reverse;
+use {MatchRule, Storage, TagMap, count_present};
+
+impl<TAG: Eq> MatchRule<TAG> {
+    /// Returns how strongly the given tags match the rule.
+    ///
+    /// The score is the number of distinct tags listed by the rule outside of any
+    /// negation that are present, plus the number of tags matched by its patterns.
+    /// With `AnyTag` or `AtLeast`, entries having more of the listed tags score higher.
+    pub fn score(&self, tags: &[TAG]) -> usize {
+        use MatchRule::*;
+        let sum = |rules: &[MatchRule<TAG>]| rules.iter().map(|rule| rule.score(tags)).sum();
+        match *self {
+            Tags(ref m_tags) | AnyTag(ref m_tags) | AtLeast(_, ref m_tags) |
+            Exactly(_, ref m_tags) => count_present(tags, m_tags, usize::MAX),
+            Rules(ref rules) | AnyRule(ref rules) | Xor(ref rules) => sum(rules),
+            Pattern(ref pattern) => tags.iter().filter(|tag| pattern.matches_tag(tag)).count(),
+            NotTags(_) | NotRules(_) | AtMost(..) | Not(_) | Anything | Nothing |
+            Predicate(_) => 0,
+        }
+    }
+}
+
+/// Something that can score how strongly the tags of an entry match a rule.
+///
+/// Used by `TagMap::matching_scored`. Closures taking the tags and the rule can be
+/// used as scorers.
+pub trait Scorer<TAG> {
+    /// Returns the score of the given tags for the rule, higher meaning more relevant.
+    fn score(&self, tags: &[TAG], rule: &MatchRule<TAG>) -> f32;
+}
+
+/// The default `Scorer`, scoring entries with `MatchRule::score`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TagOverlap;
+
+impl<TAG: Eq> Scorer<TAG> for TagOverlap {
+    fn score(&self, tags: &[TAG], rule: &MatchRule<TAG>) -> f32 {
+        rule.score(tags) as f32
+    }
+}
+
+impl<TAG, F: Fn(&[TAG], &MatchRule<TAG>) -> f32> Scorer<TAG> for F {
+    fn score(&self, tags: &[TAG], rule: &MatchRule<TAG>) -> f32 {
+        self(tags, rule)
+    }
+}
+
+impl<T, TAG: Eq, B: Storage<T, TAG>> TagMap<T, TAG, B> {
+    /// Returns the keys of the entries matching the rule with their scores, highest
+    /// score first.
+    ///
+    /// See `MatchRule::score`. Entries with the same score are in the same order as
+    /// `iter`.
+    pub fn matching_ranked(&self, rule: &MatchRule<TAG>) -> Vec<(&T, usize)> {
+        let mut ranked: Vec<_> = self.matching_entries(rule)
+            .map(|(key, tags)| (key, rule.score(tags)))
+            .collect();
+        ranked.sort_by_key(|&(_, score)| Reverse(score));
+        ranked
+    }
+    /// Returns the scores of the entries matching the rule with their keys, highest
+    /// score first.
+    ///
+    /// Entries with the same score are in the same order as `iter`.
+    pub fn matching_scored<S>(&self, rule: &MatchRule<TAG>, scorer: &S) -> Vec<(f32, &T)>
+        where S: ?Sized + Scorer<TAG>
+    {
+        let mut scored: Vec<_> = self.matching_entries(rule)
+            .map(|(key, tags)| (scorer.score(tags, rule), key))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored
+    }
+}
+
+#[test]
+fn test_matching_ranked() {
+    use MatchRule::*;
+    let mut map = TagMap::new();
+    map.insert("a", vec!["rust", "cli"]);
+    map.insert("b", vec!["rust", "cli", "async", "tui"]);
+    map.insert("c", vec!["rust", "web"]);
+    map.insert("d", vec!["go", "cli", "async"]);
+    let rule = Tags(vec!["rust"]) & AnyTag(vec!["cli", "async", "tui"]);
+    assert_eq!(map.matching_ranked(&rule), [(&"b", 4), (&"a", 2)]);
+    let rule = AtLeast(2, vec!["cli", "async", "tui"]) & !Tags(vec!["go"]);
+    assert_eq!(rule.score(&["cli", "async", "go"]), 2);
+    assert_eq!(map.matching_ranked(&rule), [(&"b", 3)]);
+    assert_eq!(map.matching_ranked(&Anything).len(), 4);
+    assert_eq!(map.matching_scored(&rule, &TagOverlap), [(3.0, &"b")]);
+    let fewer_tags = |tags: &[&str], _: &MatchRule<&str>| -(tags.len() as f32);
+    let scored = map.matching_scored(&Anything, &fewer_tags);
+    assert_eq!(scored, [(-2.0, &"a"), (-2.0, &"c"), (-3.0, &"d"), (-4.0, &"b")]);
+}
@@ -0,0 +1,63 @@
+//! Serde support for `TagMap`.
+
+use serde::Deserialize;
+use serde::de::Deserializer;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::collections::BTreeMap;
+use {TagMap, Vocabulary};
+
+/// Serializes the entries of a map as a map from keys to tag sequences.
+pub(crate) struct Entries<'a, T: 'a, TAG: 'a + Eq>(pub(crate) &'a TagMap<T, TAG>);
+
+impl<'a, T: Ord + Serialize, TAG: Eq + Serialize> Serialize for Entries<'a, T, TAG> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter())
+    }
+}
+
+/// A map is serialized as a struct with an `entries` map from keys to tag sequences,
+/// and its `vocabulary`.
+impl<T: Ord + Serialize, TAG: Eq + Serialize> Serialize for TagMap<T, TAG> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("TagMap", 2)?;
+        state.serialize_field("entries", &Entries(self))?;
+        state.serialize_field("vocabulary", &self.vocabulary)?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "T: Ord + Deserialize<'de>, TAG: Deserialize<'de>"))]
+struct Fields<T, TAG> {
+    entries: BTreeMap<T, Vec<TAG>>,
+    #[serde(default)]
+    vocabulary: Vocabulary<TAG>,
+}
+
+/// A missing vocabulary deserializes as an empty one.
+impl<'de, T, TAG> Deserialize<'de> for TagMap<T, TAG>
+    where T: Ord + Deserialize<'de>,
+          TAG: Eq + Deserialize<'de>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = Fields::deserialize(deserializer)?;
+        let mut map = TagMap::from_sorted_iter(fields.entries);
+        map.vocabulary = fields.vocabulary;
+        Ok(map)
+    }
+}
+
+#[test]
+fn test_serde() {
+    let mut map = TagMap::new();
+    map.insert("a".to_string(), vec!["x".to_string(), "y".to_string()]);
+    map.insert("b".to_string(), vec![]);
+    map.add_implication("x".to_string(), "z".to_string());
+    let json = ::serde_json::to_string(&map).unwrap();
+    assert!(json.starts_with(r#"{"entries":{"a":["x","y"],"b":[]},"vocabulary":"#));
+    let loaded: TagMap<String, String> = ::serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded, map);
+    assert_eq!(loaded.vocabulary(), map.vocabulary());
+    let bare: TagMap<u32, String> = ::serde_json::from_str(r#"{"entries":{"1":["a"]}}"#).unwrap();
+    assert_eq!(bare.get_tags(&1), Some(&["a".to_string()][..]));
+}
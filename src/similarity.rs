@@ -0,0 +1,119 @@
+//! Similarity between the tags of entries.
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use {Lookup, Storage, TagMap};
+
+/// Returns the Jaccard index of two tag lists, treated as sets.
+///
+/// This is the number of distinct tags they share, divided by the number of distinct
+/// tags in either. Two empty lists have a similarity of 1.
+pub fn jaccard<TAG: Eq>(a: &[TAG], b: &[TAG]) -> f64 {
+    let distinct = |tags: &[TAG]| {
+        tags.iter().enumerate().filter(|&(i, tag)| !tags[..i].contains(tag)).count()
+    };
+    let shared = a.iter()
+        .enumerate()
+        .filter(|&(i, tag)| !a[..i].contains(tag) && b.contains(tag))
+        .count();
+    let union = distinct(a) + distinct(b) - shared;
+    if union == 0 {
+        1.0
+    } else {
+        shared as f64 / union as f64
+    }
+}
+
+impl<T, TAG: Eq, B: Storage<T, TAG>> TagMap<T, TAG, B> {
+    /// Returns the other entries whose tags have a Jaccard index of at least
+    /// `min_similarity` with the tags of the given entry, most similar first.
+    ///
+    /// Entries with the same similarity are in the same order as `iter`. Returns
+    /// nothing if the entry isn't present.
+    pub fn similar_to<Q>(&self, key: &Q, min_similarity: f64) -> Vec<(&T, f64)>
+        where Q: ?Sized + PartialEq,
+              T: Borrow<Q>,
+              B: Lookup<Q, T, TAG>
+    {
+        self.similar_by(key, min_similarity, jaccard)
+    }
+    /// Like `similar_to`, but measuring the similarity of two tag lists with `metric`.
+    pub fn similar_by<Q, F>(&self, key: &Q, min_similarity: f64, metric: F) -> Vec<(&T, f64)>
+        where Q: ?Sized + PartialEq,
+              T: Borrow<Q>,
+              B: Lookup<Q, T, TAG>,
+              F: Fn(&[TAG], &[TAG]) -> f64
+    {
+        let tags = match self.get_tags(key) {
+            Some(tags) => tags,
+            None => return Vec::new(),
+        };
+        let mut similar: Vec<_> = self.iter()
+            .filter(|&(other, _)| other.borrow() != key)
+            .map(|(other, other_tags)| (other, metric(tags, other_tags)))
+            .filter(|&(_, similarity)| similarity >= min_similarity)
+            .collect();
+        similar.sort_by(|a, b| b.1.total_cmp(&a.1));
+        similar
+    }
+    /// Groups the entries into clusters of similar tags, returning the cluster of each key.
+    ///
+    /// Entries are visited in the same order as `iter`. Each one joins the cluster whose first entry has
+    /// the most similar tags, if their Jaccard index is at least `min_similarity`, or
+    /// starts a new cluster otherwise. Clusters are numbered from 0, in order of creation.
+    pub fn clusters(&self, min_similarity: f64) -> BTreeMap<&T, usize>
+        where T: Ord
+    {
+        let mut leaders: Vec<&[TAG]> = Vec::new();
+        let mut clusters = BTreeMap::new();
+        for (key, tags) in self.iter() {
+            let best = leaders.iter()
+                .map(|leader| jaccard(leader, tags))
+                .enumerate()
+                .filter(|&(_, similarity)| similarity >= min_similarity)
+                .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)));
+            let cluster = match best {
+                Some((cluster, _)) => cluster,
+                None => {
+                    leaders.push(tags);
+                    leaders.len() - 1
+                }
+            };
+            clusters.insert(key, cluster);
+        }
+        clusters
+    }
+}
+
+#[test]
+fn test_similar_to() {
+    assert_eq!(jaccard(&["a", "b", "b"], &["b", "c"]), 1.0 / 3.0);
+    assert_eq!(jaccard::<&str>(&[], &[]), 1.0);
+    let mut map = TagMap::new();
+    map.insert("a", vec!["red", "round", "small"]);
+    map.insert("b", vec!["red", "round"]);
+    map.insert("c", vec!["blue", "square"]);
+    map.insert("d", vec!["small", "round", "red"]);
+    map.insert("e", vec!["red"]);
+    let similar = map.similar_to("a", 0.3);
+    assert_eq!(similar, [(&"d", 1.0), (&"b", 2.0 / 3.0), (&"e", 1.0 / 3.0)]);
+    assert!(map.similar_to("z", 0.0).is_empty());
+    let shared = map.similar_by("b", 1.0, |a, b| {
+        a.iter().filter(|tag| b.contains(tag)).count() as f64
+    });
+    assert_eq!(shared, [(&"a", 2.0), (&"d", 2.0), (&"e", 1.0)]);
+}
+
+#[test]
+fn test_clusters() {
+    let mut map = TagMap::new();
+    map.insert(1, vec!["cat", "cute", "pet"]);
+    map.insert(2, vec!["car", "red", "fast"]);
+    map.insert(3, vec!["cat", "pet"]);
+    map.insert(4, vec!["car", "fast"]);
+    map.insert(5, vec!["tree"]);
+    map.insert(6, vec!["car", "cat"]);
+    let clusters = map.clusters(0.5);
+    assert_eq!(clusters.values().cloned().collect::<Vec<_>>(), [0, 1, 0, 1, 2, 3]);
+    assert!(map.clusters(0.0).values().all(|&cluster| cluster == 0));
+}
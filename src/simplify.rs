@@ -0,0 +1,314 @@
+//! Simplification and normalization of `MatchRule`s.
+
+use MatchRule;
+use MatchRule::*;
+
+impl<TAG: Eq> MatchRule<TAG> {
+    /// Returns an equivalent, simplified rule.
+    ///
+    /// Nested `Rules` and `AnyRule`s are flattened, duplicate tags and rules are
+    /// removed, double negations are eliminated, and rules that always or never
+    /// match are folded into `Anything` and `Nothing`.
+    pub fn simplify(self) -> Self {
+        match self {
+            Tags(tags) => {
+                let tags = dedup(tags);
+                if tags.is_empty() { Anything } else { Tags(tags) }
+            }
+            NotTags(tags) => {
+                let tags = dedup(tags);
+                if tags.is_empty() { Anything } else { NotTags(tags) }
+            }
+            AnyTag(tags) => {
+                let tags = dedup(tags);
+                match tags.len() {
+                    0 => Nothing,
+                    1 => Tags(tags),
+                    _ => AnyTag(tags),
+                }
+            }
+            AtLeast(n, tags) => {
+                let tags = dedup(tags);
+                if n == 0 {
+                    Anything
+                } else if n > tags.len() {
+                    Nothing
+                } else if n == tags.len() {
+                    Tags(tags)
+                } else if n == 1 {
+                    AnyTag(tags)
+                } else {
+                    AtLeast(n, tags)
+                }
+            }
+            Exactly(n, tags) => {
+                let tags = dedup(tags);
+                if n > tags.len() {
+                    Nothing
+                } else if n == 0 {
+                    NotTags(tags).simplify()
+                } else if n == tags.len() {
+                    Tags(tags)
+                } else {
+                    Exactly(n, tags)
+                }
+            }
+            AtMost(n, tags) => {
+                let tags = dedup(tags);
+                if n >= tags.len() {
+                    Anything
+                } else if n == 0 {
+                    NotTags(tags)
+                } else {
+                    AtMost(n, tags)
+                }
+            }
+            Rules(rules) => simplify_all(rules),
+            AnyRule(rules) => simplify_any(rules),
+            NotRules(rules) => simplify_any(rules).negated(),
+            Not(rule) => rule.simplify().negated(),
+            Xor(rules) => {
+                let mut rules: Vec<_> = rules.into_iter()
+                    .map(MatchRule::simplify)
+                    .filter(|rule| *rule != Nothing)
+                    .collect();
+                match rules.len() {
+                    0 => Nothing,
+                    1 => rules.pop().unwrap(),
+                    _ => Xor(rules),
+                }
+            }
+            rule => rule,
+        }
+    }
+    /// Returns the negation of the rule, without descending into it.
+    pub(crate) fn negated(self) -> Self {
+        match self {
+            Anything => Nothing,
+            Nothing => Anything,
+            Not(rule) => *rule,
+            Tags(tags) => {
+                if tags.len() == 1 { NotTags(tags) } else { Not(Box::new(Tags(tags))) }
+            }
+            NotTags(tags) => if tags.len() == 1 { Tags(tags) } else { AnyTag(tags) },
+            AnyTag(tags) => NotTags(tags),
+            AnyRule(rules) => NotRules(rules),
+            NotRules(rules) => AnyRule(rules),
+            rule => Not(Box::new(rule)),
+        }
+    }
+}
+
+impl<TAG: Clone> MatchRule<TAG> {
+    /// Returns a rule matching exactly the entries this rule doesn't.
+    ///
+    /// Negations are pushed down to the tags using De Morgan's laws, and tag counts
+    /// are inverted. Rules without a direct complement, like `Xor` and predicates,
+    /// are wrapped in `Not`.
+    pub fn complement(self) -> Self {
+        match self {
+            Anything => Nothing,
+            Nothing => Anything,
+            Not(rule) => *rule,
+            Tags(tags) => {
+                if tags.len() == 1 {
+                    NotTags(tags)
+                } else {
+                    AnyRule(tags.into_iter().map(|tag| NotTags(vec![tag])).collect())
+                }
+            }
+            NotTags(tags) => AnyTag(tags),
+            AnyTag(tags) => NotTags(tags),
+            Rules(rules) => AnyRule(rules.into_iter().map(MatchRule::complement).collect()),
+            AnyRule(rules) => Rules(rules.into_iter().map(MatchRule::complement).collect()),
+            NotRules(rules) => AnyRule(rules),
+            AtLeast(0, _) => Nothing,
+            AtLeast(n, tags) => AtMost(n - 1, tags),
+            AtMost(n, tags) => n.checked_add(1).map_or(Nothing, |n| AtLeast(n, tags)),
+            Exactly(0, tags) => AtLeast(1, tags),
+            Exactly(n, tags) => match n.checked_add(1) {
+                Some(more) => AnyRule(vec![AtMost(n - 1, tags.clone()), AtLeast(more, tags)]),
+                None => AtMost(n - 1, tags),
+            },
+            rule => Not(Box::new(rule)),
+        }
+    }
+}
+
+impl<TAG: Clone + Eq> MatchRule<TAG> {
+    /// Returns an equivalent rule in disjunctive normal form.
+    ///
+    /// The result is an `AnyRule` of `Rules`, whose members are single tag tests,
+    /// negated tag tests, or rules that can't be broken down further, like
+    /// predicates. Note that the result can be exponentially larger than the rule.
+    pub fn to_dnf(&self) -> Self {
+        let conjunctions = dnf(self.clone());
+        AnyRule(conjunctions.into_iter().map(Rules).collect()).simplify()
+    }
+}
+
+fn dedup<T: PartialEq>(items: Vec<T>) -> Vec<T> {
+    let mut out = Vec::with_capacity(items.len());
+    for item in items {
+        if !out.contains(&item) {
+            out.push(item);
+        }
+    }
+    out
+}
+
+fn simplify_all<TAG: Eq>(rules: Vec<MatchRule<TAG>>) -> MatchRule<TAG> {
+    let (mut tags, mut not_tags, mut out) = (Vec::new(), Vec::new(), Vec::new());
+    let mut pending: Vec<_> = rules.into_iter().rev().map(MatchRule::simplify).collect();
+    while let Some(rule) = pending.pop() {
+        match rule {
+            Anything => {}
+            Nothing => return Nothing,
+            Rules(inner) => pending.extend(inner.into_iter().rev()),
+            Tags(t) => tags.extend(t),
+            NotTags(t) => not_tags.extend(t),
+            rule => {
+                if !out.contains(&rule) {
+                    out.push(rule);
+                }
+            }
+        }
+    }
+    let (tags, not_tags) = (dedup(tags), dedup(not_tags));
+    if tags.iter().any(|tag| not_tags.contains(tag)) {
+        return Nothing;
+    }
+    if !not_tags.is_empty() {
+        out.insert(0, NotTags(not_tags));
+    }
+    if !tags.is_empty() {
+        out.insert(0, Tags(tags));
+    }
+    match out.len() {
+        0 => Anything,
+        1 => out.pop().unwrap(),
+        _ => Rules(out),
+    }
+}
+
+fn simplify_any<TAG: Eq>(rules: Vec<MatchRule<TAG>>) -> MatchRule<TAG> {
+    let (mut tags, mut out) = (Vec::new(), Vec::new());
+    let mut pending: Vec<_> = rules.into_iter().rev().map(MatchRule::simplify).collect();
+    while let Some(mut rule) = pending.pop() {
+        match rule {
+            Nothing => {}
+            Anything => return Anything,
+            AnyRule(inner) => pending.extend(inner.into_iter().rev()),
+            AnyTag(t) => tags.extend(t),
+            Tags(ref mut t) if t.len() == 1 => tags.append(t),
+            rule => {
+                if !out.contains(&rule) {
+                    out.push(rule);
+                }
+            }
+        }
+    }
+    let tags = dedup(tags);
+    if !tags.is_empty() {
+        out.insert(0, AnyTag(tags).simplify());
+    }
+    match out.len() {
+        0 => Nothing,
+        1 => out.pop().unwrap(),
+        _ => AnyRule(out),
+    }
+}
+
+/// Returns the rule as a list of alternatives, each a list of rules that must all match.
+fn dnf<TAG: Clone + Eq>(rule: MatchRule<TAG>) -> Vec<Vec<MatchRule<TAG>>> {
+    match rule {
+        Anything => vec![vec![]],
+        Nothing => vec![],
+        Tags(tags) => vec![tags.into_iter().map(|tag| Tags(vec![tag])).collect()],
+        NotTags(tags) => vec![tags.into_iter().map(|tag| NotTags(vec![tag])).collect()],
+        AnyTag(tags) => tags.into_iter().map(|tag| vec![Tags(vec![tag])]).collect(),
+        AnyRule(rules) => rules.into_iter().flat_map(dnf).collect(),
+        Rules(rules) => {
+            rules.into_iter().fold(vec![vec![]], |acc, rule| {
+                let alternatives = dnf(rule);
+                let mut out = Vec::with_capacity(acc.len() * alternatives.len());
+                for left in &acc {
+                    for right in &alternatives {
+                        out.push(left.iter().chain(right).cloned().collect());
+                    }
+                }
+                out
+            })
+        }
+        NotRules(rules) => dnf(Rules(rules.into_iter().map(MatchRule::negated).collect())),
+        Not(rule) => {
+            match *rule {
+                Rules(rules) => dnf(AnyRule(rules.into_iter().map(MatchRule::negated).collect())),
+                Tags(tags) => tags.into_iter().map(|tag| vec![NotTags(vec![tag])]).collect(),
+                rule => {
+                    match rule.negated() {
+                        Not(rule) => vec![vec![Not(rule)]],
+                        rule => dnf(rule),
+                    }
+                }
+            }
+        }
+        rule => vec![vec![rule]],
+    }
+}
+
+#[test]
+fn test_simplify() {
+    use Matcher;
+    let rule = Rules(vec![Rules(vec![Tags(vec!["a", "a"]), Anything]),
+                          Not(Box::new(Not(Box::new(Tags(vec!["b"]))))),
+                          NotRules(vec![AnyTag(vec!["c", "d", "c"])])]);
+    assert_eq!(rule.simplify(), Rules(vec![Tags(vec!["a", "b"]), NotTags(vec!["c", "d"])]));
+    assert_eq!(Rules(vec![Tags(vec!["a"]), NotTags(vec!["a"])]).simplify(), Nothing);
+    assert_eq!(AnyRule(vec![AnyTag(vec!["a"]), AnyRule(vec![Tags(vec!["b"]), Nothing])])
+                   .simplify(),
+               AnyTag(vec!["a", "b"]));
+    assert_eq!(AtLeast(3, vec!["a", "b", "b"]).simplify(), Nothing);
+    let rules = [Rules(vec![AnyTag(vec!["a", "b"]), Not(Box::new(Tags(vec!["c", "d"])))]),
+                 Not(Box::new(Rules(vec![AnyTag(vec!["a", "b"]), NotTags(vec!["c"])]))),
+                 Xor(vec![Tags(vec!["a"]), AnyRule(vec![Tags(vec!["b"]), Tags(vec!["c"])])]),
+                 AtLeast(2, vec!["a", "b", "c", "d"])];
+    let all = ["a", "b", "c", "d"];
+    for rule in &rules {
+        let (simplified, dnf) = (rule.clone().simplify(), rule.to_dnf());
+        for bits in 0..16 {
+            let tags: Vec<_> = (0..4).filter(|i| bits & (1 << i) != 0).map(|i| all[i]).collect();
+            assert_eq!(simplified.matches(&tags), rule.matches(&tags), "{:?}", rule);
+            assert_eq!(dnf.matches(&tags), rule.matches(&tags), "{:?}", rule);
+        }
+    }
+}
+
+#[test]
+fn test_complement() {
+    use Matcher;
+    let rules = [Tags(vec!["a", "b"]),
+                 Rules(vec![AnyTag(vec!["a", "b"]), NotTags(vec!["c"])]),
+                 AnyRule(vec![NotRules(vec![Tags(vec!["a"])]), Not(Box::new(AnyTag(vec!["d"])))]),
+                 Xor(vec![Tags(vec!["a"]), Tags(vec!["b"])]),
+                 AtLeast(0, vec!["a"]),
+                 AtLeast(2, vec!["a", "b", "c"]),
+                 Exactly(0, vec!["a", "b"]),
+                 Exactly(2, vec!["a", "b", "c"]),
+                 AtMost(1, vec!["b", "c", "d"]),
+                 AtMost(usize::MAX, vec!["a", "b"]),
+                 Exactly(usize::MAX, vec!["a", "b"]),
+                 Anything];
+    let all = ["a", "b", "c", "d"];
+    for rule in &rules {
+        let complement = rule.clone().complement();
+        for bits in 0..16 {
+            let tags: Vec<_> = (0..4).filter(|i| bits & (1 << i) != 0).map(|i| all[i]).collect();
+            assert_ne!(complement.matches(&tags), rule.matches(&tags), "{:?}", rule);
+        }
+    }
+    assert_eq!(Rules(vec![Tags(vec!["a"]), AnyTag(vec!["b", "c"])]).complement(),
+               AnyRule(vec![NotTags(vec!["a"]), NotTags(vec!["b", "c"])]));
+    assert_eq!(AtMost(usize::MAX, vec!["a"]).complement(), Nothing);
+    assert_eq!(Exactly(usize::MAX, vec!["a"]).complement(), AtMost(usize::MAX - 1, vec!["a"]));
+}
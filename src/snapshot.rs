@@ -0,0 +1,133 @@
+//! A mutable tag map with cheap snapshots.
+
+use persistent::PersistentTagMap;
+use std::borrow::Borrow;
+use std::ops::Deref;
+use TagMap;
+
+/// A mutable tag map that can take snapshots in constant time.
+///
+/// The entries are stored in a `PersistentTagMap`, so a snapshot shares them with the
+/// map, and changing an entry afterwards only copies the path to it, leaving the
+/// snapshot as it was. Snapshots can be queried from other threads while the map keeps
+/// changing. The map can be read through `Deref`.
+#[derive(Debug, Clone)]
+pub struct CowTagMap<T, TAG> {
+    map: PersistentTagMap<T, TAG>,
+}
+
+/// A frozen view of a `CowTagMap`, returned by `CowTagMap::snapshot`.
+///
+/// Can be read through `Deref`, and cloned cheaply.
+#[derive(Debug, Clone)]
+pub struct TagMapSnapshot<T, TAG> {
+    map: PersistentTagMap<T, TAG>,
+}
+
+impl<T, TAG> CowTagMap<T, TAG> {
+    /// Creates a new empty map.
+    pub fn new() -> Self {
+        CowTagMap { map: PersistentTagMap::new() }
+    }
+    /// Returns a snapshot of the map as it is now, in constant time.
+    pub fn snapshot(&self) -> TagMapSnapshot<T, TAG> {
+        TagMapSnapshot { map: self.map.clone() }
+    }
+    /// Removes all entries from the map.
+    pub fn clear(&mut self) {
+        self.map = PersistentTagMap::new();
+    }
+    /// Returns the underlying map.
+    pub fn into_inner(self) -> PersistentTagMap<T, TAG> {
+        self.map
+    }
+}
+
+impl<T: Ord, TAG: Eq> CowTagMap<T, TAG> {
+    /// Inserts an entry with the given tags.
+    ///
+    /// Returns whether the key was already present. Its old tags may be shared with
+    /// snapshots, so unlike `TagMap::insert`, they aren't returned.
+    pub fn insert(&mut self, key: T, tags: Vec<TAG>) -> bool {
+        let present = self.map.contains_key(&key);
+        self.map = self.map.insert(key, tags);
+        present
+    }
+    /// Removes an entry, returning whether it was present.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> bool
+        where T: Borrow<Q>
+    {
+        let present = self.map.contains_key(key);
+        self.map = self.map.remove(key);
+        present
+    }
+}
+
+impl<T: Ord + Clone, TAG: Eq + Clone> CowTagMap<T, TAG> {
+    /// Adds a tag to an entry, inserting the entry if it's not present.
+    ///
+    /// Returns false if the entry already had the tag.
+    pub fn add_tag(&mut self, key: T, tag: TAG) -> bool {
+        let added = !self.map.has_tag(&key, &tag);
+        self.map = self.map.add_tag(key, tag);
+        added
+    }
+    /// Removes a tag from an entry.
+    ///
+    /// Returns false if the entry is not present or didn't have the tag.
+    pub fn remove_tag<Q: ?Sized + Ord>(&mut self, key: &Q, tag: &TAG) -> bool
+        where T: Borrow<Q>
+    {
+        let removed = self.map.has_tag(key, tag);
+        self.map = self.map.remove_tag(key, tag);
+        removed
+    }
+}
+
+impl<T, TAG> Default for CowTagMap<T, TAG> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord, TAG: Eq> From<TagMap<T, TAG>> for CowTagMap<T, TAG> {
+    fn from(map: TagMap<T, TAG>) -> Self {
+        CowTagMap { map: PersistentTagMap::from(map) }
+    }
+}
+
+impl<T, TAG> Deref for CowTagMap<T, TAG> {
+    type Target = PersistentTagMap<T, TAG>;
+    fn deref(&self) -> &PersistentTagMap<T, TAG> {
+        &self.map
+    }
+}
+
+impl<T, TAG> Deref for TagMapSnapshot<T, TAG> {
+    type Target = PersistentTagMap<T, TAG>;
+    fn deref(&self) -> &PersistentTagMap<T, TAG> {
+        &self.map
+    }
+}
+
+#[test]
+fn test_snapshot() {
+    use std::thread;
+    use MatchRule::*;
+    let mut map = CowTagMap::new();
+    map.insert(1, vec!["inbox"]);
+    map.insert(2, vec!["inbox", "starred"]);
+    let snapshot = map.snapshot();
+    assert!(map.remove_tag(&1, &"inbox"));
+    assert!(map.add_tag(3, "inbox"));
+    assert!(map.remove(&2));
+    let inbox = Tags(vec!["inbox"]);
+    assert_eq!(map.matching(&inbox).collect::<Vec<_>>(), [&3]);
+    let background = thread::spawn(move || {
+        snapshot.matching(&Tags(vec!["inbox"])).cloned().collect::<Vec<_>>()
+    });
+    assert_eq!(background.join().unwrap(), [1, 2]);
+    assert!(map.insert(3, vec![]));
+    map.clear();
+    assert!(map.is_empty());
+}
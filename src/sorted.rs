@@ -0,0 +1,162 @@
+//! A tag map keeping the tags of each entry sorted.
+
+use std::borrow::Borrow;
+use std::ops::Deref;
+use {MatchRule, TagMap};
+
+/// A `TagMap` that keeps the tags of each entry sorted and free of duplicates.
+///
+/// Rules passed to `matching` test tag membership with binary searches instead of
+/// linear scans. The map can be read through `Deref`, but must be modified through
+/// the methods of this type, to keep the tags sorted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortedTagMap<T: Ord, TAG: Eq> {
+    map: TagMap<T, TAG>,
+}
+
+impl<T: Ord, TAG: Ord> SortedTagMap<T, TAG> {
+    /// Creates a new empty map.
+    pub fn new() -> Self {
+        SortedTagMap { map: TagMap::new() }
+    }
+    /// Returns the underlying map.
+    pub fn into_inner(self) -> TagMap<T, TAG> {
+        self.map
+    }
+    /// Frees the unused capacity of the tag lists.
+    pub fn compact(&mut self) {
+        self.map.compact();
+    }
+    /// Inserts an entry with the given tags, sorting them.
+    ///
+    /// If the key was already present, its old tags are replaced and returned.
+    pub fn insert(&mut self, key: T, mut tags: Vec<TAG>) -> Option<Vec<TAG>> {
+        tags.sort();
+        tags.dedup();
+        self.map.insert(key, tags)
+    }
+    /// Removes an entry, returning its tags if it was present.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<Vec<TAG>>
+        where T: Borrow<Q>
+    {
+        self.map.remove(key)
+    }
+    /// Returns whether the entry for the given key has the given tag.
+    pub fn has_tag<Q: ?Sized + Ord>(&self, key: &Q, tag: &TAG) -> bool
+        where T: Borrow<Q>
+    {
+        self.map.get_tags(key).is_some_and(|tags| tags.binary_search(tag).is_ok())
+    }
+    /// Adds a tag to an entry, inserting the entry if it's not present.
+    ///
+    /// Returns false if the entry already had the tag.
+    pub fn add_tag(&mut self, key: T, tag: TAG) -> bool {
+        let tags = self.map.entry(key).or_default();
+        match tags.binary_search(&tag) {
+            Ok(_) => false,
+            Err(i) => {
+                tags.insert(i, tag);
+                true
+            }
+        }
+    }
+    /// Removes a tag from an entry.
+    ///
+    /// Returns false if the entry is not present or didn't have the tag.
+    pub fn remove_tag<Q: ?Sized + Ord>(&mut self, key: &Q, tag: &TAG) -> bool
+        where T: Borrow<Q>
+    {
+        self.map.remove_tag(key, tag)
+    }
+    /// Returns the keys of the entries matching the given rule, in order.
+    pub fn matching<'a>(&'a self, rule: &'a MatchRule<TAG>) -> impl Iterator<Item = &'a T> + 'a {
+        self.map.iter().filter_map(move |(key, tags)| {
+            if matches_sorted(tags, rule) {
+                Some(key)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl<T: Ord, TAG: Ord> Default for SortedTagMap<T, TAG> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord, TAG: Ord> From<TagMap<T, TAG>> for SortedTagMap<T, TAG> {
+    fn from(mut map: TagMap<T, TAG>) -> Self {
+        for (_, tags) in map.iter_mut() {
+            tags.sort();
+            tags.dedup();
+        }
+        SortedTagMap { map }
+    }
+}
+
+impl<T: Ord, TAG: Eq> Deref for SortedTagMap<T, TAG> {
+    type Target = TagMap<T, TAG>;
+    fn deref(&self) -> &TagMap<T, TAG> {
+        &self.map
+    }
+}
+
+fn has<TAG: Ord>(tags: &[TAG], tag: &TAG) -> bool {
+    tags.binary_search(tag).is_ok()
+}
+
+/// Evaluates a rule against sorted tags.
+fn matches_sorted<TAG: Ord>(tags: &[TAG], rule: &MatchRule<TAG>) -> bool {
+    use MatchRule::*;
+    // Like `count_present`, counts each distinct listed tag once
+    let count = |m_tags: &[TAG]| {
+        m_tags.iter()
+            .enumerate()
+            .filter(|&(i, tag)| has(tags, tag) && !m_tags[..i].contains(tag))
+            .count()
+    };
+    match *rule {
+        Tags(ref m_tags) => m_tags.iter().all(|tag| has(tags, tag)),
+        NotTags(ref m_tags) => !m_tags.iter().any(|tag| has(tags, tag)),
+        AnyTag(ref m_tags) => m_tags.iter().any(|tag| has(tags, tag)),
+        Rules(ref rules) => rules.iter().all(|rule| matches_sorted(tags, rule)),
+        NotRules(ref rules) => !rules.iter().any(|rule| matches_sorted(tags, rule)),
+        AnyRule(ref rules) => rules.iter().any(|rule| matches_sorted(tags, rule)),
+        Xor(ref rules) => {
+            let mut matching = rules.iter().filter(|rule| matches_sorted(tags, rule));
+            matching.next().is_some() && matching.next().is_none()
+        }
+        AtLeast(n, ref m_tags) => count(m_tags) >= n,
+        Exactly(n, ref m_tags) => count(m_tags) == n,
+        AtMost(n, ref m_tags) => count(m_tags) <= n,
+        Anything => true,
+        Nothing => false,
+        Not(ref rule) => !matches_sorted(tags, rule),
+        Predicate(ref pred) => (pred.0)(tags),
+        Pattern(ref pattern) => tags.iter().any(|tag| pattern.matches_tag(tag)),
+    }
+}
+
+#[test]
+fn test_sorted() {
+    use MatchRule::*;
+    let mut unsorted = TagMap::new();
+    unsorted.insert(1, vec!["c", "a", "b", "a"]);
+    unsorted.insert(2, vec!["b"]);
+    let mut map = SortedTagMap::from(unsorted);
+    map.insert(3, vec!["d", "a"]);
+    assert!(map.add_tag(2, "a"));
+    assert!(!map.add_tag(2, "b"));
+    assert!(map.remove_tag(&1, &"c"));
+    assert_eq!(map.get_tags(&1), Some(&["a", "b"][..]));
+    assert_eq!(map.get_tags(&2), Some(&["a", "b"][..]));
+    assert!(map.has_tag(&3, &"d"));
+    let rules = [Tags(vec!["a", "b"]), NotTags(vec!["d"]), AtLeast(2, vec!["a", "a", "d"]),
+                 Xor(vec![Tags(vec!["d"]), Tags(vec!["b"])]), Exactly(1, vec!["b", "d", "e"])];
+    for rule in &rules {
+        let expected: Vec<_> = map.deref().matching(rule).collect();
+        assert_eq!(map.matching(rule).collect::<Vec<_>>(), expected, "{:?}", rule);
+    }
+}
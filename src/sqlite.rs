@@ -0,0 +1,96 @@
+//! Reading and writing the entries of a map in an SQLite database.
+
+use rusqlite::types::{FromSql, ToSql};
+use rusqlite::{Connection, Result};
+use TagMap;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS entries (
+        id INTEGER PRIMARY KEY,
+        key NOT NULL UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS entry_tags (
+        entry_id INTEGER NOT NULL REFERENCES entries (id) ON DELETE CASCADE,
+        tag NOT NULL,
+        PRIMARY KEY (entry_id, tag)
+    );
+";
+
+impl<T: Ord, TAG: Eq> TagMap<T, TAG> {
+    /// Writes the entries to the `entries` and `entry_tags` tables of a database,
+    /// replacing their contents.
+    ///
+    /// The tables are created if they don't exist. Each row of `entries` has an
+    /// integer `id` and a `key`, and each row of `entry_tags` has the `entry_id` of an
+    /// entry and one of its `tag`s. Keys and tags are stored as they convert to SQL
+    /// values, without a declared column type. Everything is written in one
+    /// transaction, and the vocabulary isn't written.
+    pub fn to_sqlite(&self, conn: &Connection) -> Result<()>
+        where T: ToSql,
+              TAG: ToSql
+    {
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(SCHEMA)?;
+        tx.execute_batch("DELETE FROM entry_tags; DELETE FROM entries;")?;
+        {
+            let mut insert_entry = tx.prepare("INSERT INTO entries (key) VALUES (?1)")?;
+            let mut insert_tag =
+                tx.prepare("INSERT OR IGNORE INTO entry_tags (entry_id, tag) VALUES (?1, ?2)")?;
+            for (key, tags) in self.iter() {
+                let id = insert_entry.insert([key])?;
+                for tag in tags {
+                    insert_tag.execute((id, tag))?;
+                }
+            }
+        }
+        tx.commit()
+    }
+    /// Reads entries from the `entries` and `entry_tags` tables of a database.
+    ///
+    /// See `to_sqlite` for the schema. Tags are read in the order they were inserted.
+    pub fn from_sqlite(conn: &Connection) -> Result<Self>
+        where T: FromSql,
+              TAG: FromSql
+    {
+        let mut query = conn.prepare(
+            "SELECT entries.id, entries.key, entry_tags.tag FROM entries
+             LEFT JOIN entry_tags ON entry_tags.entry_id = entries.id
+             ORDER BY entries.id, entry_tags.rowid")?;
+        let mut rows = query.query([])?;
+        let mut entries = Vec::new();
+        let mut last_id = None;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            if last_id != Some(id) {
+                entries.push((row.get(1)?, Vec::new()));
+                last_id = Some(id);
+            }
+            if let Some(tag) = row.get(2)? {
+                entries.last_mut().unwrap().1.push(tag);
+            }
+        }
+        Ok(TagMap::from_sorted_iter(entries))
+    }
+}
+
+#[test]
+fn test_sqlite() {
+    let conn = Connection::open_in_memory().unwrap();
+    let mut map = TagMap::new();
+    map.insert("a.png".to_string(), vec!["cute".to_string(), "cat".to_string()]);
+    map.insert("b.png".to_string(), vec![]);
+    map.insert("c.png".to_string(), vec!["dog".to_string()]);
+    map.to_sqlite(&conn).unwrap();
+    let count: i64 = conn.query_row(
+        "SELECT count(*) FROM entry_tags JOIN entries ON entries.id = entry_id
+         WHERE tag = 'cat' OR tag = 'dog'", [], |row| row.get(0)).unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(TagMap::from_sqlite(&conn).unwrap(), map);
+    map.remove("a.png");
+    map.to_sqlite(&conn).unwrap();
+    assert_eq!(TagMap::from_sqlite(&conn).unwrap(), map);
+    let mut numbers = TagMap::new();
+    numbers.insert(2u32, vec![5u8, 1]);
+    numbers.to_sqlite(&conn).unwrap();
+    assert_eq!(TagMap::from_sqlite(&conn).unwrap(), numbers);
+}
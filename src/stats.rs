@@ -0,0 +1,171 @@
+//! Statistics about the tags used in a map.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use {Matcher, Storage, TagMap};
+
+impl<T, TAG: Ord, B: Storage<T, TAG>> TagMap<T, TAG, B> {
+    /// Returns every distinct tag used by the entries.
+    pub fn tags(&self) -> BTreeSet<&TAG> {
+        self.values().flat_map(|tags| tags.iter()).collect()
+    }
+    /// Returns the number of entries having each tag in use.
+    pub fn tag_counts(&self) -> BTreeMap<&TAG, usize> {
+        count_tags(self.values())
+    }
+    /// Returns the number of entries having each tag in use, most frequent first.
+    ///
+    /// Tags with the same count are in order.
+    pub fn tag_counts_by_frequency(&self) -> Vec<(&TAG, usize)> {
+        by_frequency(self.tag_counts())
+    }
+    /// Returns the tags appearing together with `tag`, with the number of entries having
+    /// both, most frequent first.
+    pub fn co_occurring(&self, tag: &TAG) -> Vec<(&TAG, usize)> {
+        let mut counts = count_tags(self.values().filter(|tags| tags.contains(tag)));
+        counts.remove(tag);
+        by_frequency(counts)
+    }
+    /// Returns the number of entries matching the rule having each tag, most frequent
+    /// first.
+    pub fn facets<R: ?Sized + Matcher<TAG>>(&self, rule: &R) -> Vec<(&TAG, usize)> {
+        by_frequency(count_tags(self.matching_entries(rule).map(|(_, tags)| tags)))
+    }
+    /// Suggests tags narrowing down the entries matching the rule, best first.
+    ///
+    /// Returns the tags some but not all of the matching entries have, with the number
+    /// of those entries that have them. The tags splitting the matches most evenly come
+    /// first, since requiring or excluding them narrows down the matches the most.
+    pub fn refinements<R: ?Sized + Matcher<TAG>>(&self, rule: &R) -> Vec<(&TAG, usize)> {
+        refinements_of(self.facets(rule), self.count_matching(rule))
+    }
+    /// Returns the number of entries having each pair of distinct tags appearing together.
+    ///
+    /// Each pair is listed once, with its smaller tag first.
+    pub fn co_occurrences(&self) -> BTreeMap<(&TAG, &TAG), usize> {
+        let mut counts = BTreeMap::new();
+        for tags in self.values() {
+            let tags: BTreeSet<_> = tags.iter().collect();
+            for (i, &a) in tags.iter().enumerate() {
+                for &b in tags.iter().skip(i + 1) {
+                    *counts.entry((a, b)).or_insert(0) += 1;
+                }
+            }
+        }
+        counts
+    }
+}
+
+/// Keeps the facets of the tags some but not all of the `total` matches have, ordered
+/// by how evenly they split the matches.
+pub(crate) fn refinements_of<TAG>(facets: Vec<(&TAG, usize)>, total: usize) -> Vec<(&TAG, usize)> {
+    let mut refinements: Vec<_> = facets.into_iter().filter(|&(_, count)| count < total).collect();
+    refinements.sort_by_key(|&(_, count)| Reverse(count.min(total - count)));
+    refinements
+}
+
+/// Counts the tag lists having each tag.
+pub(crate) fn count_tags<'a, TAG, I>(lists: I) -> BTreeMap<&'a TAG, usize>
+    where TAG: Ord + 'a,
+          I: IntoIterator<Item = &'a [TAG]>
+{
+    let mut counts = BTreeMap::new();
+    for tags in lists {
+        for (i, tag) in tags.iter().enumerate() {
+            if !tags[..i].contains(tag) {
+                *counts.entry(tag).or_insert(0) += 1;
+            }
+        }
+    }
+    counts
+}
+
+impl<T, TAG: Ord + AsRef<str>, B: Storage<T, TAG>> TagMap<T, TAG, B> {
+    /// Returns the distinct tags in use starting with the given prefix, in order.
+    pub fn tags_with_prefix(&self, prefix: &str) -> Vec<&TAG> {
+        self.tags().into_iter().filter(|tag| tag.as_ref().starts_with(prefix)).collect()
+    }
+}
+
+/// Sorts tag counts by decreasing count, keeping tags with the same count in order.
+pub(crate) fn by_frequency<TAG>(counts: BTreeMap<&TAG, usize>) -> Vec<(&TAG, usize)> {
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by_key(|&(_, count)| Reverse(count));
+    counts
+}
+
+#[test]
+fn test_tags() {
+    use IndexedTagMap;
+    let mut map = TagMap::new();
+    map.insert(1, vec!["b", "a"]);
+    map.insert(2, vec!["c", "a"]);
+    map.insert(3, vec![]);
+    assert_eq!(map.tags().into_iter().collect::<Vec<_>>(), [&"a", &"b", &"c"]);
+    let mut indexed = IndexedTagMap::from(map);
+    indexed.remove_tag(&1, &"b");
+    assert_eq!(indexed.tags().into_iter().collect::<Vec<_>>(), [&"a", &"c"]);
+    indexed.set_deferred(true);
+    indexed.add_tag(3, "d");
+    assert_eq!(indexed.tags().len(), 3);
+}
+
+#[test]
+fn test_tag_counts() {
+    use IndexedTagMap;
+    let mut map = TagMap::new();
+    map.insert(1, vec!["b", "a", "a"]);
+    map.insert(2, vec!["c", "a"]);
+    map.insert(3, vec!["c"]);
+    map.insert(4, vec!["d", "c"]);
+    let expected = [(&"c", 3), (&"a", 2), (&"b", 1), (&"d", 1)];
+    assert_eq!(map.tag_counts()[&"a"], 2);
+    assert_eq!(map.tag_counts_by_frequency(), expected);
+    let indexed = IndexedTagMap::from(map.clone());
+    assert_eq!(indexed.tag_counts(), map.tag_counts());
+    assert_eq!(indexed.tag_counts_by_frequency(), expected);
+}
+
+#[test]
+fn test_co_occurrence() {
+    use {IndexedTagMap, MatchRule};
+    let mut map = TagMap::new();
+    map.insert(1, vec!["cat", "cute", "small"]);
+    map.insert(2, vec!["cat", "cute", "cat"]);
+    map.insert(3, vec!["dog", "cute"]);
+    map.insert(4, vec!["dog", "small"]);
+    assert_eq!(map.co_occurring(&"cat"), [(&"cute", 2), (&"small", 1)]);
+    assert!(map.co_occurring(&"bird").is_empty());
+    let pairs = map.co_occurrences();
+    assert_eq!(pairs[&(&"cat", &"cute")], 2);
+    assert_eq!(pairs[&(&"dog", &"small")], 1);
+    assert_eq!(pairs.get(&(&"cat", &"dog")), None);
+    assert_eq!(pairs.len(), 5);
+    let cute = MatchRule::Tags(vec!["cute"]);
+    assert_eq!(map.facets(&cute), [(&"cute", 3), (&"cat", 2), (&"dog", 1), (&"small", 1)]);
+    let indexed = IndexedTagMap::from(map.clone());
+    for tag in &["cat", "cute", "dog", "small", "bird"] {
+        assert_eq!(indexed.co_occurring(tag), map.co_occurring(tag));
+    }
+    assert_eq!(indexed.facets(&cute), map.facets(&cute));
+    map.insert(5, vec!["cute", "bird", "small"]);
+    assert_eq!(map.refinements(&cute), [(&"cat", 2), (&"small", 2), (&"bird", 1), (&"dog", 1)]);
+    let indexed = IndexedTagMap::from(map.clone());
+    assert_eq!(indexed.refinements(&cute), map.refinements(&cute));
+}
+
+#[test]
+fn test_tags_with_prefix() {
+    use IndexedTagMap;
+    let mut map = TagMap::new();
+    map.insert(1, vec!["scifi", "science", "art"]);
+    map.insert(2, vec!["sci", "school", "science"]);
+    assert_eq!(map.tags_with_prefix("sci"), [&"sci", &"science", &"scifi"]);
+    assert!(map.tags_with_prefix("x").is_empty());
+    let mut indexed = IndexedTagMap::from(map.clone());
+    assert_eq!(indexed.tags_with_prefix("sc"), map.tags_with_prefix("sc"));
+    assert_eq!(indexed.tags_with_prefix(""), map.tags_with_prefix(""));
+    indexed.set_deferred(true);
+    indexed.remove(&2);
+    assert_eq!(indexed.tags_with_prefix("sci"), [&"science", &"scifi"]);
+}
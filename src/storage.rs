@@ -0,0 +1,301 @@
+//! The maps a `TagMap` can store its entries in.
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::iter::FromIterator;
+use std::mem::size_of;
+use std::ops::{Deref, DerefMut, RangeFull};
+use {Entry, OccupiedEntry, VacantEntry};
+
+/// A boxed filter passed to `Storage::extract_if`.
+pub type EntryFilter<'a, T, L> = Box<dyn FnMut(&T, &mut L) -> bool + 'a>;
+
+/// A list storing the tags of an entry of a `TagMap`.
+///
+/// Implemented by `Vec`, the default. With the `smallvec` feature, it's also
+/// implemented by `SmallVec`, so that short lists can be stored inline, as in
+/// `TagMap<T, TAG, BTreeMap<T, SmallVec<[TAG; 4]>>>`.
+pub trait TagList<TAG>:
+    Default + Deref<Target = [TAG]> + DerefMut + IntoIterator<Item = TAG> + FromIterator<TAG>
+{
+    /// Creates a list from a vector.
+    fn from_vec(tags: Vec<TAG>) -> Self;
+    /// Converts the list into a vector.
+    fn into_vec(self) -> Vec<TAG>;
+    /// Appends a tag.
+    fn push(&mut self, tag: TAG);
+    /// Removes the tag at the given index, shifting the following ones.
+    fn remove(&mut self, index: usize) -> TAG;
+    /// Keeps only the tags for which `f` returns true.
+    fn retain<F: FnMut(&TAG) -> bool>(&mut self, f: F);
+    /// Frees unused capacity.
+    fn shrink_to_fit(&mut self);
+    /// Returns the heap memory used by the list.
+    fn heap_size(&self) -> usize;
+}
+
+impl<TAG> TagList<TAG> for Vec<TAG> {
+    fn from_vec(tags: Vec<TAG>) -> Self {
+        tags
+    }
+    fn into_vec(self) -> Vec<TAG> {
+        self
+    }
+    fn push(&mut self, tag: TAG) {
+        self.push(tag);
+    }
+    fn remove(&mut self, index: usize) -> TAG {
+        self.remove(index)
+    }
+    fn retain<F: FnMut(&TAG) -> bool>(&mut self, f: F) {
+        self.retain(f);
+    }
+    fn shrink_to_fit(&mut self) {
+        self.shrink_to_fit();
+    }
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<TAG>()
+    }
+}
+
+#[cfg(feature = "smallvec")]
+impl<A: ::smallvec::Array> TagList<A::Item> for ::smallvec::SmallVec<A> {
+    fn from_vec(tags: Vec<A::Item>) -> Self {
+        let mut tags = ::smallvec::SmallVec::from_vec(tags);
+        // Moves short lists inline, even if the vector had spare capacity
+        tags.shrink_to_fit();
+        tags
+    }
+    fn into_vec(self) -> Vec<A::Item> {
+        self.into_vec()
+    }
+    fn push(&mut self, tag: A::Item) {
+        self.push(tag);
+    }
+    fn remove(&mut self, index: usize) -> A::Item {
+        self.remove(index)
+    }
+    fn retain<F: FnMut(&A::Item) -> bool>(&mut self, mut f: F) {
+        self.retain(|tag| f(tag));
+    }
+    fn shrink_to_fit(&mut self) {
+        self.shrink_to_fit();
+    }
+    fn heap_size(&self) -> usize {
+        if self.spilled() {
+            self.capacity() * size_of::<A::Item>()
+        } else {
+            0
+        }
+    }
+}
+
+/// A map storing the entries of a `TagMap`.
+///
+/// Implemented by `BTreeMap`, the default, and by `HashMap`, used by `HashTagMap`.
+/// The methods of `TagMap` that don't depend on key order are implemented once
+/// over this trait.
+pub trait Storage<T, TAG>: Sized {
+    /// The list storing the tags of each entry.
+    type List: TagList<TAG>;
+    /// Iterator over the entries.
+    type Iter<'a>: ExactSizeIterator<Item = (&'a T, &'a Self::List)> + Clone
+        where Self: 'a,
+              T: 'a,
+              Self::List: 'a;
+    /// Mutable iterator over the entries.
+    type IterMut<'a>: ExactSizeIterator<Item = (&'a T, &'a mut Self::List)>
+        where Self: 'a,
+              T: 'a,
+              Self::List: 'a;
+    /// Owning iterator over the entries.
+    type IntoIter: ExactSizeIterator<Item = (T, Self::List)>;
+    /// Iterator removing the entries accepted by a filter.
+    type ExtractIf<'a>: Iterator<Item = (T, Self::List)>
+        where Self: 'a,
+              T: 'a,
+              Self::List: 'a;
+    /// A vacant entry.
+    type Vacant<'a>: VacantStorage<'a, T, Self::List>
+        where Self: 'a,
+              T: 'a,
+              Self::List: 'a;
+    /// An occupied entry.
+    type Occupied<'a>: OccupiedStorage<'a, T, Self::List>
+        where Self: 'a,
+              T: 'a,
+              Self::List: 'a;
+    /// Returns the number of entries.
+    fn len(&self) -> usize;
+    /// Returns whether there are no entries.
+    fn is_empty(&self) -> bool;
+    /// Removes all entries.
+    fn clear(&mut self);
+    /// Returns an iterator over the entries.
+    fn iter(&self) -> Self::Iter<'_>;
+    /// Returns a mutable iterator over the entries.
+    fn iter_mut(&mut self) -> Self::IterMut<'_>;
+    /// Returns an owning iterator over the entries.
+    fn into_entries(self) -> Self::IntoIter;
+    /// Inserts an entry, returning the old tags if the key was present.
+    fn insert(&mut self, key: T, tags: Self::List) -> Option<Self::List>;
+    /// Gets the entry for the given key.
+    fn entry(&mut self, key: T) -> Entry<'_, T, TAG, Self>;
+    /// Keeps only the entries for which `f` returns true.
+    fn retain<F: FnMut(&T, &mut Self::List) -> bool>(&mut self, f: F);
+    /// Removes the entries for which `filter` returns true, yielding them.
+    fn extract_if<'a>(&'a mut self, filter: EntryFilter<'a, T, Self::List>)
+                      -> Self::ExtractIf<'a>;
+    /// Frees unused capacity.
+    fn shrink_to_fit(&mut self);
+}
+
+/// Lookups in a `Storage` by a borrowed form `Q` of its keys.
+pub trait Lookup<Q: ?Sized, T, TAG>: Storage<T, TAG> {
+    /// Returns the tags of an entry.
+    fn get(&self, key: &Q) -> Option<&Self::List>;
+    /// Returns the tags of an entry mutably.
+    fn get_mut(&mut self, key: &Q) -> Option<&mut Self::List>;
+    /// Removes an entry, returning its tags.
+    fn remove(&mut self, key: &Q) -> Option<Self::List>;
+}
+
+/// A vacant entry of a `Storage`.
+pub trait VacantStorage<'a, T: 'a, L: 'a> {
+    /// Returns the key of the entry.
+    fn key(&self) -> &T;
+    /// Takes ownership of the key.
+    fn into_key(self) -> T;
+    /// Inserts the entry with the given tags, and returns them.
+    fn insert(self, tags: L) -> &'a mut L;
+}
+
+/// An occupied entry of a `Storage`.
+pub trait OccupiedStorage<'a, T: 'a, L: 'a> {
+    /// Returns the key of the entry.
+    fn key(&self) -> &T;
+    /// Returns the tags of the entry.
+    fn get(&self) -> &L;
+    /// Returns the tags of the entry mutably.
+    fn get_mut(&mut self) -> &mut L;
+    /// Converts the entry into a mutable reference to its tags.
+    fn into_mut(self) -> &'a mut L;
+    /// Replaces the tags of the entry, returning the old ones.
+    fn insert(&mut self, tags: L) -> L;
+    /// Removes the entry, returning its key and tags.
+    fn remove_entry(self) -> (T, L);
+}
+
+impl<T: Ord, TAG, L: TagList<TAG>> Storage<T, TAG> for BTreeMap<T, L> {
+    type List = L;
+    type Iter<'a> = btree_map::Iter<'a, T, L> where T: 'a, L: 'a;
+    type IterMut<'a> = btree_map::IterMut<'a, T, L> where T: 'a, L: 'a;
+    type IntoIter = btree_map::IntoIter<T, L>;
+    type ExtractIf<'a> = btree_map::ExtractIf<'a, T, L, RangeFull, EntryFilter<'a, T, L>>
+        where T: 'a,
+              L: 'a;
+    type Vacant<'a> = btree_map::VacantEntry<'a, T, L> where T: 'a, L: 'a;
+    type Occupied<'a> = btree_map::OccupiedEntry<'a, T, L> where T: 'a, L: 'a;
+    fn len(&self) -> usize {
+        self.len()
+    }
+    fn is_empty(&self) -> bool {
+        self.is_empty()
+    }
+    fn clear(&mut self) {
+        self.clear();
+    }
+    fn iter(&self) -> Self::Iter<'_> {
+        self.iter()
+    }
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        self.iter_mut()
+    }
+    fn into_entries(self) -> Self::IntoIter {
+        IntoIterator::into_iter(self)
+    }
+    fn insert(&mut self, key: T, tags: L) -> Option<L> {
+        self.insert(key, tags)
+    }
+    fn entry(&mut self, key: T) -> Entry<'_, T, TAG, Self> {
+        match self.entry(key) {
+            btree_map::Entry::Vacant(inner) => Entry::Vacant(VacantEntry::new(inner)),
+            btree_map::Entry::Occupied(inner) => Entry::Occupied(OccupiedEntry::new(inner)),
+        }
+    }
+    fn retain<F: FnMut(&T, &mut L) -> bool>(&mut self, f: F) {
+        self.retain(f);
+    }
+    fn extract_if<'a>(&'a mut self, filter: EntryFilter<'a, T, L>) -> Self::ExtractIf<'a> {
+        self.extract_if(.., filter)
+    }
+    fn shrink_to_fit(&mut self) {}
+}
+
+impl<T: Ord, Q: ?Sized + Ord, TAG, L: TagList<TAG>> Lookup<Q, T, TAG> for BTreeMap<T, L>
+    where T: Borrow<Q>
+{
+    fn get(&self, key: &Q) -> Option<&L> {
+        self.get(key)
+    }
+    fn get_mut(&mut self, key: &Q) -> Option<&mut L> {
+        self.get_mut(key)
+    }
+    fn remove(&mut self, key: &Q) -> Option<L> {
+        self.remove(key)
+    }
+}
+
+impl<'a, T: Ord, L> VacantStorage<'a, T, L> for btree_map::VacantEntry<'a, T, L> {
+    fn key(&self) -> &T {
+        self.key()
+    }
+    fn into_key(self) -> T {
+        self.into_key()
+    }
+    fn insert(self, tags: L) -> &'a mut L {
+        self.insert(tags)
+    }
+}
+
+impl<'a, T: Ord, L> OccupiedStorage<'a, T, L> for btree_map::OccupiedEntry<'a, T, L> {
+    fn key(&self) -> &T {
+        self.key()
+    }
+    fn get(&self) -> &L {
+        self.get()
+    }
+    fn get_mut(&mut self) -> &mut L {
+        self.get_mut()
+    }
+    fn into_mut(self) -> &'a mut L {
+        self.into_mut()
+    }
+    fn insert(&mut self, tags: L) -> L {
+        self.insert(tags)
+    }
+    fn remove_entry(self) -> (T, L) {
+        self.remove_entry()
+    }
+}
+
+#[cfg(feature = "smallvec")]
+#[test]
+fn test_smallvec_storage() {
+    use smallvec::SmallVec;
+    use {MatchRule, TagMap};
+    let mut map: TagMap<_, _, BTreeMap<_, SmallVec<[&str; 2]>>> = TagMap::default();
+    map.insert(1, vec!["a", "b"]);
+    assert!(map.add_tag(1, "c"));
+    assert!(map.add_tag(2, "a"));
+    assert!(map.remove_tag(&1, &"b"));
+    assert_eq!(map.get_tags(&1), Some(&["a", "c"][..]));
+    let a = MatchRule::Tags(vec!["a"]);
+    assert_eq!(map.matching(&a).collect::<Vec<_>>(), [&1, &2]);
+    assert!(map.memory_usage().tags > 2 * size_of::<SmallVec<[&str; 2]>>());
+    map.compact();
+    assert_eq!(map.memory_usage().tags, 2 * size_of::<SmallVec<[&str; 2]>>());
+    let old = map.insert(2, vec!["x"]).unwrap();
+    assert!(!old.spilled());
+}
@@ -0,0 +1,206 @@
+//! Relations between tags that are applied when matching.
+
+use std::borrow::Cow;
+use std::mem::size_of;
+use Matcher;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Relations between tags, applied to the tags of entries when matching.
+///
+/// Each `TagMap` has a vocabulary. Rules only take it into account when wrapped
+/// with `TagMap::resolve` or `Vocabulary::resolve`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Vocabulary<TAG> {
+    aliases: Vec<Vec<TAG>>,
+    implications: Vec<(TAG, TAG)>,
+}
+
+impl<TAG> Default for Vocabulary<TAG> {
+    fn default() -> Self {
+        Vocabulary {
+            aliases: Vec::new(),
+            implications: Vec::new(),
+        }
+    }
+}
+
+impl<TAG> Vocabulary<TAG> {
+    /// Returns the memory used by the vocabulary, in bytes.
+    pub(crate) fn memory_usage(&self) -> usize {
+        let groups: usize = self.aliases.iter().map(|group| group.capacity()).sum();
+        self.aliases.capacity() * size_of::<Vec<TAG>>() + groups * size_of::<TAG>() +
+        self.implications.capacity() * size_of::<(TAG, TAG)>()
+    }
+    /// Converts every tag of the vocabulary with `f`.
+    pub(crate) fn map<U, F: FnMut(TAG) -> U>(self, mut f: F) -> Vocabulary<U> {
+        Vocabulary {
+            aliases: self.aliases
+                .into_iter()
+                .map(|group| group.into_iter().map(&mut f).collect())
+                .collect(),
+            implications: self.implications
+                .into_iter()
+                .map(|(from, to)| (f(from), f(to)))
+                .collect(),
+        }
+    }
+}
+
+impl<TAG: Eq> Vocabulary<TAG> {
+    /// Creates an empty vocabulary.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Returns whether the vocabulary has no relations.
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty() && self.implications.is_empty()
+    }
+    /// Registers tags as aliases of each other.
+    ///
+    /// Groups sharing a tag are merged.
+    pub fn add_alias_group<I: IntoIterator<Item = TAG>>(&mut self, tags: I) {
+        let mut group = Vec::new();
+        for tag in tags {
+            if !group.contains(&tag) {
+                group.push(tag);
+            }
+        }
+        let mut i = 0;
+        while i < self.aliases.len() {
+            if self.aliases[i].iter().any(|tag| group.contains(tag)) {
+                for tag in self.aliases.swap_remove(i) {
+                    if !group.contains(&tag) {
+                        group.push(tag);
+                    }
+                }
+            } else {
+                i += 1;
+            }
+        }
+        if group.len() > 1 {
+            self.aliases.push(group);
+        }
+    }
+    /// Returns the aliases of a tag, including the tag itself.
+    pub fn aliases(&self, tag: &TAG) -> Option<&[TAG]> {
+        self.aliases.iter().find(|group| group.contains(tag)).map(|group| &group[..])
+    }
+    /// Registers that entries tagged `from` are also implicitly tagged `to`.
+    ///
+    /// Implications are transitive, and apply to aliases as well. Returns `false`
+    /// without adding the implication if it would form a cycle.
+    pub fn add_implication(&mut self, from: TAG, to: TAG) -> bool {
+        {
+            let mut reached = vec![&to];
+            let mut i = 0;
+            while i < reached.len() {
+                let tag = reached[i];
+                if *tag == from {
+                    return false;
+                }
+                for next in self.aliases(tag).unwrap_or(&[]).iter().chain(self.implied(tag)) {
+                    if !reached.contains(&next) {
+                        reached.push(next);
+                    }
+                }
+                i += 1;
+            }
+        }
+        if !self.implications.iter().any(|(f, t)| *f == from && *t == to) {
+            self.implications.push((from, to));
+        }
+        true
+    }
+    /// Returns the tags directly implied by a tag.
+    pub fn implied<'a>(&'a self, tag: &'a TAG) -> impl Iterator<Item = &'a TAG> + 'a {
+        self.implications.iter().filter(move |(from, _)| from == tag).map(|(_, to)| to)
+    }
+    /// Wraps a rule, so it's matched against tags extended by the vocabulary.
+    pub fn resolve<'a, R: ?Sized + Matcher<TAG>>(&'a self, rule: &'a R) -> Resolved<'a, TAG, R> {
+        Resolved {
+            vocabulary: self,
+            rule,
+        }
+    }
+}
+
+impl<TAG: Eq + Clone> Vocabulary<TAG> {
+    /// Returns the given tags extended with their aliases and the tags they imply.
+    ///
+    /// Borrows the tags if there's nothing to add.
+    pub fn expand<'t>(&self, tags: &'t [TAG]) -> Cow<'t, [TAG]> {
+        let mut out = Cow::Borrowed(tags);
+        let mut i = 0;
+        while i < out.len() {
+            let mut new = Vec::new();
+            for tag in self.aliases(&out[i]).unwrap_or(&[]).iter().chain(self.implied(&out[i])) {
+                if !out.contains(tag) && !new.contains(tag) {
+                    new.push(tag.clone());
+                }
+            }
+            if !new.is_empty() {
+                out.to_mut().extend(new);
+            }
+            i += 1;
+        }
+        out
+    }
+}
+
+/// A rule matched against tags extended by a `Vocabulary`.
+///
+/// Created by `Vocabulary::resolve`. Since aliases and implied tags are added to
+/// the tags of an entry, counting rules like `AtLeast` count them as separate tags.
+#[derive(Debug)]
+pub struct Resolved<'a, TAG: 'a, R: 'a + ?Sized> {
+    vocabulary: &'a Vocabulary<TAG>,
+    rule: &'a R,
+}
+
+impl<'a, TAG: Eq + Clone, R: ?Sized + Matcher<TAG>> Matcher<TAG> for Resolved<'a, TAG, R> {
+    fn matches(&self, tags: &[TAG]) -> bool {
+        self.rule.matches(&self.vocabulary.expand(tags))
+    }
+}
+
+#[test]
+fn test_aliases() {
+    use {MatchRule, TagMap};
+    let mut map = TagMap::new();
+    map.insert("rex", vec!["dog", "pic"]);
+    map.insert("tom", vec!["feline"]);
+    map.insert("fido", vec!["canine"]);
+    map.add_alias_group(vec!["dog", "canine"]);
+    map.add_alias_group(vec!["cat", "feline"]);
+    map.add_alias_group(vec!["kitty", "cat"]);
+    assert_eq!(map.vocabulary().aliases(&"kitty"), Some(&["kitty", "cat", "feline"][..]));
+    let rule = MatchRule::Tags(vec!["canine"]);
+    assert_eq!(map.count_matching(&rule), 1);
+    assert_eq!(map.matching(&map.resolve(&rule)).cloned().collect::<Vec<_>>(), ["fido", "rex"]);
+    let rule = MatchRule::NotTags(vec!["kitty"]);
+    assert_eq!(map.matching(&map.resolve(&rule)).cloned().collect::<Vec<_>>(), ["fido", "rex"]);
+}
+
+#[test]
+fn test_implications() {
+    use {MatchRule, TagMap};
+    let mut map = TagMap::new();
+    map.insert("rex", vec!["poodle"]);
+    map.insert("tom", vec!["cat"]);
+    map.insert("nemo", vec!["fish"]);
+    map.add_alias_group(vec!["dog", "canine"]);
+    assert!(map.add_implication("poodle", "canine"));
+    assert!(map.add_implication("dog", "mammal"));
+    assert!(map.add_implication("cat", "mammal"));
+    assert!(map.add_implication("mammal", "animal"));
+    assert!(map.add_implication("fish", "animal"));
+    assert!(!map.add_implication("animal", "dog"));
+    assert!(!map.add_implication("mammal", "mammal"));
+    let rule = MatchRule::Tags(vec!["animal"]);
+    assert_eq!(map.matching(&map.resolve(&rule)).count(), 3);
+    let rule = MatchRule::Tags(vec!["mammal", "dog"]);
+    assert_eq!(map.matching(&map.resolve(&rule)).cloned().collect::<Vec<_>>(), ["rex"]);
+    assert_eq!(map.vocabulary().expand(&["fish"]), &["fish", "animal"][..]);
+}
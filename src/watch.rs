@@ -0,0 +1,247 @@
+//! A tag map notifying subscribers of changes to its entries.
+
+use std::fmt;
+use std::ops::Deref;
+use std::sync::mpsc::{self, Receiver, Sender};
+use {MatchRule, Matcher, TagMap};
+
+/// A change to the entries matching a watched rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatchChange<T> {
+    /// The entry for the key started matching the rule.
+    Started(T),
+    /// The entry for the key stopped matching the rule, or was removed.
+    Stopped(T),
+}
+
+/// A change to an entry, passed to the hooks of a `WatchedTagMap`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChangeEvent<'a, T: 'a, TAG: 'a> {
+    /// An entry was inserted with the given tags, or had its tags replaced.
+    EntryInserted(&'a T, &'a [TAG]),
+    /// An entry was removed.
+    EntryRemoved(&'a T),
+    /// A tag was added to an existing entry.
+    TagAdded(&'a T, &'a TAG),
+    /// A tag was removed from an entry.
+    TagRemoved(&'a T, &'a TAG),
+}
+
+type Hook<T, TAG> = Box<dyn FnMut(&ChangeEvent<'_, T, TAG>) + Send>;
+
+/// A `TagMap` notifying subscribers of changes to its entries.
+///
+/// Hooks added with `on_change` are called after each change that has an effect.
+/// Rules can be watched with `watch`, to be notified when entries start or stop
+/// matching them. Each change to an entry only evaluates the watched rules against
+/// that entry. A watcher is dropped when a change can't be sent because its receiver
+/// was dropped. The map can be read through `Deref`, but must be modified through the
+/// methods of this type.
+pub struct WatchedTagMap<T: Ord, TAG: Eq> {
+    map: TagMap<T, TAG>,
+    watchers: Vec<(MatchRule<TAG>, Sender<MatchChange<T>>)>,
+    hooks: Vec<Hook<T, TAG>>,
+}
+
+impl<T: Ord + fmt::Debug, TAG: Eq + fmt::Debug> fmt::Debug for WatchedTagMap<T, TAG> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WatchedTagMap")
+            .field("map", &self.map)
+            .field("watchers", &self.watchers)
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+impl<T: Ord + Clone, TAG: Eq> WatchedTagMap<T, TAG> {
+    /// Creates a new empty map.
+    pub fn new() -> Self {
+        WatchedTagMap {
+            map: TagMap::new(),
+            watchers: Vec::new(),
+            hooks: Vec::new(),
+        }
+    }
+    /// Returns the underlying map.
+    pub fn into_inner(self) -> TagMap<T, TAG> {
+        self.map
+    }
+    /// Starts watching a rule, returning a receiver of the changes to its matches.
+    ///
+    /// Only changes made afterwards are sent. The entries matching the rule now can be
+    /// found with `matching`.
+    pub fn watch(&mut self, rule: MatchRule<TAG>) -> Receiver<MatchChange<T>> {
+        let (sender, receiver) = mpsc::channel();
+        self.watchers.push((rule, sender));
+        receiver
+    }
+    /// Adds a hook called with each change made to the entries afterwards.
+    ///
+    /// To receive the changes on another thread, the hook can send them over a channel.
+    pub fn on_change<F>(&mut self, hook: F)
+        where F: FnMut(&ChangeEvent<'_, T, TAG>) + Send + 'static
+    {
+        self.hooks.push(Box::new(hook));
+    }
+    /// Returns the number of watched rules.
+    ///
+    /// Watchers whose receivers were dropped are counted until a change is sent to them.
+    pub fn watcher_count(&self) -> usize {
+        self.watchers.len()
+    }
+    /// Removes all entries from the map.
+    pub fn clear(&mut self) {
+        let map = &self.map;
+        self.watchers.retain(|(rule, sender)| {
+            map.matching(rule).all(|key| sender.send(MatchChange::Stopped(key.clone())).is_ok())
+        });
+        for key in self.map.keys() {
+            notify(&mut self.hooks, &ChangeEvent::EntryRemoved(key));
+        }
+        self.map.clear();
+    }
+    /// Inserts an entry with the given tags.
+    ///
+    /// If the key was already present, its old tags are replaced and returned.
+    pub fn insert(&mut self, key: T, tags: Vec<TAG>) -> Option<Vec<TAG>> {
+        let old = self.change(key.clone(), |map| map.insert(key.clone(), tags));
+        if let Some((key, tags)) = self.map.entries.get_key_value(&key) {
+            notify(&mut self.hooks, &ChangeEvent::EntryInserted(key, tags));
+        }
+        old
+    }
+    /// Removes an entry, returning its tags if it was present.
+    pub fn remove(&mut self, key: &T) -> Option<Vec<TAG>> {
+        let tags = self.change(key.clone(), |map| map.remove(key));
+        if tags.is_some() {
+            notify(&mut self.hooks, &ChangeEvent::EntryRemoved(key));
+        }
+        tags
+    }
+    /// Adds a tag to an entry, inserting the entry if it's not present.
+    ///
+    /// Returns false if the entry already had the tag. If the entry is inserted, the
+    /// change is reported as `EntryInserted`.
+    pub fn add_tag(&mut self, key: T, tag: TAG) -> bool {
+        let present = self.map.contains_key(&key);
+        let added = self.change(key.clone(), |map| map.add_tag(key.clone(), tag));
+        if added {
+            let (key, tags) = self.map.entries.get_key_value(&key).unwrap();
+            if present {
+                notify(&mut self.hooks, &ChangeEvent::TagAdded(key, tags.last().unwrap()));
+            } else {
+                notify(&mut self.hooks, &ChangeEvent::EntryInserted(key, tags));
+            }
+        }
+        added
+    }
+    /// Removes a tag from an entry.
+    ///
+    /// Returns false if the entry is not present or didn't have the tag.
+    pub fn remove_tag(&mut self, key: &T, tag: &TAG) -> bool {
+        let removed = self.change(key.clone(), |map| map.remove_tag(key, tag));
+        if removed {
+            notify(&mut self.hooks, &ChangeEvent::TagRemoved(key, tag));
+        }
+        removed
+    }
+    /// Applies a change to the entry for `key`, notifying the watchers whose rules it
+    /// started or stopped matching.
+    fn change<R, F: FnOnce(&mut TagMap<T, TAG>) -> R>(&mut self, key: T, f: F) -> R {
+        let matched: Vec<_> = {
+            let tags = self.map.get_tags(&key);
+            let matches = |rule: &MatchRule<TAG>| tags.is_some_and(|tags| rule.matches(tags));
+            self.watchers.iter().map(|(rule, _)| matches(rule)).collect()
+        };
+        let result = f(&mut self.map);
+        let tags = self.map.get_tags(&key);
+        let mut matched = matched.into_iter();
+        self.watchers.retain(|(rule, sender)| {
+            let before = matched.next().unwrap_or(false);
+            match (before, tags.is_some_and(|tags| rule.matches(tags))) {
+                (false, true) => sender.send(MatchChange::Started(key.clone())).is_ok(),
+                (true, false) => sender.send(MatchChange::Stopped(key.clone())).is_ok(),
+                _ => true,
+            }
+        });
+        result
+    }
+}
+
+/// Calls the hooks with a change.
+fn notify<T, TAG>(hooks: &mut [Hook<T, TAG>], event: &ChangeEvent<'_, T, TAG>) {
+    for hook in hooks {
+        hook(event);
+    }
+}
+
+impl<T: Ord + Clone, TAG: Eq> Default for WatchedTagMap<T, TAG> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord + Clone, TAG: Eq> From<TagMap<T, TAG>> for WatchedTagMap<T, TAG> {
+    fn from(map: TagMap<T, TAG>) -> Self {
+        WatchedTagMap { map, ..Self::new() }
+    }
+}
+
+impl<T: Ord, TAG: Eq> Deref for WatchedTagMap<T, TAG> {
+    type Target = TagMap<T, TAG>;
+    fn deref(&self) -> &TagMap<T, TAG> {
+        &self.map
+    }
+}
+
+#[test]
+fn test_watch() {
+    use self::MatchChange::*;
+    let mut map = WatchedTagMap::new();
+    map.insert("a", vec!["inbox"]);
+    let inbox = map.watch(MatchRule::Tags(vec!["inbox"]));
+    map.insert("b", vec!["inbox", "urgent"]);
+    map.add_tag("a", "read");
+    map.remove_tag(&"a", &"inbox");
+    map.add_tag("c", "archive");
+    map.remove(&"b");
+    map.insert("c", vec!["inbox"]);
+    map.clear();
+    let changes: Vec<_> = inbox.try_iter().collect();
+    assert_eq!(changes, [Started("b"), Stopped("a"), Stopped("b"), Started("c"), Stopped("c")]);
+    let urgent = map.watch(MatchRule::Tags(vec!["urgent"]));
+    map.add_tag("d", "urgent");
+    assert_eq!(urgent.try_recv(), Ok(Started("d")));
+    drop(urgent);
+    map.add_tag("d", "other");
+    assert_eq!(map.watcher_count(), 2);
+    map.remove_tag(&"d", &"urgent");
+    assert_eq!(map.watcher_count(), 1);
+}
+
+#[test]
+fn test_on_change() {
+    use std::sync::mpsc;
+    let mut map = WatchedTagMap::new();
+    let (sender, receiver) = mpsc::channel();
+    map.on_change(move |event: &ChangeEvent<&str, &str>| {
+        let event = match *event {
+            ChangeEvent::EntryInserted(key, tags) => format!("+{} {:?}", key, tags),
+            ChangeEvent::EntryRemoved(key) => format!("-{}", key),
+            ChangeEvent::TagAdded(key, tag) => format!("{} +{}", key, tag),
+            ChangeEvent::TagRemoved(key, tag) => format!("{} -{}", key, tag),
+        };
+        sender.send(event).unwrap();
+    });
+    map.insert("a", vec!["x"]);
+    map.add_tag("a", "y");
+    map.add_tag("a", "y");
+    map.add_tag("b", "z");
+    map.remove_tag(&"a", &"x");
+    map.remove_tag(&"a", &"x");
+    map.remove(&"a");
+    map.remove(&"a");
+    map.clear();
+    let events: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(events, ["+a [\"x\"]", "a +y", "+b [\"z\"]", "a -x", "-a", "-b"]);
+}